@@ -0,0 +1,37 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use openbook_offchain_services::{
+    scraper::events::{OpenBookEvent, OPENBOOK_EVENTS},
+    structs::openbook_v2::{EventContext, OpenBookFill, OpenBookMarketMetadata},
+    utils::{to_timestampz, PROGRAM_CONFIG},
+};
+
+// Raw event payloads, discriminator included, straight into the event registry. Decoded fills
+// are converted with fuzzed lot sizes, so the fill arithmetic is fuzzed too.
+fuzz_target!(|input: (Vec<u8>, i64, i64)| {
+    let (payload, base_lot_size, quote_lot_size) = input;
+    if let Ok(Some(OpenBookEvent::Fill(log))) = OPENBOOK_EVENTS.decode(&payload, 0) {
+        let market = OpenBookMarketMetadata {
+            creation_datetime: to_timestampz(0),
+            program_pk: PROGRAM_CONFIG.program_ids[0].to_string(),
+            market_pk: log.market.to_string(),
+            market_name: "fuzz".to_string(),
+            base_mint: String::new(),
+            quote_mint: String::new(),
+            base_decimals: 9,
+            quote_decimals: 6,
+            base_lot_size,
+            quote_lot_size,
+            scraper_active: true,
+            cluster: "mainnet".to_string(),
+        };
+        let ctx = EventContext {
+            signature: String::new(),
+            log_index: 0,
+            slot: 0,
+            block_datetime: to_timestampz(0),
+        };
+        let _ = OpenBookFill::from_log(log, &market, &ctx);
+    }
+});
@@ -0,0 +1,18 @@
+#![no_main]
+
+use anchor_lang::__private::base64;
+use libfuzzer_sys::fuzz_target;
+use openbook_offchain_services::scraper::parsing::{decode_program_data_logs, logs_truncated};
+
+// The input is used both as log lines and as the payload of a `Program data:` line, so the
+// fuzzer reaches the event decoders without having to find valid base64 first.
+fuzz_target!(|data: &[u8]| {
+    let mut logs: Vec<String> = String::from_utf8_lossy(data)
+        .lines()
+        .map(|l| l.to_string())
+        .collect();
+    logs.push(format!("Program data: {}", base64::encode(data)));
+
+    let _ = logs_truncated(&logs);
+    let _ = decode_program_data_logs(&logs);
+});
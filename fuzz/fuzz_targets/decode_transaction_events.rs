@@ -0,0 +1,87 @@
+#![no_main]
+
+use anchor_lang::{__private::base64, event::EVENT_IX_TAG_LE};
+use libfuzzer_sys::fuzz_target;
+use openbook_offchain_services::{
+    scraper::{
+        events::OpenBookEvent,
+        parsing::{decode_transaction_events, sort_openbook_events},
+    },
+    structs::openbook_v2::OpenBookMarketMetadata,
+    utils::{to_timestampz, PROGRAM_CONFIG},
+};
+use serde_json::json;
+use solana_transaction_status::EncodedTransactionWithStatusMeta;
+use std::collections::HashMap;
+
+// A transaction of the first configured program with one `Program data:` log line and one
+// `emit_cpi!` inner instruction, so both decoders and the merge of their payloads are fuzzed.
+// The decoded events are then sorted with every market of a fill as a target market, with
+// fuzzed lot sizes.
+fuzz_target!(|input: (Vec<u8>, Vec<u8>, i64, i64)| {
+    let (log_payload, cpi_payload, base_lot_size, quote_lot_size) = input;
+    let program_id = PROGRAM_CONFIG.program_ids[0].to_string();
+    let cpi_data = [EVENT_IX_TAG_LE.as_slice(), cpi_payload.as_slice()].concat();
+
+    let txn = json!({
+        "transaction": {
+            "signatures": ["1111111111111111111111111111111111111111111111111111111111111111"],
+            "message": {
+                "header": {
+                    "numRequiredSignatures": 1,
+                    "numReadonlySignedAccounts": 0,
+                    "numReadonlyUnsignedAccounts": 1
+                },
+                "accountKeys": [program_id],
+                "recentBlockhash": "11111111111111111111111111111111",
+                "instructions": []
+            }
+        },
+        "meta": {
+            "err": null,
+            "status": { "Ok": null },
+            "fee": 5000,
+            "preBalances": [],
+            "postBalances": [],
+            "innerInstructions": [{
+                "index": 0,
+                "instructions": [{
+                    "programIdIndex": 0,
+                    "accounts": [],
+                    "data": bs58::encode(cpi_data).into_string()
+                }]
+            }],
+            "logMessages": [format!("Program data: {}", base64::encode(&log_payload))]
+        }
+    });
+    let txn: EncodedTransactionWithStatusMeta = match serde_json::from_value(txn) {
+        Ok(txn) => txn,
+        Err(_) => return,
+    };
+    let decoded = match decode_transaction_events(&txn) {
+        Ok(decoded) => decoded,
+        Err(_) => return,
+    };
+
+    let mut target_markets = HashMap::new();
+    for (_, event) in decoded.iter() {
+        if let OpenBookEvent::Fill(log) = event {
+            let market = OpenBookMarketMetadata {
+                creation_datetime: to_timestampz(0),
+                program_pk: program_id.clone(),
+                market_pk: log.market.to_string(),
+                market_name: "fuzz".to_string(),
+                base_mint: String::new(),
+                quote_mint: String::new(),
+                base_decimals: 9,
+                quote_decimals: 6,
+                base_lot_size,
+                quote_lot_size,
+                scraper_active: true,
+                cluster: "mainnet".to_string(),
+            };
+            target_markets.insert(market.market_pk.clone(), market);
+        }
+    }
+    let _ = sort_openbook_events(decoded, &target_markets, "fuzz", &program_id, 0, 0);
+});
@@ -0,0 +1,400 @@
+use openbook_offchain_services::{
+    database::{
+        fetch::{
+            fetch_all_markets, fetch_block_scan_ranges_between, fetch_dead_letter_transactions,
+            fetch_market, fetch_market_imports, fetch_market_lags, fetch_market_summaries,
+        },
+        initialize::{connect_to_database, setup_database},
+        insert::{
+            complete_market_import, drop_market_imports, rename_market,
+            requeue_dead_letter_transactions, retry_market_imports, set_market_active,
+        },
+    },
+    scraper::{
+        archive::reparse_archived_transactions,
+        import::{find_market_creation, MAX_IMPORT_ATTEMPTS},
+        inspect::inspect_transaction,
+        parsing::transaction_signature,
+        source::{
+            fill_missing_block_times, read_recorded_transactions, transaction_source_from_env,
+        },
+    },
+    structs::{
+        archived_transaction::ReparseSummary,
+        block_scan::BlockScanCoverage,
+        inspected_transaction::InspectedTransaction,
+        market_import::MarketImport,
+        market_summary::{MarketLag, MarketSummary},
+        openbook_v2::OpenBookMarketMetadata,
+        transaction::DeadLetterTransaction,
+    },
+    utils::{to_timestampz, PROGRAM_CONFIG},
+};
+use serde::Serialize;
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use std::{collections::HashMap, path::Path};
+
+const USAGE: &str = "usage: admin [--json] <command>
+
+commands:
+    list                            list markets with their fill and candle counts
+    activate <market>               enable scraping and candle batching for a market
+    deactivate <market>             disable scraping and candle batching for a market
+    rename <market> <new_name>      rename a market, keeping its candles
+    import <market_pk>              import market metadata from its creation transaction
+    imports                         list markets queued for import, the ones that stopped being
+                                    retried first
+    retry-import <market_pk>...     retry failed market imports
+    retry-import all                retry every failed market import
+    drop-import <market_pk>...      remove markets from the import queue
+    lag                             show how far behind the fills and candles of each market are
+    dead-letters                    list transactions that failed too many times to be fetched
+    requeue <signature>...          retry dead lettered transactions
+    requeue all                     retry every dead lettered transaction
+    reparse market <market> [<from_slot> <to_slot>]
+                                    rebuild the fills and events of a market from the archive
+    reparse slots <from_slot> <to_slot>
+                                    rebuild the fills and events of a slot range from the archive
+    coverage <from> <to>            check that every block between two unix timestamps was scanned
+    inspect <signature | file>      show how a transaction or a file of recorded transactions is
+                                    parsed, without writing anything. Markets are created from
+                                    their MarketMetaData event, which replaced the parsing of
+                                    createMarket instructions
+
+<market> is a market pubkey or name";
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    env_logger::init();
+    dotenv::dotenv().ok();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let json = args.iter().any(|a| a == "--json");
+    let args: Vec<&str> = args
+        .iter()
+        .map(|a| a.as_str())
+        .filter(|a| *a != "--json")
+        .collect();
+
+    let pool = connect_to_database().await?;
+    if let ["inspect", target] = args.as_slice() {
+        let inspected = inspect(&pool, target).await?;
+        return print_output(&inspected[..], json, print_inspected);
+    }
+    setup_database(&pool).await?;
+
+    match args.as_slice() {
+        ["list"] => {
+            let summaries = fetch_market_summaries(&pool).await?;
+            print_output(&summaries[..], json, print_summaries)?;
+        }
+        ["activate", market] | ["deactivate", market] => {
+            let active = args[0] == "activate";
+            let mut market = find_market(&pool, market).await?;
+            set_market_active(&pool, &market.market_pk, active).await?;
+            market.scraper_active = active;
+            print_output(&market, json, print_market)?;
+        }
+        ["rename", market, new_name] => {
+            let market = find_market(&pool, market).await?;
+            rename_market(&pool, &market.market_pk, new_name).await?;
+            let market = find_market(&pool, &market.market_pk).await?;
+            print_output(&market, json, print_market)?;
+        }
+        ["import", market_pk] => {
+            let market_pk = market_pk.parse::<Pubkey>()?;
+            let source = transaction_source_from_env()?;
+            let market = match find_market_creation(source.as_ref(), &market_pk, None).await? {
+                Some(m) => m,
+                None => {
+                    return Err(anyhow::anyhow!(
+                        "no market creation found for {}",
+                        market_pk
+                    ))
+                }
+            };
+            complete_market_import(&pool, market).await?;
+            let market = find_market(&pool, &market_pk.to_string()).await?;
+            print_output(&market, json, print_market)?;
+        }
+        ["imports"] => {
+            let imports =
+                fetch_market_imports(&pool, &PROGRAM_CONFIG.cluster, MAX_IMPORT_ATTEMPTS).await?;
+            print_output(&imports[..], json, print_imports)?;
+        }
+        ["retry-import", "all"] => {
+            let num_retried = retry_market_imports(&pool, &PROGRAM_CONFIG.cluster, None).await?;
+            print_output(&num_retried, json, print_retried_imports)?;
+        }
+        ["retry-import", market_pks @ ..] if !market_pks.is_empty() => {
+            let market_pks = market_pks.iter().map(|m| m.to_string()).collect();
+            let num_retried =
+                retry_market_imports(&pool, &PROGRAM_CONFIG.cluster, Some(market_pks)).await?;
+            print_output(&num_retried, json, print_retried_imports)?;
+        }
+        ["drop-import", market_pks @ ..] if !market_pks.is_empty() => {
+            let market_pks = market_pks.iter().map(|m| m.to_string()).collect();
+            let num_dropped =
+                drop_market_imports(&pool, &PROGRAM_CONFIG.cluster, market_pks).await?;
+            print_output(&num_dropped, json, print_dropped_imports)?;
+        }
+        ["lag"] => {
+            let lags = fetch_market_lags(&pool).await?;
+            print_output(&lags[..], json, print_lags)?;
+        }
+        ["dead-letters"] => {
+            let transactions = fetch_dead_letter_transactions(&pool).await?;
+            print_output(&transactions[..], json, print_dead_letters)?;
+        }
+        ["requeue", "all"] => {
+            let num_requeued = requeue_dead_letter_transactions(&pool, None).await?;
+            print_output(&num_requeued, json, print_requeued)?;
+        }
+        ["requeue", signatures @ ..] if !signatures.is_empty() => {
+            let signatures = signatures.iter().map(|s| s.to_string()).collect();
+            let num_requeued = requeue_dead_letter_transactions(&pool, Some(signatures)).await?;
+            print_output(&num_requeued, json, print_requeued)?;
+        }
+        ["reparse", "market", market, slots @ ..] if slots.is_empty() || slots.len() == 2 => {
+            let market = find_market(&pool, market).await?;
+            let (from_slot, to_slot) = match slots {
+                [from_slot, to_slot] => (from_slot.parse::<u64>()?, to_slot.parse::<u64>()?),
+                _ => (0, u64::MAX),
+            };
+            let summary =
+                reparse_archived_transactions(&pool, Some(&market), from_slot, to_slot).await?;
+            print_output(&summary, json, print_reparse)?;
+        }
+        ["reparse", "slots", from_slot, to_slot] => {
+            let summary = reparse_archived_transactions(
+                &pool,
+                None,
+                from_slot.parse::<u64>()?,
+                to_slot.parse::<u64>()?,
+            )
+            .await?;
+            print_output(&summary, json, print_reparse)?;
+        }
+        ["coverage", from, to] => {
+            let from = to_timestampz(from.parse::<u64>()?);
+            let to = to_timestampz(to.parse::<u64>()?);
+            let ranges =
+                fetch_block_scan_ranges_between(&pool, &PROGRAM_CONFIG.cluster, from, to).await?;
+            let coverage = BlockScanCoverage::from_ranges(from, to, &ranges);
+            print_output(&coverage, json, print_coverage)?;
+        }
+        _ => {
+            return Err(anyhow::anyhow!(USAGE));
+        }
+    }
+
+    Ok(())
+}
+
+async fn find_market(
+    pool: &deadpool_postgres::Pool,
+    market: &str,
+) -> anyhow::Result<OpenBookMarketMetadata> {
+    match fetch_market(pool, market).await? {
+        Some(m) => Ok(m),
+        None => Err(anyhow::anyhow!("market {} not found", market)),
+    }
+}
+
+/// Inspects the transaction with the given signature, or the recorded transactions of a file.
+/// Only reads from the database, so that it can be pointed at production.
+async fn inspect(
+    pool: &deadpool_postgres::Pool,
+    target: &str,
+) -> anyhow::Result<Vec<InspectedTransaction>> {
+    let source = transaction_source_from_env()?;
+    let (sig_strings, mut txns) = if Path::new(target).is_file() {
+        let recorded = read_recorded_transactions(Path::new(target))?;
+        let sig_strings = recorded
+            .iter()
+            .map(|t| transaction_signature(&t.transaction).unwrap_or_default())
+            .collect::<Vec<String>>();
+        (sig_strings, recorded.into_iter().map(Ok).collect())
+    } else {
+        let signature = target.parse::<Signature>()?;
+        let txns = source.get_transactions(&[signature]).await;
+        (vec![signature.to_string()], txns)
+    };
+    fill_missing_block_times(source.as_ref(), &mut txns).await;
+
+    let markets: HashMap<String, OpenBookMarketMetadata> = fetch_all_markets(pool)
+        .await?
+        .into_iter()
+        .map(|m| (m.market_pk.clone(), m))
+        .collect();
+    let mut inspected = vec![];
+    for (signature, txn) in sig_strings.iter().zip(txns) {
+        match txn {
+            Ok(txn) => inspected.push(inspect_transaction(signature, txn, &markets)),
+            Err(e) => return Err(anyhow::anyhow!("failed to fetch {}: {}", signature, e)),
+        }
+    }
+    Ok(inspected)
+}
+
+fn print_output<T: Serialize + ?Sized>(
+    value: &T,
+    json: bool,
+    print_human: fn(&T),
+) -> anyhow::Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(value)?);
+    } else {
+        print_human(value);
+    }
+    Ok(())
+}
+
+fn print_market(market: &OpenBookMarketMetadata) {
+    println!("market_pk:       {}", market.market_pk);
+    println!("market_name:     {}", market.market_name);
+    println!("base_mint:       {}", market.base_mint);
+    println!("quote_mint:      {}", market.quote_mint);
+    println!("created:         {}", market.creation_datetime.to_rfc3339());
+    println!("scraper_active:  {}", market.scraper_active);
+}
+
+fn print_summaries(summaries: &[MarketSummary]) {
+    println!(
+        "{:<44}  {:<24}  {:<6}  {:>12}  {:>12}",
+        "MARKET", "NAME", "ACTIVE", "FILLS", "CANDLES"
+    );
+    for s in summaries.iter() {
+        println!(
+            "{:<44}  {:<24}  {:<6}  {:>12}  {:>12}",
+            s.market_pk, s.market_name, s.scraper_active, s.fill_count, s.candle_count
+        );
+    }
+}
+
+fn print_lags(lags: &[MarketLag]) {
+    println!(
+        "{:<24}  {:<6}  {:<26}  {:>10}  {:<26}  {:>10}",
+        "NAME", "ACTIVE", "LATEST FILL", "FILL LAG", "LATEST CANDLE", "CANDLE LAG"
+    );
+    for l in lags.iter() {
+        println!(
+            "{:<24}  {:<6}  {:<26}  {:>10}  {:<26}  {:>10}",
+            l.market_name,
+            l.scraper_active,
+            l.latest_fill.as_deref().unwrap_or("-"),
+            format_lag(l.fill_lag_seconds),
+            l.latest_candle.as_deref().unwrap_or("-"),
+            format_lag(l.candle_lag_seconds),
+        );
+    }
+}
+
+fn print_dead_letters(transactions: &[DeadLetterTransaction]) {
+    println!(
+        "{:<88}  {:>10}  {:>8}  ERROR",
+        "SIGNATURE", "SLOT", "ATTEMPTS"
+    );
+    for t in transactions.iter() {
+        println!(
+            "{:<88}  {:>10}  {:>8}  {}",
+            t.signature,
+            t.slot,
+            t.attempts,
+            t.last_error.as_deref().unwrap_or("-")
+        );
+    }
+}
+
+fn print_requeued(num_requeued: &u64) {
+    println!("requeued {} transactions", num_requeued);
+}
+
+fn print_imports(imports: &[MarketImport]) {
+    println!(
+        "{:<44}  {:<25}  {:>8}  {:<5}  ERROR",
+        "MARKET", "QUEUED", "ATTEMPTS", "STUCK"
+    );
+    for i in imports.iter() {
+        println!(
+            "{:<44}  {:<25}  {:>8}  {:<5}  {}",
+            i.market_pk,
+            i.queued_datetime,
+            i.attempts,
+            i.stuck,
+            i.last_error.as_deref().unwrap_or("-")
+        );
+    }
+}
+
+fn print_retried_imports(num_retried: &u64) {
+    println!("retrying {} market imports", num_retried);
+}
+
+fn print_dropped_imports(num_dropped: &u64) {
+    println!("dropped {} market imports", num_dropped);
+}
+
+fn print_reparse(summary: &ReparseSummary) {
+    println!(
+        "reparsed {} transactions, {} fills",
+        summary.transactions, summary.fills
+    );
+    for (signature, error) in summary.failed.iter() {
+        println!("failed {}: {}", signature, error);
+    }
+}
+
+fn print_coverage(coverage: &BlockScanCoverage) {
+    println!("from:     {}", coverage.from);
+    println!("to:       {}", coverage.to);
+    println!("covered:  {}", coverage.covered);
+    for (start_slot, end_slot) in coverage.ranges.iter() {
+        println!("scanned   {} to {}", start_slot, end_slot);
+    }
+    for (start_slot, end_slot) in coverage.gaps.iter() {
+        println!("missing   {} to {}", start_slot, end_slot);
+    }
+}
+
+fn print_inspected(transactions: &[InspectedTransaction]) {
+    for t in transactions.iter() {
+        println!("signature:       {}", t.signature);
+        println!("slot:            {}", t.slot);
+        println!(
+            "block_datetime:  {}",
+            t.block_datetime.as_deref().unwrap_or("-")
+        );
+        println!("log_truncated:   {}", t.log_truncated);
+        if let Some(error) = &t.error {
+            println!("error:           {}", error);
+        }
+        if !t.unknown_markets.is_empty() {
+            println!("unknown_markets: {}", t.unknown_markets.join(", "));
+        }
+        for e in t.events.iter() {
+            let log_index = e.log_index.map_or("-".to_string(), |i| i.to_string());
+            println!(
+                "\n[{}] {} {}",
+                log_index,
+                e.kind,
+                e.market_pk.as_deref().unwrap_or("")
+            );
+            for (name, value) in e.fields.iter() {
+                println!("    {:<32} {}", name, value);
+            }
+        }
+        for (log_index, data) in t.undecoded_program_data.iter() {
+            let log_index = log_index.map_or("-".to_string(), |i| i.to_string());
+            println!("\n[{}] undecoded program data: {}", log_index, data);
+        }
+        println!();
+    }
+}
+
+fn format_lag(seconds: Option<i64>) -> String {
+    match seconds {
+        Some(s) => format!("{}s", s),
+        None => "-".to_string(),
+    }
+}
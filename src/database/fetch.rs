@@ -1,10 +1,17 @@
 use crate::structs::{
+    archived_transaction::ArchivedTransaction,
+    backfill::BackfillState,
+    block_scan::BlockScanRange,
     candle::Candle,
     coingecko::{PgCoinGecko24HighLow, PgCoinGecko24HourVolume},
+    fill_gap::{FillGap, FillSequence},
+    market_import::MarketImport,
+    market_summary::{MarketLag, MarketSummary},
     openbook_v2::{OpenBookFill, OpenBookMarketMetadata},
     resolution::Resolution,
+    scraper_state::ScraperState,
     trader::Trader,
-    transaction::PgTransaction,
+    transaction::{DeadLetterTransaction, PgTransaction},
 };
 use chrono::{DateTime, Utc};
 use deadpool_postgres::{GenericClient, Pool};
@@ -30,9 +37,14 @@ pub async fn fetch_earliest_fill(
         maker_slot as "maker_slot",
         maker_out as "maker_out",
         price as "price",
-        quantity as "quantity"
+        quantity as "quantity",
+        program_pk as "program_pk",
+        cluster as "cluster",
+        signature as "signature",
+        log_index as "log_index"
         from fills
         where market_pk = $1
+        and commitment <> 'orphaned'
         ORDER BY block_datetime asc LIMIT 1"#;
 
     let row = client.query_opt(stmt, &[&market_address_string]).await?;
@@ -67,11 +79,16 @@ pub async fn fetch_fills_from(
         maker_slot as "maker_slot",
         maker_out as "maker_out",
         price as "price",
-        quantity as "quantity"
+        quantity as "quantity",
+        program_pk as "program_pk",
+        cluster as "cluster",
+        signature as "signature",
+        log_index as "log_index"
         from fills 
          where market_pk = $1
          and block_datetime >= $2::timestamptz
          and block_datetime < $3::timestamptz
+         and commitment <> 'orphaned'
          ORDER BY block_datetime asc"#;
 
     let rows = client
@@ -97,7 +114,9 @@ pub async fn fetch_latest_finished_candle(
         high as "high!",
         low as "low!",
         volume as "volume!",
-        complete as "complete!"
+        complete as "complete!",
+        program_pk as "program_pk!",
+        cluster as "cluster!"
         from candles
         where market_name = $1
         and resolution = $2
@@ -133,7 +152,9 @@ pub async fn fetch_earliest_candles(
         high as "high!",
         low as "low!",
         volume as "volume!",
-        complete as "complete!"
+        complete as "complete!",
+        program_pk as "program_pk!",
+        cluster as "cluster!"
         from candles
         where market_name = $1
         and resolution = $2
@@ -166,7 +187,9 @@ pub async fn fetch_candles_from(
         high as "high!",
         low as "low!",
         volume as "volume!",
-        complete as "complete!"
+        complete as "complete!",
+        program_pk as "program_pk!",
+        cluster as "cluster!"
         from candles
         where market_name = $1
         and resolution = $2
@@ -210,6 +233,7 @@ pub async fn fetch_top_traders_by_base_volume_from(
             WHERE  market_pk = $1
                 AND block_datetime >= $2
                 AND block_datetime < $3
+                AND commitment <> 'orphaned'
             UNION ALL
             SELECT
                 taker AS trader,
@@ -219,6 +243,7 @@ pub async fn fetch_top_traders_by_base_volume_from(
             WHERE  market_pk = $1
                 AND block_datetime >= $2
                 AND block_datetime < $3
+                AND commitment <> 'orphaned'
         ) AS all_trades
         GROUP BY
             trader
@@ -254,6 +279,7 @@ pub async fn fetch_top_traders_by_quote_volume_from(
             WHERE  market_pk = $1
                 AND block_datetime >= $2
                 AND block_datetime < $3
+                AND commitment <> 'orphaned'
             UNION ALL
             SELECT
                 taker AS trader,
@@ -263,6 +289,7 @@ pub async fn fetch_top_traders_by_quote_volume_from(
             WHERE  market_pk = $1
                 AND block_datetime >= $2
                 AND block_datetime < $3
+                AND commitment <> 'orphaned'
         ) AS all_trades
         GROUP BY
             trader
@@ -355,26 +382,100 @@ pub async fn fetch_coingecko_24h_high_low(
         .collect())
 }
 
-/// Fetches unprocessed, non-error transactions for the specified worker partition.
-/// Pulls at most 50 transactions at a time.
-pub async fn fetch_worker_transactions(
-    worker_id: i32,
+/// Claims up to `limit` unprocessed, non-error transactions of the cluster that are due for a
+/// fetch for the given claimant, newest first. Rows locked by a concurrent claim are skipped, and a claim is
+/// only held until its lease expires, so the transactions of a claimant that died are picked up
+/// again by the others.
+pub async fn claim_transactions(
     pool: &Pool,
+    cluster: &str,
+    claimant: &str,
+    limit: i64,
+    lease_secs: i64,
 ) -> anyhow::Result<Vec<PgTransaction>> {
     let client = pool.get().await?;
 
-    let stmt = r#"SELECT signature, program_pk, block_datetime, slot, err, "processed", worker_partition
-            FROM transactions
-            where worker_partition = $1
-            and err = false 
-            and processed = false
-            LIMIT 50"#;
+    let stmt = r#"UPDATE transactions t
+            SET claimed_by = $1,
+            claim_expires_at = now() + make_interval(secs => $3)
+            FROM (
+                SELECT signature
+                FROM transactions
+                where err = false
+                and processed = false
+                and dead_letter = false
+                and (claim_expires_at IS NULL OR claim_expires_at < now())
+                and (next_retry_at IS NULL OR next_retry_at <= now())
+                and cluster = $4
+                ORDER BY slot DESC
+                LIMIT $2
+                FOR UPDATE SKIP LOCKED
+            ) claimable
+            WHERE t.signature = claimable.signature
+            RETURNING t.signature, t.program_pk, t.block_datetime, t.slot, t.err, t.processed, t.cluster"#;
 
-    let rows = client.query(stmt, &[&worker_id]).await?;
+    let rows = client
+        .query(stmt, &[&claimant, &limit, &(lease_secs as f64), &cluster])
+        .await?;
 
     Ok(rows.into_iter().map(PgTransaction::from_row).collect())
 }
 
+/// Claims markets of the cluster queued for import that are not claimed by another process, for
+/// the length of a lease. Markets that failed `max_attempts` times are left in the queue.
+pub async fn claim_market_imports(
+    pool: &Pool,
+    cluster: &str,
+    limit: i64,
+    lease_secs: i64,
+    max_attempts: i32,
+) -> anyhow::Result<Vec<String>> {
+    let client = pool.get().await?;
+
+    let stmt = r#"UPDATE market_imports m
+            SET claim_expires_at = now() + make_interval(secs => $2)
+            FROM (
+                SELECT market_pk
+                FROM market_imports
+                where attempts < $3
+                and (claim_expires_at IS NULL OR claim_expires_at < now())
+                and cluster = $4
+                ORDER BY queued_at
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            ) claimable
+            WHERE m.market_pk = claimable.market_pk
+            RETURNING m.market_pk"#;
+
+    let rows = client
+        .query(
+            stmt,
+            &[&limit, &(lease_secs as f64), &max_attempts, &cluster],
+        )
+        .await?;
+
+    Ok(rows.into_iter().map(|r| r.get(0)).collect())
+}
+
+/// Fetches the markets of the cluster queued for import, the ones that failed `max_attempts`
+/// times first.
+pub async fn fetch_market_imports(
+    pool: &Pool,
+    cluster: &str,
+    max_attempts: i32,
+) -> anyhow::Result<Vec<MarketImport>> {
+    let client = pool.get().await?;
+
+    let stmt = r#"SELECT market_pk, queued_at, attempts, last_error, attempts >= $2 as stuck
+            FROM market_imports
+            where cluster = $1
+            ORDER BY stuck DESC, queued_at"#;
+
+    let rows = client.query(stmt, &[&cluster, &max_attempts]).await?;
+
+    Ok(rows.into_iter().map(MarketImport::from_row).collect())
+}
+
 pub async fn fetch_active_markets(pool: &Pool) -> anyhow::Result<Vec<OpenBookMarketMetadata>> {
     let client = pool.get().await?;
 
@@ -390,7 +491,8 @@ pub async fn fetch_active_markets(pool: &Pool) -> anyhow::Result<Vec<OpenBookMar
         quote_decimals, 
         base_lot_size, 
         quote_lot_size, 
-        scraper_active
+        scraper_active,
+        cluster
     FROM public.market_metadata
         where scraper_active = true"#;
 
@@ -401,3 +503,339 @@ pub async fn fetch_active_markets(pool: &Pool) -> anyhow::Result<Vec<OpenBookMar
         .map(OpenBookMarketMetadata::from_row)
         .collect())
 }
+
+/// Fetches every market in `market_metadata`, active or not.
+pub async fn fetch_all_markets(pool: &Pool) -> anyhow::Result<Vec<OpenBookMarketMetadata>> {
+    let client = pool.get().await?;
+
+    let stmt = r#"
+    SELECT 
+        creation_datetime, 
+        program_pk, 
+        market_pk, 
+        market_name, 
+        base_mint, 
+        quote_mint, 
+        base_decimals, 
+        quote_decimals, 
+        base_lot_size, 
+        quote_lot_size, 
+        scraper_active,
+        cluster
+    FROM public.market_metadata"#;
+
+    let rows = client.query(stmt, &[]).await?;
+
+    Ok(rows
+        .into_iter()
+        .map(OpenBookMarketMetadata::from_row)
+        .collect())
+}
+
+/// Fetches a market by pubkey or by name, whether it is active or not.
+pub async fn fetch_market(
+    pool: &Pool,
+    market: &str,
+) -> anyhow::Result<Option<OpenBookMarketMetadata>> {
+    let client = pool.get().await?;
+
+    let stmt = r#"
+    SELECT 
+        creation_datetime, 
+        program_pk, 
+        market_pk, 
+        market_name, 
+        base_mint, 
+        quote_mint, 
+        base_decimals, 
+        quote_decimals, 
+        base_lot_size, 
+        quote_lot_size, 
+        scraper_active,
+        cluster
+    FROM public.market_metadata
+        where market_pk = $1 or market_name = $1"#;
+
+    let row = client.query_opt(stmt, &[&market]).await?;
+
+    Ok(row.map(OpenBookMarketMetadata::from_row))
+}
+
+/// Fetches every market with its number of fills and candles.
+pub async fn fetch_market_summaries(pool: &Pool) -> anyhow::Result<Vec<MarketSummary>> {
+    let client = pool.get().await?;
+
+    let stmt = r#"SELECT
+        m.market_pk,
+        m.market_name,
+        m.scraper_active,
+        (SELECT count(*) FROM fills f WHERE f.market_pk = m.market_pk AND f.commitment <> 'orphaned') as fill_count,
+        (SELECT count(*) FROM candles c WHERE c.market_name = m.market_name) as candle_count
+    FROM market_metadata m
+    ORDER BY m.market_name"#;
+
+    let rows = client.query(stmt, &[]).await?;
+
+    Ok(rows.into_iter().map(MarketSummary::from_row).collect())
+}
+
+/// Fetches the newest fill and the newest complete 1 minute candle of every market.
+pub async fn fetch_market_lags(pool: &Pool) -> anyhow::Result<Vec<MarketLag>> {
+    let client = pool.get().await?;
+
+    let stmt = r#"SELECT
+        m.market_pk,
+        m.market_name,
+        m.scraper_active,
+        (SELECT max(f.block_datetime) FROM fills f WHERE f.market_pk = m.market_pk AND f.commitment <> 'orphaned') as latest_fill,
+        (SELECT max(c.end_time) FROM candles c
+            WHERE c.market_name = m.market_name
+            and c.resolution = $1
+            and c.complete = true) as latest_candle
+    FROM market_metadata m
+    ORDER BY m.market_name"#;
+
+    let rows = client.query(stmt, &[&Resolution::R1m.to_string()]).await?;
+
+    Ok(rows.into_iter().map(MarketLag::from_row).collect())
+}
+
+pub async fn fetch_backfill_state(
+    pool: &Pool,
+    address: &str,
+) -> anyhow::Result<Option<BackfillState>> {
+    let client = pool.get().await?;
+
+    let stmt = r#"SELECT
+        address,
+        before_signature,
+        oldest_slot,
+        target_slot,
+        target_datetime,
+        complete
+    FROM backfill_state
+        where address = $1"#;
+
+    let row = client.query_opt(stmt, &[&address]).await?;
+
+    Ok(row.map(BackfillState::from_row))
+}
+
+pub async fn fetch_scraper_state(
+    pool: &Pool,
+    address: &str,
+) -> anyhow::Result<Option<ScraperState>> {
+    let client = pool.get().await?;
+
+    let stmt = r#"SELECT address, last_signature, last_slot
+    FROM scraper_state
+        where address = $1"#;
+
+    let row = client.query_opt(stmt, &[&address]).await?;
+
+    Ok(row.map(ScraperState::from_row))
+}
+
+/// Finds `seq_num` gaps between the fills of a market since the given time.
+pub async fn fetch_fill_gaps(
+    pool: &Pool,
+    market_pk: &str,
+    since: DateTime<Utc>,
+) -> anyhow::Result<Vec<FillGap>> {
+    let client = pool.get().await?;
+
+    let stmt = r#"SELECT market_pk, seq_num, next_seq_num, slot, next_slot, signature, next_signature
+    FROM (
+        SELECT
+            market_pk,
+            seq_num,
+            slot,
+            signature,
+            lead(seq_num) OVER w as next_seq_num,
+            lead(slot) OVER w as next_slot,
+            lead(signature) OVER w as next_signature
+        FROM fills
+        where market_pk = $1
+        and block_datetime >= $2
+        and commitment <> 'orphaned'
+        WINDOW w AS (ORDER BY seq_num)
+    ) adjacent_fills
+    where next_seq_num > seq_num + 1
+    ORDER BY seq_num"#;
+
+    let rows = client.query(stmt, &[&market_pk, &since]).await?;
+
+    Ok(rows.into_iter().map(FillGap::from_row).collect())
+}
+
+/// Fetches the oldest distinct slots of fills of the cluster that are still only confirmed, up
+/// to the given finalized slot.
+pub async fn fetch_unfinalized_fill_slots(
+    pool: &Pool,
+    cluster: &str,
+    max_slot: u64,
+    limit: i64,
+) -> anyhow::Result<Vec<u64>> {
+    let client = pool.get().await?;
+
+    let stmt = r#"SELECT DISTINCT slot
+            FROM fills
+            where commitment = 'confirmed'
+            and slot <= $1
+            and cluster = $3
+            ORDER BY slot
+            LIMIT $2"#;
+
+    let rows = client
+        .query(stmt, &[&(max_slot as i64), &limit, &cluster])
+        .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|r| r.get::<usize, i64>(0) as u64)
+        .collect())
+}
+
+/// Fetches the dead lettered transactions, newest first.
+pub async fn fetch_dead_letter_transactions(
+    pool: &Pool,
+) -> anyhow::Result<Vec<DeadLetterTransaction>> {
+    let client = pool.get().await?;
+
+    let stmt = r#"SELECT signature, program_pk, block_datetime, slot, attempts, last_error
+            FROM transactions
+            where dead_letter = true
+            ORDER BY slot DESC"#;
+
+    let rows = client.query(stmt, &[]).await?;
+
+    Ok(rows
+        .into_iter()
+        .map(DeadLetterTransaction::from_row)
+        .collect())
+}
+
+/// Fetches the `seq_num` range and fill count of a market since the given time, or None if it
+/// has no fills in that time.
+pub async fn fetch_fill_sequence(
+    pool: &Pool,
+    market_pk: &str,
+    since: DateTime<Utc>,
+) -> anyhow::Result<Option<FillSequence>> {
+    let client = pool.get().await?;
+
+    let stmt = r#"SELECT market_pk, count(*), min(seq_num), max(seq_num)
+            FROM fills
+            where market_pk = $1
+            and block_datetime >= $2
+            and commitment <> 'orphaned'
+            GROUP BY market_pk"#;
+
+    let row = client.query_opt(stmt, &[&market_pk, &since]).await?;
+
+    Ok(row.map(FillSequence::from_row))
+}
+
+/// Fetches a page of archived transactions between two slots, inclusive, in slot order,
+/// optionally only the ones referencing an account. Pages continue after the `(slot, signature)`
+/// of the last transaction of the previous page.
+pub async fn fetch_archived_transactions(
+    pool: &Pool,
+    account: Option<&str>,
+    from_slot: u64,
+    to_slot: u64,
+    after: Option<(u64, String)>,
+    limit: i64,
+) -> anyhow::Result<Vec<ArchivedTransaction>> {
+    let client = pool.get().await?;
+
+    let (after_slot, after_signature) = match after {
+        Some((slot, signature)) => (slot as i64, signature),
+        None => (-1, String::new()),
+    };
+
+    let stmt = r#"SELECT signature, slot, block_time, program_pk, account_keys, cluster, payload
+            FROM transaction_archive
+            where slot BETWEEN $1 AND $2
+            and (slot, signature) > ($3, $4)
+            and ($5::text IS NULL OR account_keys @> ARRAY[$5::text])
+            ORDER BY slot, signature
+            LIMIT $6"#;
+
+    let rows = client
+        .query(
+            stmt,
+            &[
+                &(from_slot as i64),
+                &(to_slot.min(i64::MAX as u64) as i64),
+                &after_slot,
+                &after_signature,
+                &account,
+                &limit,
+            ],
+        )
+        .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(ArchivedTransaction::from_row)
+        .collect())
+}
+
+/// Fetches the block scan ranges of the cluster ending at or after `from_slot`, in slot order.
+pub async fn fetch_block_scan_ranges_from(
+    pool: &Pool,
+    cluster: &str,
+    from_slot: u64,
+) -> anyhow::Result<Vec<BlockScanRange>> {
+    let client = pool.get().await?;
+
+    let stmt = r#"SELECT cluster, start_slot, end_slot, start_datetime, end_datetime, num_blocks, num_transactions
+            FROM block_scan_ranges
+            where cluster = $1
+            and end_slot >= $2
+            ORDER BY start_slot"#;
+
+    let rows = client.query(stmt, &[&cluster, &(from_slot as i64)]).await?;
+
+    Ok(rows.into_iter().map(BlockScanRange::from_row).collect())
+}
+
+/// Fetches the block scan ranges of the cluster around two times, in slot order: from the last
+/// range with a block at or before `from` to the first range with a block at or after `to`, so
+/// that a window starting or ending between the blocks of two ranges is still covered by them.
+/// Ranges of empty blocks have no datetimes, so they are selected by slot.
+pub async fn fetch_block_scan_ranges_between(
+    pool: &Pool,
+    cluster: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> anyhow::Result<Vec<BlockScanRange>> {
+    let client = pool.get().await?;
+
+    let stmt = r#"WITH bounds AS (
+            SELECT
+                COALESCE(
+                    (SELECT max(start_slot) FROM block_scan_ranges
+                        where cluster = $1 and start_datetime <= $2),
+                    (SELECT min(start_slot) FROM block_scan_ranges
+                        where cluster = $1 and end_datetime >= $2)
+                ) as from_slot,
+                COALESCE(
+                    (SELECT min(end_slot) FROM block_scan_ranges
+                        where cluster = $1 and end_datetime >= $3),
+                    (SELECT max(end_slot) FROM block_scan_ranges
+                        where cluster = $1 and start_datetime <= $3)
+                ) as to_slot
+        )
+        SELECT cluster, start_slot, end_slot, start_datetime, end_datetime, num_blocks, num_transactions
+            FROM block_scan_ranges, bounds
+            where cluster = $1
+            and end_slot >= bounds.from_slot
+            and start_slot <= bounds.to_slot
+            ORDER BY start_slot"#;
+
+    let rows = client.query(stmt, &[&cluster, &from, &to]).await?;
+
+    Ok(rows.into_iter().map(BlockScanRange::from_row).collect())
+}
@@ -6,7 +6,7 @@ use deadpool_postgres::{
 use native_tls::{Certificate, Identity, TlsConnector};
 use postgres_native_tls::MakeTlsConnector;
 
-use crate::utils::PgConfig;
+use crate::utils::{PgConfig, DEFAULT_CLUSTER, OPENBOOK_KEY};
 
 pub async fn connect_to_database() -> anyhow::Result<Pool> {
     let mut pg_config = PgConfig::from_env()?;
@@ -57,7 +57,7 @@ pub async fn connect_to_database() -> anyhow::Result<Pool> {
     match pool.get().await {
         Ok(_) => println!("Database connected"),
         Err(e) => {
-            println!("Failed to connect to database: {}, retrying", e.to_string());
+            println!("Failed to connect to database: {}, retrying", e);
             tokio::time::sleep(Duration::from_millis(500)).await;
         }
     }
@@ -70,11 +70,23 @@ pub async fn setup_database(pool: &Pool) -> anyhow::Result<()> {
     let transactions_table_fut = create_transactions_table(pool);
     let market_metadata_fut = create_market_metadata_table(pool);
     let fills_table_fut = create_fills_table(pool);
+    let backfill_state_fut = create_backfill_state_table(pool);
+    let scraper_state_fut = create_scraper_state_table(pool);
+    let event_tables_fut = create_event_tables(pool);
+    let market_imports_fut = create_market_imports_table(pool);
+    let transaction_archive_fut = create_transaction_archive_table(pool);
+    let block_scan_ranges_fut = create_block_scan_ranges_table(pool);
     let result = tokio::try_join!(
         candles_table_fut,
         transactions_table_fut,
         fills_table_fut,
-        market_metadata_fut
+        market_metadata_fut,
+        backfill_state_fut,
+        scraper_state_fut,
+        event_tables_fut,
+        market_imports_fut,
+        transaction_archive_fut,
+        block_scan_ranges_fut
     );
     match result {
         Ok(_) => {
@@ -124,6 +136,14 @@ pub async fn create_candles_table(pool: &Pool) -> anyhow::Result<()> {
         END $$", &[]
     ).await?;
 
+    client
+        .batch_execute(&format!(
+            "ALTER TABLE candles ADD COLUMN IF NOT EXISTS program_pk text NOT NULL DEFAULT '{}';
+            ALTER TABLE candles ADD COLUMN IF NOT EXISTS cluster text NOT NULL DEFAULT '{}';",
+            OPENBOOK_KEY, DEFAULT_CLUSTER
+        ))
+        .await?;
+
     Ok(())
 }
 
@@ -134,9 +154,9 @@ pub async fn create_fills_table(pool: &Pool) -> anyhow::Result<()> {
         .execute(
             "CREATE TABLE IF NOT EXISTS fills (
             block_datetime timestamptz not null,
-            slot int4 not null,
+            slot int8 not null,
             market_pk text not null,
-            seq_num int4 not null,
+            seq_num int8 not null,
             maker text not null,
             maker_client_order_id text not null,
             maker_fee double precision not null,
@@ -155,18 +175,172 @@ pub async fn create_fills_table(pool: &Pool) -> anyhow::Result<()> {
         )
         .await?;
 
+    // slots and seq_nums outgrow int4, tables created before they were int8 are migrated once
+    client
+        .batch_execute(
+            "DO $$
+            BEGIN
+                IF (SELECT data_type FROM information_schema.columns
+                    WHERE table_name = 'fills' AND column_name = 'slot') = 'integer' THEN
+                    ALTER TABLE fills ALTER COLUMN slot TYPE int8, ALTER COLUMN seq_num TYPE int8;
+                END IF;
+            END $$;",
+        )
+        .await?;
+
     client
         .execute(
             "CREATE INDEX IF NOT EXISTS idx_market_time ON fills (market_pk, block_datetime)",
             &[],
         )
         .await?;
+
+    client
+        .batch_execute(&format!(
+            "ALTER TABLE fills ADD COLUMN IF NOT EXISTS program_pk text NOT NULL DEFAULT '{}';
+            ALTER TABLE fills ADD COLUMN IF NOT EXISTS cluster text NOT NULL DEFAULT '{}';",
+            OPENBOOK_KEY, DEFAULT_CLUSTER
+        ))
+        .await?;
+
+    // fills are written at confirmed commitment, and later either finalized or orphaned when
+    // their slot never makes it into the finalized chain
+    client
+        .batch_execute(
+            "ALTER TABLE fills ADD COLUMN IF NOT EXISTS commitment text NOT NULL DEFAULT 'confirmed';
+            CREATE INDEX IF NOT EXISTS idx_fills_unfinalized ON fills (slot) WHERE commitment = 'confirmed';",
+        )
+        .await?;
+
+    // the transaction of each fill, null for fills stored before it was recorded
+    client
+        .batch_execute(
+            "ALTER TABLE fills ADD COLUMN IF NOT EXISTS signature text;
+            ALTER TABLE fills ADD COLUMN IF NOT EXISTS log_index int4;
+            CREATE INDEX IF NOT EXISTS idx_fills_signature ON fills (signature);",
+        )
+        .await?;
+    Ok(())
+}
+
+/// Tables for the OpenBook events other than fills, keyed by the transaction signature and the
+/// position of the event within the transaction. Native token amounts are stored as numeric.
+pub async fn create_event_tables(pool: &Pool) -> anyhow::Result<()> {
+    let client = pool.get().await?;
+
+    client
+        .batch_execute(
+            "CREATE TABLE IF NOT EXISTS deposits (
+                signature text NOT NULL,
+                log_index int4 NOT NULL,
+                block_datetime timestamptz NOT NULL,
+                slot bigint NOT NULL,
+                open_orders_account text NOT NULL,
+                signer text NOT NULL,
+                base_amount numeric NOT NULL,
+                quote_amount numeric NOT NULL,
+                CONSTRAINT deposits_pk PRIMARY KEY (signature, log_index)
+            );
+            CREATE INDEX IF NOT EXISTS idx_deposits_ooa_time ON deposits (open_orders_account, block_datetime);
+
+            CREATE TABLE IF NOT EXISTS settle_funds (
+                signature text NOT NULL,
+                log_index int4 NOT NULL,
+                block_datetime timestamptz NOT NULL,
+                slot bigint NOT NULL,
+                open_orders_account text NOT NULL,
+                base_native numeric NOT NULL,
+                quote_native numeric NOT NULL,
+                referrer_rebate numeric NOT NULL,
+                referrer text,
+                CONSTRAINT settle_funds_pk PRIMARY KEY (signature, log_index)
+            );
+            CREATE INDEX IF NOT EXISTS idx_settle_funds_ooa_time ON settle_funds (open_orders_account, block_datetime);
+
+            CREATE TABLE IF NOT EXISTS sweep_fees (
+                signature text NOT NULL,
+                log_index int4 NOT NULL,
+                block_datetime timestamptz NOT NULL,
+                slot bigint NOT NULL,
+                market_pk text NOT NULL,
+                amount numeric NOT NULL,
+                receiver text NOT NULL,
+                CONSTRAINT sweep_fees_pk PRIMARY KEY (signature, log_index)
+            );
+            CREATE INDEX IF NOT EXISTS idx_sweep_fees_market_time ON sweep_fees (market_pk, block_datetime);
+
+            CREATE TABLE IF NOT EXISTS open_orders_positions (
+                signature text NOT NULL,
+                log_index int4 NOT NULL,
+                block_datetime timestamptz NOT NULL,
+                slot bigint NOT NULL,
+                owner text NOT NULL,
+                open_orders_account_num int8 NOT NULL,
+                market_pk text NOT NULL,
+                bids_base_lots int8 NOT NULL,
+                bids_quote_lots int8 NOT NULL,
+                asks_base_lots int8 NOT NULL,
+                base_free_native numeric NOT NULL,
+                quote_free_native numeric NOT NULL,
+                locked_maker_fees numeric NOT NULL,
+                referrer_rebates_available numeric NOT NULL,
+                maker_volume numeric NOT NULL,
+                taker_volume numeric NOT NULL,
+                CONSTRAINT open_orders_positions_pk PRIMARY KEY (signature, log_index)
+            );
+            CREATE INDEX IF NOT EXISTS idx_open_orders_positions_market_owner ON open_orders_positions (market_pk, owner, block_datetime);
+
+            CREATE TABLE IF NOT EXISTS set_delegates (
+                signature text NOT NULL,
+                log_index int4 NOT NULL,
+                block_datetime timestamptz NOT NULL,
+                slot bigint NOT NULL,
+                open_orders_account text NOT NULL,
+                delegate text,
+                CONSTRAINT set_delegates_pk PRIMARY KEY (signature, log_index)
+            );
+
+            CREATE TABLE IF NOT EXISTS total_order_fills (
+                signature text NOT NULL,
+                log_index int4 NOT NULL,
+                block_datetime timestamptz NOT NULL,
+                slot bigint NOT NULL,
+                side int4 NOT NULL,
+                taker text NOT NULL,
+                total_quantity_paid numeric NOT NULL,
+                total_quantity_received numeric NOT NULL,
+                fees numeric NOT NULL,
+                CONSTRAINT total_order_fills_pk PRIMARY KEY (signature, log_index)
+            );
+            CREATE INDEX IF NOT EXISTS idx_total_order_fills_taker_time ON total_order_fills (taker, block_datetime);",
+        )
+        .await?;
     Ok(())
 }
 
 pub async fn create_transactions_table(pool: &Pool) -> anyhow::Result<()> {
     let client = pool.get().await?;
 
+    // the table used to be split into the partitions transactions_0..9 by slot % 10, which
+    // scraping tasks polled one each. Work is shared out by claims now, so a partitioned table
+    // is copied into a plain one keyed by signature, keeping every column but worker_partition
+    client
+        .batch_execute(
+            "DO $$
+            BEGIN
+                IF EXISTS (SELECT 1 FROM pg_partitioned_table
+                    WHERE partrelid = to_regclass('transactions')) THEN
+                    ALTER TABLE transactions RENAME TO transactions_partitioned;
+                    CREATE TABLE transactions (LIKE transactions_partitioned INCLUDING DEFAULTS);
+                    INSERT INTO transactions SELECT * FROM transactions_partitioned;
+                    DROP TABLE transactions_partitioned;
+                    ALTER TABLE transactions DROP COLUMN worker_partition;
+                    ALTER TABLE transactions ADD CONSTRAINT transactions_pk PRIMARY KEY (signature);
+                END IF;
+            END $$;",
+        )
+        .await?;
+
     client
         .execute(
             "CREATE TABLE IF NOT EXISTS transactions (
@@ -176,27 +350,50 @@ pub async fn create_transactions_table(pool: &Pool) -> anyhow::Result<()> {
                 slot bigint NOT NULL,
                 err bool NOT NULL,
                 processed bool NOT NULL,
-                worker_partition int4 NOT NULL,
-                CONSTRAINT transactions_pk PRIMARY KEY (signature, worker_partition)
-            ) PARTITION BY LIST (worker_partition);",
+                CONSTRAINT transactions_pk PRIMARY KEY (signature)
+            );",
             &[],
         )
         .await?;
 
     client.batch_execute(
-        "CREATE INDEX IF NOT EXISTS transactions_processed_err_idx ON ONLY transactions (signature) WHERE processed IS NOT TRUE and err IS NOT TRUE;
-        CREATE INDEX IF NOT EXISTS transactions_program_pk_idx ON ONLY transactions USING btree (program_pk, slot DESC);
-
-        CREATE TABLE IF NOT EXISTS transactions_0 PARTITION OF transactions  FOR VALUES IN (0);
-        CREATE TABLE IF NOT EXISTS transactions_1 PARTITION OF transactions  FOR VALUES IN (1);
-        CREATE TABLE IF NOT EXISTS transactions_2 PARTITION OF transactions  FOR VALUES IN (2);
-        CREATE TABLE IF NOT EXISTS transactions_3 PARTITION OF transactions  FOR VALUES IN (3);
-        CREATE TABLE IF NOT EXISTS transactions_4 PARTITION OF transactions  FOR VALUES IN (4);
-        CREATE TABLE IF NOT EXISTS transactions_5 PARTITION OF transactions  FOR VALUES IN (5);
-        CREATE TABLE IF NOT EXISTS transactions_6 PARTITION OF transactions  FOR VALUES IN (6);
-        CREATE TABLE IF NOT EXISTS transactions_7 PARTITION OF transactions  FOR VALUES IN (7);
-        CREATE TABLE IF NOT EXISTS transactions_8 PARTITION OF transactions  FOR VALUES IN (8);
-        CREATE TABLE IF NOT EXISTS transactions_9 PARTITION OF transactions  FOR VALUES IN (9);"
+        "CREATE INDEX IF NOT EXISTS transactions_processed_err_idx ON transactions (signature) WHERE processed IS NOT TRUE and err IS NOT TRUE;
+        CREATE INDEX IF NOT EXISTS transactions_program_pk_idx ON transactions USING btree (program_pk, slot DESC);"
+    ).await?;
+
+    // transactions whose program logs were truncated. Logs are truncated when the transaction
+    // executes, so fetching it again returns the same logs. Its events are recovered from the
+    // emit_cpi! inner instructions instead, and fills still missing are re-scraped by the audit
+    client
+        .batch_execute(
+            "ALTER TABLE transactions ADD COLUMN IF NOT EXISTS log_truncated bool NOT NULL DEFAULT false;",
+        )
+        .await?;
+
+    client
+        .execute(
+            &format!(
+                "ALTER TABLE transactions ADD COLUMN IF NOT EXISTS cluster text NOT NULL DEFAULT '{}'",
+                DEFAULT_CLUSTER
+            ),
+            &[],
+        )
+        .await?;
+
+    // unprocessed transactions are claimed by scraper tasks for the length of a lease
+    client.batch_execute(
+        "ALTER TABLE transactions ADD COLUMN IF NOT EXISTS claimed_by text;
+        ALTER TABLE transactions ADD COLUMN IF NOT EXISTS claim_expires_at timestamptz;
+        CREATE INDEX IF NOT EXISTS transactions_claimable_idx ON transactions (slot DESC) WHERE processed IS NOT TRUE and err IS NOT TRUE;"
+    ).await?;
+
+    // failed fetches are retried with backoff, and dead lettered after too many attempts
+    client.batch_execute(
+        "ALTER TABLE transactions ADD COLUMN IF NOT EXISTS attempts int4 NOT NULL DEFAULT 0;
+        ALTER TABLE transactions ADD COLUMN IF NOT EXISTS last_error text;
+        ALTER TABLE transactions ADD COLUMN IF NOT EXISTS next_retry_at timestamptz;
+        ALTER TABLE transactions ADD COLUMN IF NOT EXISTS dead_letter bool NOT NULL DEFAULT false;
+        CREATE INDEX IF NOT EXISTS transactions_dead_letter_idx ON transactions (slot DESC) WHERE dead_letter;"
     ).await?;
 
     Ok(())
@@ -224,5 +421,195 @@ pub async fn create_market_metadata_table(pool: &Pool) -> anyhow::Result<()> {
             &[],
         )
         .await?;
+
+    client
+        .execute(
+            "CREATE UNIQUE INDEX IF NOT EXISTS idx_market_name ON market_metadata (market_name)",
+            &[],
+        )
+        .await?;
+
+    client
+        .execute(
+            &format!(
+                "ALTER TABLE market_metadata ADD COLUMN IF NOT EXISTS cluster text NOT NULL DEFAULT '{}'",
+                DEFAULT_CLUSTER
+            ),
+            &[],
+        )
+        .await?;
+    Ok(())
+}
+
+pub async fn create_backfill_state_table(pool: &Pool) -> anyhow::Result<()> {
+    let client = pool.get().await?;
+
+    client
+        .execute(
+            "CREATE TABLE IF NOT EXISTS backfill_state (
+                address text NOT NULL,
+                before_signature text,
+                oldest_slot bigint,
+                target_slot bigint,
+                target_datetime timestamptz,
+                complete bool NOT NULL,
+                updated_at timestamptz NOT NULL DEFAULT now(),
+                CONSTRAINT backfill_state_pk PRIMARY KEY (address)
+            );",
+            &[],
+        )
+        .await?;
     Ok(())
 }
+
+pub async fn create_scraper_state_table(pool: &Pool) -> anyhow::Result<()> {
+    let client = pool.get().await?;
+
+    client
+        .execute(
+            "CREATE TABLE IF NOT EXISTS scraper_state (
+                address text NOT NULL,
+                last_signature text NOT NULL,
+                last_slot bigint NOT NULL,
+                updated_at timestamptz NOT NULL DEFAULT now(),
+                CONSTRAINT scraper_state_pk PRIMARY KEY (address)
+            );",
+            &[],
+        )
+        .await?;
+    Ok(())
+}
+
+pub async fn create_market_imports_table(pool: &Pool) -> anyhow::Result<()> {
+    let client = pool.get().await?;
+
+    client
+        .execute(
+            "CREATE TABLE IF NOT EXISTS market_imports (
+                market_pk text NOT NULL,
+                queued_at timestamptz NOT NULL DEFAULT now(),
+                attempts int4 NOT NULL DEFAULT 0,
+                last_error text,
+                claim_expires_at timestamptz,
+                CONSTRAINT market_imports_pk PRIMARY KEY (market_pk)
+            );",
+            &[],
+        )
+        .await?;
+
+    // imports are claimed by the scrapers of the cluster that queued them
+    client
+        .execute(
+            &format!(
+                "ALTER TABLE market_imports ADD COLUMN IF NOT EXISTS cluster text NOT NULL DEFAULT '{}'",
+                DEFAULT_CLUSTER
+            ),
+            &[],
+        )
+        .await?;
+    Ok(())
+}
+
+/// Raw transactions kept for reparsing, see `ArchivedTransaction`. Searchable by slot and by
+/// the accounts a transaction references, which include its markets.
+pub async fn create_transaction_archive_table(pool: &Pool) -> anyhow::Result<()> {
+    let client = pool.get().await?;
+
+    client
+        .batch_execute(
+            "CREATE TABLE IF NOT EXISTS transaction_archive (
+                signature text NOT NULL,
+                slot bigint NOT NULL,
+                block_time bigint,
+                program_pk text NOT NULL,
+                account_keys text[] NOT NULL,
+                cluster text NOT NULL,
+                payload bytea NOT NULL,
+                archived_at timestamptz NOT NULL DEFAULT now(),
+                CONSTRAINT transaction_archive_pk PRIMARY KEY (signature)
+            );
+            CREATE INDEX IF NOT EXISTS idx_transaction_archive_slot ON transaction_archive (slot, signature);
+            CREATE INDEX IF NOT EXISTS idx_transaction_archive_account_keys ON transaction_archive USING gin (account_keys);",
+        )
+        .await?;
+    Ok(())
+}
+
+/// Slot ranges read by the block scanner, see `BlockScanRange`. A range that continues the
+/// previous one is merged into it, so consecutive scanning keeps a single row.
+pub async fn create_block_scan_ranges_table(pool: &Pool) -> anyhow::Result<()> {
+    let client = pool.get().await?;
+
+    client
+        .batch_execute(
+            "CREATE TABLE IF NOT EXISTS block_scan_ranges (
+                cluster text NOT NULL,
+                start_slot bigint NOT NULL,
+                end_slot bigint NOT NULL,
+                start_datetime timestamptz,
+                end_datetime timestamptz,
+                num_blocks bigint NOT NULL,
+                num_transactions bigint NOT NULL,
+                updated_at timestamptz NOT NULL DEFAULT now(),
+                CONSTRAINT block_scan_ranges_pk PRIMARY KEY (cluster, start_slot)
+            );
+            CREATE INDEX IF NOT EXISTS idx_block_scan_ranges_end_slot ON block_scan_ranges (cluster, end_slot);",
+        )
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::empty_test_pool;
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn partitioned_transactions_are_migrated() {
+        let pool = empty_test_pool("partitioned_transactions_are_migrated").await;
+        let client = pool.get().await.unwrap();
+        client
+            .batch_execute(
+                "CREATE TABLE transactions (
+                    signature text NOT NULL,
+                    program_pk text NOT NULL,
+                    block_datetime timestamptz NOT NULL,
+                    slot bigint NOT NULL,
+                    err bool NOT NULL,
+                    processed bool NOT NULL,
+                    worker_partition int4 NOT NULL,
+                    CONSTRAINT transactions_pk PRIMARY KEY (signature, worker_partition)
+                ) PARTITION BY LIST (worker_partition);
+                CREATE TABLE transactions_3 PARTITION OF transactions FOR VALUES IN (3);
+                ALTER TABLE transactions ADD COLUMN cluster text NOT NULL DEFAULT 'mainnet';
+                INSERT INTO transactions VALUES ('sig', 'program', now(), 13, false, true, 3, 'devnet');",
+            )
+            .await
+            .unwrap();
+
+        setup_database(&pool).await.unwrap();
+
+        let row = client
+            .query_one(
+                "SELECT slot, processed, cluster, attempts FROM transactions WHERE signature = 'sig'",
+                &[],
+            )
+            .await
+            .unwrap();
+        assert_eq!(row.get::<_, i64>(0), 13);
+        assert!(row.get::<_, bool>(1));
+        assert_eq!(row.get::<_, String>(2), "devnet");
+        assert_eq!(row.get::<_, i32>(3), 0);
+        let leftovers = client
+            .query_one(
+                "SELECT count(*) FROM information_schema.columns
+                WHERE table_name = 'transactions_partitioned'
+                OR (table_name = 'transactions' AND column_name = 'worker_partition')",
+                &[],
+            )
+            .await
+            .unwrap();
+        assert_eq!(leftovers.get::<_, i64>(0), 0);
+    }
+}
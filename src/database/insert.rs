@@ -1,20 +1,32 @@
-use deadpool_postgres::Pool;
+use anchor_lang::prelude::Pubkey;
+use chrono::{DateTime, Utc};
+use deadpool_postgres::{Pool, Transaction};
 
 use crate::{
     structs::{
+        archived_transaction::ArchivedTransaction,
+        backfill::BackfillState,
+        block_scan::BlockScanRange,
         candle::Candle,
-        openbook_v2::{OpenBookFill, OpenBookMarketMetadata},
-        transaction::PgTransaction,
+        openbook_v2::{EventContext, OpenBookEvents, OpenBookFill, OpenBookMarketMetadata},
+        parse_error::ParseError,
+        scraper_state::ScraperState,
+        transaction::{PgTransaction, DEFAULT_MAX_TRANSACTION_ATTEMPTS},
     },
     utils::AnyhowWrap,
+    worker::metrics::METRIC_DEAD_LETTER_TRANSACTIONS_TOTAL,
 };
 
+#[allow(clippy::too_many_arguments)]
 pub async fn insert_atomically(
     pool: &Pool,
-    worker_id: i32,
     fills: Vec<OpenBookFill>,
     markets: Vec<OpenBookMarketMetadata>,
+    events: OpenBookEvents,
     signatures: Vec<String>,
+    truncated_signatures: Vec<String>,
+    failed_signatures: Vec<(String, ParseError)>,
+    archived: Vec<ArchivedTransaction>,
 ) -> anyhow::Result<()> {
     let mut client = pool.get().await?;
 
@@ -22,43 +34,852 @@ pub async fn insert_atomically(
 
     // 1. Insert fills
     if !fills.is_empty() {
-        let fills_statement = build_fills_upsert_statement(fills);
+        upsert_fills(&db_txn, fills).await?;
+    }
+
+    // 2. Insert other events
+    if !events.is_empty() {
+        for events_statement in build_events_insert_statements(&events) {
+            db_txn
+                .execute(&events_statement, &[])
+                .await
+                .map_err_anyhow()?;
+        }
+    }
+
+    // 3. Insert markets
+    if !markets.is_empty() {
+        insert_markets(&db_txn, markets).await?;
+    }
+
+    // 4. Update txns table as processed, a batch can fail as a whole
+    if !signatures.is_empty() {
+        let stmt = "UPDATE transactions
+            SET processed = true, claim_expires_at = NULL
+            WHERE signature = ANY($1)";
+        db_txn.execute(stmt, &[&signatures]).await?;
+    }
+
+    // 5. Flag txns with truncated logs
+    if !truncated_signatures.is_empty() {
+        let stmt = "UPDATE transactions
+            SET log_truncated = true
+            WHERE signature = ANY($1)";
+        db_txn.execute(stmt, &[&truncated_signatures]).await?;
+    }
+
+    // 6. Schedule a retry of txns that could not be fetched or parsed
+    if !failed_signatures.is_empty() {
+        record_transaction_failures(&db_txn, failed_signatures).await?;
+    }
+
+    // 7. Archive the raw txns for reparsing
+    if !archived.is_empty() {
+        insert_archived_transactions(&db_txn, archived).await?;
+    }
+
+    db_txn.commit().await?;
+
+    Ok(())
+}
+
+/// Records transactions that failed before they could be fetched, see
+/// `record_transaction_failures`.
+pub async fn record_failed_transactions(
+    pool: &Pool,
+    failed_signatures: Vec<(String, ParseError)>,
+) -> anyhow::Result<()> {
+    let mut client = pool.get().await?;
+
+    let db_txn = client.build_transaction().start().await?;
+    record_transaction_failures(&db_txn, failed_signatures).await?;
+    db_txn.commit().await?;
+
+    Ok(())
+}
+
+/// Counts a failed attempt for each transaction and schedules its next retry with exponential
+/// backoff. A transaction that failed `MAX_TRANSACTION_ATTEMPTS` times, or whose error can't be
+/// fixed by retrying, is dead lettered, and no longer claimed until it is requeued.
+async fn record_transaction_failures(
+    db_txn: &Transaction<'_>,
+    failed_signatures: Vec<(String, ParseError)>,
+) -> anyhow::Result<()> {
+    let max_attempts = match dotenv::var("MAX_TRANSACTION_ATTEMPTS") {
+        Ok(s) => s.parse::<i32>()?,
+        Err(_) => DEFAULT_MAX_TRANSACTION_ATTEMPTS,
+    };
+    let mut signatures = vec![];
+    let mut errors = vec![];
+    let mut retryable = vec![];
+    for (signature, error) in failed_signatures.into_iter() {
+        retryable.push(error.is_retryable());
+        errors.push(error.to_string());
+        signatures.push(signature);
+    }
+
+    let stmt = "UPDATE transactions t
+        SET attempts = t.attempts + 1,
+        last_error = f.error,
+        next_retry_at = now() + least(interval '10 seconds' * power(2, t.attempts), interval '1 hour'),
+        dead_letter = t.attempts + 1 >= $4 OR NOT f.retryable,
+        claim_expires_at = NULL
+        FROM unnest($1::text[], $2::text[], $3::bool[]) AS f(signature, error, retryable)
+        WHERE t.signature = f.signature
+        AND t.processed = false
+        RETURNING t.dead_letter";
+    let rows = db_txn
+        .query(stmt, &[&signatures, &errors, &retryable, &max_attempts])
+        .await?;
+    let num_dead_lettered = rows.iter().filter(|r| r.get::<usize, bool>(0)).count();
+    METRIC_DEAD_LETTER_TRANSACTIONS_TOTAL.inc_by(num_dead_lettered as u64);
+    Ok(())
+}
+
+/// Archives raw transactions, keeping the first copy of each.
+async fn insert_archived_transactions(
+    db_txn: &Transaction<'_>,
+    archived: Vec<ArchivedTransaction>,
+) -> anyhow::Result<()> {
+    let mut signatures = vec![];
+    let mut slots = vec![];
+    let mut block_times = vec![];
+    let mut program_pks = vec![];
+    let mut account_keys = vec![];
+    let mut clusters = vec![];
+    let mut payloads = vec![];
+    for a in archived.into_iter() {
+        signatures.push(a.signature);
+        slots.push(a.slot as i64);
+        block_times.push(a.block_time);
+        program_pks.push(a.program_pk);
+        // postgres arrays can't be nested, so the keys of each transaction are joined
+        account_keys.push(a.account_keys.join(","));
+        clusters.push(a.cluster);
+        payloads.push(a.payload);
+    }
+
+    let stmt = "INSERT INTO transaction_archive
+        (signature, slot, block_time, program_pk, account_keys, cluster, payload)
+        SELECT a.signature, a.slot, a.block_time, a.program_pk, string_to_array(a.account_keys, ','), a.cluster, a.payload
+        FROM unnest($1::text[], $2::int8[], $3::int8[], $4::text[], $5::text[], $6::text[], $7::bytea[])
+            AS a(signature, slot, block_time, program_pk, account_keys, cluster, payload)
+        ON CONFLICT DO NOTHING";
+    db_txn
+        .execute(
+            stmt,
+            &[
+                &signatures,
+                &slots,
+                &block_times,
+                &program_pks,
+                &account_keys,
+                &clusters,
+                &payloads,
+            ],
+        )
+        .await?;
+    Ok(())
+}
+
+/// Replaces the fills and events of reparsed transactions. Events are replaced for every
+/// transaction, fills only for the given markets. The candles of those markets from the
+/// earliest reparsed fill onwards are marked incomplete, so the worker batches them again.
+/// Returns the number of fills written.
+pub async fn replace_reparsed_events(
+    pool: &Pool,
+    fills: Vec<OpenBookFill>,
+    events: OpenBookEvents,
+    signatures: Vec<String>,
+    market_pks: Vec<String>,
+) -> anyhow::Result<u64> {
+    let mut client = pool.get().await?;
+
+    let db_txn = client.build_transaction().start().await?;
+
+    // the candles of the fills being replaced, covering fills that the reparse drops
+    let stmt = "UPDATE candles c
+        SET complete = false
+        FROM (
+            SELECT m.market_name, min(f.block_datetime) as from_time
+            FROM fills f
+            JOIN market_metadata m ON m.market_pk = f.market_pk
+            WHERE f.signature = ANY($1)
+            AND f.market_pk = ANY($2)
+            GROUP BY m.market_name
+        ) affected
+        WHERE c.market_name = affected.market_name
+        AND c.end_time > affected.from_time";
+    db_txn.execute(stmt, &[&signatures, &market_pks]).await?;
+
+    db_txn
+        .execute(
+            "DELETE FROM fills WHERE signature = ANY($1) AND market_pk = ANY($2)",
+            &[&signatures, &market_pks],
+        )
+        .await?;
+    for table in [
+        "deposits",
+        "settle_funds",
+        "sweep_fees",
+        "open_orders_positions",
+        "set_delegates",
+        "total_order_fills",
+    ] {
+        let stmt = format!("DELETE FROM {} WHERE signature = ANY($1)", table);
+        db_txn.execute(&stmt, &[&signatures]).await?;
+    }
+
+    let mut num_fills = 0;
+    if !fills.is_empty() {
+        num_fills = upsert_fills(&db_txn, fills).await?;
+    }
+
+    if !events.is_empty() {
+        for events_statement in build_events_insert_statements(&events) {
+            db_txn
+                .execute(&events_statement, &[])
+                .await
+                .map_err_anyhow()?;
+        }
+    }
+
+    // the candles of the reparsed fills, covering fills that are new or moved
+    let stmt = "UPDATE candles c
+        SET complete = false
+        FROM (
+            SELECT m.market_name, min(f.block_datetime) as from_time
+            FROM fills f
+            JOIN market_metadata m ON m.market_pk = f.market_pk
+            WHERE f.signature = ANY($1)
+            GROUP BY m.market_name
+        ) affected
+        WHERE c.market_name = affected.market_name
+        AND c.end_time > affected.from_time";
+    db_txn.execute(stmt, &[&signatures]).await?;
+
+    db_txn.commit().await?;
+
+    Ok(num_fills)
+}
+
+/// Queues transactions of the cluster to be scraped again: the given signatures, which are
+/// inserted if they are new, and any transactions already stored in the slot window. Returns the
+/// number of queued transactions.
+pub async fn requeue_transactions_for_rescrape(
+    pool: &Pool,
+    cluster: &str,
+    transactions: Vec<PgTransaction>,
+    slot_window: Option<(u64, u64)>,
+) -> anyhow::Result<u64> {
+    let mut client = pool.get().await?;
+
+    let db_txn = client.build_transaction().start().await?;
+
+    let signatures: Vec<String> = transactions.iter().map(|t| t.signature.clone()).collect();
+    if !transactions.is_empty() {
+        let insert_statement = build_transactions_insert_statement(transactions);
         db_txn
-            .execute(&fills_statement, &[])
+            .execute(&insert_statement, &[])
             .await
-            .map_err_anyhow()
-            .unwrap();
+            .map_err_anyhow()?;
     }
 
-    // 2. Insert markets
-    if !markets.is_empty() {
-        let markets_statement = build_markets_insert_statement(markets);
+    let (from_slot, to_slot) = match slot_window {
+        Some((from_slot, to_slot)) => (Some(from_slot as i64), Some(to_slot as i64)),
+        None => (None, None),
+    };
+    let stmt = "UPDATE transactions
+        SET processed = false,
+        attempts = 0,
+        dead_letter = false,
+        next_retry_at = NULL,
+        claim_expires_at = NULL
+        WHERE err = false
+        AND cluster = $4
+        AND (signature = ANY($1) OR slot BETWEEN $2 AND $3)";
+    let num_queued = db_txn
+        .execute(stmt, &[&signatures, &from_slot, &to_slot, &cluster])
+        .await?;
+
+    db_txn.commit().await?;
+
+    Ok(num_queued)
+}
+
+/// Moves dead lettered transactions back into the queue with a fresh attempt count, either the
+/// given signatures or all of them. Returns the number of requeued transactions.
+pub async fn requeue_dead_letter_transactions(
+    pool: &Pool,
+    signatures: Option<Vec<String>>,
+) -> anyhow::Result<u64> {
+    let client = pool.get().await?;
+
+    let stmt = "UPDATE transactions
+        SET dead_letter = false,
+        attempts = 0,
+        next_retry_at = NULL,
+        claim_expires_at = NULL
+        WHERE dead_letter = true
+        AND ($1::text[] IS NULL OR signature = ANY($1))";
+    let num_requeued = client.execute(stmt, &[&signatures]).await?;
+    Ok(num_requeued)
+}
+
+/// Applies the outcome of a finalization check to the fills of the cluster. Fills in finalized
+/// slots are marked as finalized. Fills in slots that the finalized chain skipped are tombstoned as orphaned, and
+/// the candles of their markets from the minute of the earliest orphaned fill onwards are marked
+/// incomplete, so the worker batches them again without those fills.
+pub async fn reconcile_fill_commitment(
+    pool: &Pool,
+    cluster: &str,
+    finalized_slots: &[u64],
+    orphaned_slots: &[u64],
+) -> anyhow::Result<(u64, u64)> {
+    let finalized_slots: Vec<i64> = finalized_slots.iter().map(|s| *s as i64).collect();
+    let orphaned_slots: Vec<i64> = orphaned_slots.iter().map(|s| *s as i64).collect();
+
+    let mut client = pool.get().await?;
+
+    let db_txn = client.build_transaction().start().await?;
+
+    let stmt = "UPDATE fills
+        SET commitment = 'finalized'
+        WHERE commitment = 'confirmed'
+        AND cluster = $2
+        AND slot = ANY($1)";
+    let num_finalized = db_txn.execute(stmt, &[&finalized_slots, &cluster]).await?;
+
+    let stmt = "UPDATE fills
+        SET commitment = 'orphaned'
+        WHERE commitment = 'confirmed'
+        AND cluster = $2
+        AND slot = ANY($1)";
+    let num_orphaned = db_txn.execute(stmt, &[&orphaned_slots, &cluster]).await?;
+
+    if num_orphaned > 0 {
+        let stmt = "UPDATE candles c
+            SET complete = false
+            FROM (
+                SELECT m.market_name, min(f.block_datetime) as from_time
+                FROM fills f
+                JOIN market_metadata m ON m.market_pk = f.market_pk
+                WHERE f.commitment = 'orphaned'
+                AND f.cluster = $2
+                AND f.slot = ANY($1)
+                GROUP BY m.market_name
+            ) affected
+            WHERE c.market_name = affected.market_name
+            AND c.cluster = $2
+            AND c.end_time > affected.from_time";
+        db_txn.execute(stmt, &[&orphaned_slots, &cluster]).await?;
+    }
+
+    db_txn.commit().await?;
+
+    Ok((num_finalized, num_orphaned))
+}
+
+/// Upserts fills from the polled path. A fill that rewrites a stored fill from another slot or
+/// transaction, as after a fork, marks the candles of its market from the earlier of the two
+/// block times onwards incomplete, so the worker batches them again with the new fill. So does
+/// a new fill older than candles that were already batched, as recovered by a re-scrape, a
+/// backfill or a block scan.
+async fn upsert_fills(db_txn: &Transaction<'_>, fills: Vec<OpenBookFill>) -> anyhow::Result<u64> {
+    let market_pks: Vec<&str> = fills.iter().map(|f| f.market_pk.as_str()).collect();
+    let seq_nums: Vec<i64> = fills.iter().map(|f| f.seq_num as i64).collect();
+    let slots: Vec<i64> = fills.iter().map(|f| f.slot as i64).collect();
+    let signatures: Vec<&str> = fills.iter().map(|f| f.signature.as_str()).collect();
+    let block_datetimes: Vec<DateTime<Utc>> = fills.iter().map(|f| f.block_datetime).collect();
+    let stmt = "UPDATE candles c
+        SET complete = false
+        FROM (
+            SELECT m.market_name,
+                min(least(coalesce(f.block_datetime, u.block_datetime), u.block_datetime)) as from_time
+            FROM unnest($1::text[], $2::int8[], $3::int8[], $4::text[], $5::timestamptz[])
+                AS u(market_pk, seq_num, slot, signature, block_datetime)
+            LEFT JOIN fills f ON f.market_pk = u.market_pk AND f.seq_num = u.seq_num
+            JOIN market_metadata m ON m.market_pk = u.market_pk
+            WHERE f.market_pk IS NULL
+            OR (f.slot, f.signature) IS DISTINCT FROM (u.slot, u.signature)
+            GROUP BY m.market_name
+        ) affected
+        WHERE c.market_name = affected.market_name
+        AND c.complete
+        AND c.end_time > affected.from_time";
+    db_txn
+        .execute(
+            stmt,
+            &[
+                &market_pks,
+                &seq_nums,
+                &slots,
+                &signatures,
+                &block_datetimes,
+            ],
+        )
+        .await?;
+
+    let fills_statement = build_fills_upsert_statement(fills);
+    let num_fills = db_txn
+        .execute(&fills_statement, &[])
+        .await
+        .map_err_anyhow()?;
+    Ok(num_fills)
+}
+
+/// Inserts new markets one at a time, since their names come from the chain. A name that is
+/// already taken by another market is suffixed with the first 8 characters of the market
+/// pubkey, so `market_name` stays unique and the same market always gets the same name.
+async fn insert_markets(
+    db_txn: &Transaction<'_>,
+    markets: Vec<OpenBookMarketMetadata>,
+) -> anyhow::Result<()> {
+    let stmt = db_txn
+        .prepare(
+            "INSERT INTO public.market_metadata
+    (creation_datetime, program_pk, market_pk, market_name, base_mint, quote_mint, base_decimals, quote_decimals, base_lot_size, quote_lot_size, scraper_active, cluster)
+    VALUES ($1, $2, $3,
+        CASE WHEN EXISTS (SELECT 1 FROM public.market_metadata WHERE market_name = $4 AND market_pk <> $3)
+            THEN $4 || ' (' || left($3, 8) || ')'
+            ELSE $4
+        END,
+        $5, $6, $7, $8, $9, $10, false, $11)
+    ON CONFLICT DO NOTHING",
+        )
+        .await?;
+    for market in markets.iter() {
         db_txn
-            .execute(&markets_statement, &[])
+            .execute(
+                &stmt,
+                &[
+                    &market.creation_datetime,
+                    &market.program_pk,
+                    &market.market_pk,
+                    &market.market_name,
+                    &market.base_mint,
+                    &market.quote_mint,
+                    &(market.base_decimals as i32),
+                    &(market.quote_decimals as i32),
+                    &market.base_lot_size,
+                    &market.quote_lot_size,
+                    &market.cluster,
+                ],
+            )
+            .await?;
+    }
+    Ok(())
+}
+
+/// Inserts markets found outside of the transaction scraper, such as imported markets.
+pub async fn insert_new_markets(
+    pool: &Pool,
+    markets: Vec<OpenBookMarketMetadata>,
+) -> anyhow::Result<()> {
+    let mut client = pool.get().await?;
+
+    let db_txn = client.build_transaction().start().await?;
+    insert_markets(&db_txn, markets).await?;
+    db_txn.commit().await?;
+
+    Ok(())
+}
+
+/// Queues markets of the cluster whose metadata is unknown to be imported, skipping markets that
+/// are already known or queued. Returns the number of newly queued markets.
+pub async fn queue_market_imports(
+    pool: &Pool,
+    cluster: &str,
+    market_pks: Vec<String>,
+) -> anyhow::Result<u64> {
+    let client = pool.get().await?;
+
+    let stmt = "INSERT INTO market_imports (market_pk, cluster)
+        SELECT queued.market_pk, $2 FROM unnest($1::text[]) AS queued(market_pk)
+        WHERE NOT EXISTS (SELECT 1 FROM market_metadata m WHERE m.market_pk = queued.market_pk)
+        ON CONFLICT DO NOTHING";
+    let num_queued = client.execute(stmt, &[&market_pks, &cluster]).await?;
+
+    Ok(num_queued)
+}
+
+/// Inserts an imported market and removes it from the import queue.
+pub async fn complete_market_import(
+    pool: &Pool,
+    market: OpenBookMarketMetadata,
+) -> anyhow::Result<()> {
+    let mut client = pool.get().await?;
+
+    let db_txn = client.build_transaction().start().await?;
+    let market_pk = market.market_pk.clone();
+    insert_markets(&db_txn, vec![market]).await?;
+    db_txn
+        .execute(
+            "DELETE FROM market_imports WHERE market_pk = $1",
+            &[&market_pk],
+        )
+        .await?;
+    db_txn.commit().await?;
+
+    Ok(())
+}
+
+/// Records a failed import. The market can be claimed again once `retry_secs` have passed.
+pub async fn record_market_import_failure(
+    pool: &Pool,
+    market_pk: &str,
+    error: &str,
+    retry_secs: i64,
+) -> anyhow::Result<()> {
+    let client = pool.get().await?;
+
+    let stmt = "UPDATE market_imports
+        SET attempts = attempts + 1,
+        last_error = $2,
+        claim_expires_at = now() + make_interval(secs => $3)
+        WHERE market_pk = $1";
+    client
+        .execute(stmt, &[&market_pk, &error, &(retry_secs as f64)])
+        .await?;
+
+    Ok(())
+}
+
+/// Queues markets of the cluster for import again with a fresh attempt count, either the given
+/// markets or all of them. Returns the number of requeued markets.
+pub async fn retry_market_imports(
+    pool: &Pool,
+    cluster: &str,
+    market_pks: Option<Vec<String>>,
+) -> anyhow::Result<u64> {
+    let client = pool.get().await?;
+
+    let stmt = "UPDATE market_imports
+        SET attempts = 0,
+        claim_expires_at = NULL
+        WHERE cluster = $1
+        AND ($2::text[] IS NULL OR market_pk = ANY($2))";
+    let num_retried = client.execute(stmt, &[&cluster, &market_pks]).await?;
+    Ok(num_retried)
+}
+
+/// Removes markets of the cluster from the import queue. Transactions with fills of these
+/// markets keep failing until they are imported with `admin import`. Returns the number of
+/// removed markets.
+pub async fn drop_market_imports(
+    pool: &Pool,
+    cluster: &str,
+    market_pks: Vec<String>,
+) -> anyhow::Result<u64> {
+    let client = pool.get().await?;
+
+    let stmt = "DELETE FROM market_imports WHERE cluster = $1 AND market_pk = ANY($2)";
+    let num_dropped = client.execute(stmt, &[&cluster, &market_pks]).await?;
+    Ok(num_dropped)
+}
+
+/// Turns scraping and candle batching of a market on or off. Returns the number of markets
+/// updated.
+pub async fn set_market_active(pool: &Pool, market_pk: &str, active: bool) -> anyhow::Result<u64> {
+    let client = pool.get().await?;
+
+    let stmt = "UPDATE market_metadata SET scraper_active = $1 WHERE market_pk = $2";
+    let num_markets = client.execute(stmt, &[&active, &market_pk]).await?;
+
+    Ok(num_markets)
+}
+
+/// Renames a market and moves its candles to the new name in one transaction, so the candle
+/// and trader endpoints keep serving its history under the new name.
+pub async fn rename_market(pool: &Pool, market_pk: &str, new_name: &str) -> anyhow::Result<()> {
+    let mut client = pool.get().await?;
+
+    let db_txn = client.build_transaction().start().await?;
+
+    let old_name: String = match db_txn
+        .query_opt(
+            "SELECT market_name FROM market_metadata WHERE market_pk = $1 FOR UPDATE",
+            &[&market_pk],
+        )
+        .await?
+    {
+        Some(row) => row.get(0),
+        None => return Err(anyhow::anyhow!("market {} not found", market_pk)),
+    };
+    if old_name == new_name {
+        return Ok(());
+    }
+
+    let name_taken = db_txn
+        .query_one(
+            "SELECT EXISTS (SELECT 1 FROM market_metadata WHERE market_name = $1)
+            OR EXISTS (SELECT 1 FROM candles WHERE market_name = $1)",
+            &[&new_name],
+        )
+        .await?
+        .get::<usize, bool>(0);
+    if name_taken {
+        return Err(anyhow::anyhow!(
+            "market name {} is already in use",
+            new_name
+        ));
+    }
+
+    db_txn
+        .execute(
+            "UPDATE market_metadata SET market_name = $1 WHERE market_pk = $2",
+            &[&new_name, &market_pk],
+        )
+        .await?;
+    db_txn
+        .execute(
+            "UPDATE candles SET market_name = $1 WHERE market_name = $2",
+            &[&new_name, &old_name],
+        )
+        .await?;
+
+    db_txn.commit().await?;
+
+    Ok(())
+}
+
+/// Writes newly polled signatures and moves the checkpoint to the newest of them in one
+/// database transaction, so a restarted scraper continues exactly where it stopped.
+pub async fn insert_signatures_with_checkpoint(
+    pool: &Pool,
+    transactions: Vec<PgTransaction>,
+    state: &ScraperState,
+) -> anyhow::Result<u64> {
+    let mut client = pool.get().await?;
+
+    let db_txn = client.build_transaction().start().await?;
+
+    let mut num_txns = 0;
+    if !transactions.is_empty() {
+        let upsert_statement = build_transactions_insert_statement(transactions);
+        num_txns = db_txn
+            .execute(&upsert_statement, &[])
             .await
-            .map_err_anyhow()
-            .unwrap();
+            .map_err_anyhow()?;
     }
 
-    // 3. Update txns table as processed
-    let transactions_statement =
-        build_transactions_processed_update_statement(worker_id, signatures);
+    let stmt = "INSERT INTO scraper_state (address, last_signature, last_slot, updated_at)
+        VALUES ($1, $2, $3, now())
+        ON CONFLICT (address) DO UPDATE SET
+        last_signature=excluded.last_signature,
+        last_slot=excluded.last_slot,
+        updated_at=excluded.updated_at";
     db_txn
-        .execute(&transactions_statement, &[])
+        .execute(
+            stmt,
+            &[
+                &state.address,
+                &state.last_signature,
+                &(state.last_slot as i64),
+            ],
+        )
+        .await?;
+
+    db_txn.commit().await?;
+
+    Ok(num_txns)
+}
+
+/// Writes a page of backfilled signatures together with the advanced cursor, so that a
+/// crash between pages never loses or skips signatures.
+pub async fn insert_backfill_page(
+    pool: &Pool,
+    transactions: Vec<PgTransaction>,
+    state: &BackfillState,
+) -> anyhow::Result<u64> {
+    let mut client = pool.get().await?;
+
+    let db_txn = client.build_transaction().start().await?;
+
+    let mut num_txns = 0;
+    if !transactions.is_empty() {
+        let upsert_statement = build_transactions_insert_statement(transactions);
+        num_txns = db_txn
+            .execute(&upsert_statement, &[])
+            .await
+            .map_err_anyhow()?;
+    }
+
+    upsert_backfill_state(&db_txn, state).await?;
+
+    db_txn.commit().await?;
+
+    Ok(num_txns)
+}
+
+/// Writes the program transactions found in a range of blocks. The transactions are claimed
+/// for the scanner, which parses them right away, and are left to the transaction scrapers if
+/// it does not get to them before the lease expires.
+pub async fn insert_block_scan_transactions(
+    pool: &Pool,
+    transactions: Vec<PgTransaction>,
+    claimant: &str,
+    lease_secs: i64,
+) -> anyhow::Result<u64> {
+    if transactions.is_empty() {
+        return Ok(0);
+    }
+    let mut client = pool.get().await?;
+
+    let db_txn = client.build_transaction().start().await?;
+
+    let signatures: Vec<String> = transactions.iter().map(|t| t.signature.clone()).collect();
+    let upsert_statement = build_transactions_insert_statement(transactions);
+    let num_txns = db_txn
+        .execute(&upsert_statement, &[])
         .await
-        .map_err_anyhow()
-        .unwrap();
+        .map_err_anyhow()?;
+
+    // a range retried by the scanner still holds its claim
+    let claim_stmt = "UPDATE transactions
+        SET claimed_by = $2,
+        claim_expires_at = now() + make_interval(secs => $3)
+        WHERE signature = ANY($1)
+        AND processed = false
+        AND (claim_expires_at IS NULL OR claim_expires_at < now() OR claimed_by = $2)";
+    db_txn
+        .execute(claim_stmt, &[&signatures, &claimant, &(lease_secs as f64)])
+        .await?;
 
     db_txn.commit().await?;
 
+    Ok(num_txns)
+}
+
+/// Records a range of blocks as scanned. Called once the transactions of the range are stored
+/// and ingested, so that a recorded range never misses any.
+pub async fn insert_block_scan_range(pool: &Pool, range: &BlockScanRange) -> anyhow::Result<()> {
+    let client = pool.get().await?;
+
+    // extend the range ending right before this one, or start a new one
+    let stmt = "WITH extended AS (
+            UPDATE block_scan_ranges
+            SET end_slot = $3,
+            end_datetime = COALESCE($5, end_datetime),
+            start_datetime = COALESCE(start_datetime, $4),
+            num_blocks = num_blocks + $6,
+            num_transactions = num_transactions + $7,
+            updated_at = now()
+            WHERE cluster = $1 AND end_slot = $2::int8 - 1
+            RETURNING start_slot
+        )
+        INSERT INTO block_scan_ranges
+        (cluster, start_slot, end_slot, start_datetime, end_datetime, num_blocks, num_transactions)
+        SELECT $1, $2, $3, $4, $5, $6, $7
+        WHERE NOT EXISTS (SELECT 1 FROM extended)
+        ON CONFLICT (cluster, start_slot) DO UPDATE SET
+        end_slot = GREATEST(block_scan_ranges.end_slot, excluded.end_slot),
+        start_datetime = LEAST(block_scan_ranges.start_datetime, excluded.start_datetime),
+        end_datetime = GREATEST(block_scan_ranges.end_datetime, excluded.end_datetime),
+        num_blocks = CASE WHEN excluded.end_slot >= block_scan_ranges.end_slot
+            THEN excluded.num_blocks ELSE block_scan_ranges.num_blocks END,
+        num_transactions = CASE WHEN excluded.end_slot >= block_scan_ranges.end_slot
+            THEN excluded.num_transactions ELSE block_scan_ranges.num_transactions END,
+        updated_at = now()";
+    client
+        .execute(
+            stmt,
+            &[
+                &range.cluster,
+                &(range.start_slot as i64),
+                &(range.end_slot as i64),
+                &range.start_datetime,
+                &range.end_datetime,
+                &range.num_blocks,
+                &range.num_transactions,
+            ],
+        )
+        .await?;
+
+    Ok(())
+}
+
+async fn upsert_backfill_state(
+    client: &Transaction<'_>,
+    state: &BackfillState,
+) -> anyhow::Result<()> {
+    let stmt = "INSERT INTO backfill_state
+        (address, before_signature, oldest_slot, target_slot, target_datetime, complete, updated_at)
+        VALUES ($1, $2, $3, $4, $5, $6, now())
+        ON CONFLICT (address) DO UPDATE SET
+        before_signature=excluded.before_signature,
+        oldest_slot=excluded.oldest_slot,
+        target_slot=excluded.target_slot,
+        target_datetime=excluded.target_datetime,
+        complete=excluded.complete,
+        updated_at=excluded.updated_at";
+
+    client
+        .execute(
+            stmt,
+            &[
+                &state.address,
+                &state.before_signature,
+                &state.oldest_slot.map(|s| s as i64),
+                &state.target_slot.map(|s| s as i64),
+                &state.target_datetime,
+                &state.complete,
+            ],
+        )
+        .await?;
     Ok(())
 }
 
+pub async fn insert_streamed_fills(pool: &Pool, fills: Vec<OpenBookFill>) -> anyhow::Result<u64> {
+    let client = pool.get().await?;
+    let insert_statement = build_fills_insert_statement(fills);
+    let num_fills = client
+        .execute(&insert_statement, &[])
+        .await
+        .map_err_anyhow()?;
+    Ok(num_fills)
+}
+
+/// Fills from the polled path are authoritative, so they overwrite every column of a stored
+/// fill, whether it was first written by the websocket stream or by a transaction that a fork
+/// dropped. A rewritten fill is confirmed again until its slot is finalized. Use `upsert_fills`,
+/// which also marks the candles of rewritten fills incomplete.
 pub fn build_fills_upsert_statement(fills: Vec<OpenBookFill>) -> String {
-    let mut stmt = String::from("INSERT INTO fills (block_datetime, slot, market_pk, seq_num, maker, maker_client_order_id, maker_fee, maker_datetime, taker, taker_client_order_id, taker_fee, taker_side, maker_slot, maker_out, price, quantity) VALUES");
+    let handle_conflict = "ON CONFLICT (market_pk, seq_num)
+    DO UPDATE SET
+    block_datetime=excluded.block_datetime,
+    slot=excluded.slot,
+    maker=excluded.maker,
+    maker_client_order_id=excluded.maker_client_order_id,
+    maker_fee=excluded.maker_fee,
+    maker_datetime=excluded.maker_datetime,
+    taker=excluded.taker,
+    taker_client_order_id=excluded.taker_client_order_id,
+    taker_fee=excluded.taker_fee,
+    taker_side=excluded.taker_side,
+    maker_slot=excluded.maker_slot,
+    maker_out=excluded.maker_out,
+    price=excluded.price,
+    quantity=excluded.quantity,
+    program_pk=excluded.program_pk,
+    cluster=excluded.cluster,
+    commitment=excluded.commitment,
+    signature=excluded.signature,
+    log_index=excluded.log_index
+    ";
+    build_fills_statement(fills, handle_conflict)
+}
+
+/// Fills from the websocket stream never overwrite an existing fill.
+pub fn build_fills_insert_statement(fills: Vec<OpenBookFill>) -> String {
+    build_fills_statement(fills, "ON CONFLICT DO NOTHING")
+}
+
+fn build_fills_statement(fills: Vec<OpenBookFill>, handle_conflict: &str) -> String {
+    let mut stmt = String::from("INSERT INTO fills (block_datetime, slot, market_pk, seq_num, maker, maker_client_order_id, maker_fee, maker_datetime, taker, taker_client_order_id, taker_fee, taker_side, maker_slot, maker_out, price, quantity, program_pk, cluster, signature, log_index) VALUES");
     for (idx, fill) in fills.iter().enumerate() {
         let val_str = format!(
-            "(\'{}\', {}, \'{}\', {}, \'{}\', \'{}\', {}, \'{}\', \'{}\', \'{}\', {}, {}, {}, {}, {}, {})",
+            "(\'{}\', {}, \'{}\', {}, \'{}\', \'{}\', {}, \'{}\', \'{}\', \'{}\', {}, {}, {}, {}, {}, {}, \'{}\', \'{}\', \'{}\', {})",
             fill.block_datetime.to_rfc3339(),
             fill.slot,
             fill.market_pk,
@@ -74,7 +895,11 @@ pub fn build_fills_upsert_statement(fills: Vec<OpenBookFill>) -> String {
             fill.maker_slot,
             fill.maker_out,
             fill.price,
-            fill.quantity
+            fill.quantity,
+            fill.program_pk,
+            fill.cluster,
+            fill.signature,
+            fill.log_index
         );
 
         if idx == 0 {
@@ -84,27 +909,113 @@ pub fn build_fills_upsert_statement(fills: Vec<OpenBookFill>) -> String {
         }
     }
 
-    let handle_conflict = "ON CONFLICT DO NOTHING";
-
     stmt = format!("{} {}", stmt, handle_conflict);
     stmt
 }
 
-pub fn build_candles_upsert_statement(candles: &Vec<Candle>) -> String {
-    let mut stmt = String::from("INSERT INTO candles (market_name, start_time, end_time, resolution, open, close, high, low, volume, complete) VALUES");
-    for (idx, candle) in candles.iter().enumerate() {
+/// Returns one insert statement per non-empty event table.
+pub fn build_events_insert_statements(events: &OpenBookEvents) -> Vec<String> {
+    let mut statements = vec![];
+    if !events.deposits.is_empty() {
+        statements.push(build_event_insert_statement(
+            "deposits (signature, log_index, block_datetime, slot, open_orders_account, signer, base_amount, quote_amount)",
+            &events.deposits,
+            |e| {
+                format!(
+                    "\'{}\', \'{}\', {}, {}",
+                    e.open_orders_account, e.signer, e.base_amount, e.quote_amount
+                )
+            },
+        ));
+    }
+    if !events.settle_funds.is_empty() {
+        statements.push(build_event_insert_statement(
+            "settle_funds (signature, log_index, block_datetime, slot, open_orders_account, base_native, quote_native, referrer_rebate, referrer)",
+            &events.settle_funds,
+            |e| {
+                format!(
+                    "\'{}\', {}, {}, {}, {}",
+                    e.open_orders_account,
+                    e.base_native,
+                    e.quote_native,
+                    e.referrer_rebate,
+                    optional_pubkey_value(&e.referrer)
+                )
+            },
+        ));
+    }
+    if !events.sweep_fees.is_empty() {
+        statements.push(build_event_insert_statement(
+            "sweep_fees (signature, log_index, block_datetime, slot, market_pk, amount, receiver)",
+            &events.sweep_fees,
+            |e| format!("\'{}\', {}, \'{}\'", e.market, e.amount, e.receiver),
+        ));
+    }
+    if !events.open_orders_positions.is_empty() {
+        statements.push(build_event_insert_statement(
+            "open_orders_positions (signature, log_index, block_datetime, slot, owner, open_orders_account_num, market_pk, bids_base_lots, bids_quote_lots, asks_base_lots, base_free_native, quote_free_native, locked_maker_fees, referrer_rebates_available, maker_volume, taker_volume)",
+            &events.open_orders_positions,
+            |e| {
+                format!(
+                    "\'{}\', {}, \'{}\', {}, {}, {}, {}, {}, {}, {}, {}, {}",
+                    e.owner,
+                    e.open_orders_account_num,
+                    e.market,
+                    e.bids_base_lots,
+                    e.bids_quote_lots,
+                    e.asks_base_lots,
+                    e.base_free_native,
+                    e.quote_free_native,
+                    e.locked_maker_fees,
+                    e.referrer_rebates_available,
+                    e.maker_volume,
+                    e.taker_volume
+                )
+            },
+        ));
+    }
+    if !events.set_delegates.is_empty() {
+        statements.push(build_event_insert_statement(
+            "set_delegates (signature, log_index, block_datetime, slot, open_orders_account, delegate)",
+            &events.set_delegates,
+            |e| {
+                format!(
+                    "\'{}\', {}",
+                    e.open_orders_account,
+                    optional_pubkey_value(&e.delegate)
+                )
+            },
+        ));
+    }
+    if !events.total_order_fills.is_empty() {
+        statements.push(build_event_insert_statement(
+            "total_order_fills (signature, log_index, block_datetime, slot, side, taker, total_quantity_paid, total_quantity_received, fees)",
+            &events.total_order_fills,
+            |e| {
+                format!(
+                    "{}, \'{}\', {}, {}, {}",
+                    e.side, e.taker, e.total_quantity_paid, e.total_quantity_received, e.fees
+                )
+            },
+        ));
+    }
+    statements
+}
+
+fn build_event_insert_statement<T>(
+    table_and_columns: &str,
+    events: &[(EventContext, T)],
+    event_values: impl Fn(&T) -> String,
+) -> String {
+    let mut stmt = format!("INSERT INTO {} VALUES", table_and_columns);
+    for (idx, (ctx, event)) in events.iter().enumerate() {
         let val_str = format!(
-            "(\'{}\', \'{}\', \'{}\', \'{}\', {}, {}, {}, {}, {}, {})",
-            candle.market_name,
-            candle.start_time.to_rfc3339(),
-            candle.end_time.to_rfc3339(),
-            candle.resolution,
-            candle.open,
-            candle.close,
-            candle.high,
-            candle.low,
-            candle.volume,
-            candle.complete,
+            "(\'{}\', {}, \'{}\', {}, {})",
+            ctx.signature,
+            ctx.log_index,
+            ctx.block_datetime.to_rfc3339(),
+            ctx.slot,
+            event_values(event)
         );
 
         if idx == 0 {
@@ -114,32 +1025,98 @@ pub fn build_candles_upsert_statement(candles: &Vec<Candle>) -> String {
         }
     }
 
-    let handle_conflict = "ON CONFLICT (market_name, start_time, resolution) 
-    DO UPDATE SET 
-    open=excluded.open, 
-    close=excluded.close, 
-    high=excluded.high, 
-    low=excluded.low,
-    volume=excluded.volume,
-    complete=excluded.complete
-    ";
+    let handle_conflict = "ON CONFLICT DO NOTHING";
 
     stmt = format!("{} {}", stmt, handle_conflict);
     stmt
 }
 
+fn optional_pubkey_value(pubkey: &Option<Pubkey>) -> String {
+    match pubkey {
+        Some(p) => format!("\'{}\'", p),
+        None => "NULL".to_string(),
+    }
+}
+
+/// Upserts candles with their columns bound as arrays. Market names come from the on-chain
+/// market creations, so they can't be formatted into the statement.
+pub async fn upsert_candles(pool: &Pool, candles: &[Candle]) -> anyhow::Result<u64> {
+    if candles.is_empty() {
+        return Ok(0);
+    }
+    let mut market_names = vec![];
+    let mut start_times = vec![];
+    let mut end_times = vec![];
+    let mut resolutions = vec![];
+    let mut opens = vec![];
+    let mut closes = vec![];
+    let mut highs = vec![];
+    let mut lows = vec![];
+    let mut volumes = vec![];
+    let mut completes = vec![];
+    let mut program_pks = vec![];
+    let mut clusters = vec![];
+    for candle in candles.iter() {
+        market_names.push(candle.market_name.as_str());
+        start_times.push(candle.start_time);
+        end_times.push(candle.end_time);
+        resolutions.push(candle.resolution.as_str());
+        opens.push(candle.open);
+        closes.push(candle.close);
+        highs.push(candle.high);
+        lows.push(candle.low);
+        volumes.push(candle.volume);
+        completes.push(candle.complete);
+        program_pks.push(candle.program_pk.as_str());
+        clusters.push(candle.cluster.as_str());
+    }
+
+    let client = pool.get().await?;
+    let stmt = "INSERT INTO candles
+        (market_name, start_time, end_time, resolution, open, close, high, low, volume, complete, program_pk, cluster)
+        SELECT * FROM unnest($1::text[], $2::timestamptz[], $3::timestamptz[], $4::text[], $5::float8[], $6::float8[], $7::float8[], $8::float8[], $9::float8[], $10::bool[], $11::text[], $12::text[])
+        ON CONFLICT (market_name, start_time, resolution)
+        DO UPDATE SET
+        open=excluded.open,
+        close=excluded.close,
+        high=excluded.high,
+        low=excluded.low,
+        volume=excluded.volume,
+        complete=excluded.complete";
+    let num_candles = client
+        .execute(
+            stmt,
+            &[
+                &market_names,
+                &start_times,
+                &end_times,
+                &resolutions,
+                &opens,
+                &closes,
+                &highs,
+                &lows,
+                &volumes,
+                &completes,
+                &program_pks,
+                &clusters,
+            ],
+        )
+        .await?;
+    Ok(num_candles)
+}
+
 pub fn build_transactions_insert_statement(transactions: Vec<PgTransaction>) -> String {
-    let mut stmt = String::from("INSERT INTO transactions (signature, program_pk, block_datetime, slot, err, processed, worker_partition) VALUES");
+    let mut stmt = String::from("INSERT INTO transactions (signature, program_pk, block_datetime, slot, err, processed, cluster) VALUES");
     for (idx, txn) in transactions.iter().enumerate() {
         let val_str = format!(
-            "(\'{}\', \'{}\', \'{}\', \'{}\', {}, {}, {})",
+            "(\'{}\', \'{}\', \'{}\', \'{}\', {}, {}, \'{}\')",
             txn.signature,
             txn.program_pk,
             txn.block_datetime.to_rfc3339(),
             txn.slot,
             txn.err,
             txn.processed,
-            txn.worker_partition,
+            txn.cluster,
         );
 
         if idx == 0 {
@@ -155,57 +1132,163 @@ pub fn build_transactions_insert_statement(transactions: Vec<PgTransaction>) ->
     stmt
 }
 
-fn build_markets_insert_statement(markets: Vec<OpenBookMarketMetadata>) -> String {
-    let mut stmt = String::from("INSERT INTO public.market_metadata
-    (creation_datetime, program_pk, market_pk, market_name, base_mint, quote_mint, base_decimals, quote_decimals, base_lot_size, quote_lot_size, scraper_active)
-    VALUES");
-    for (idx, market) in markets.iter().enumerate() {
-        let val_str = format!(
-            "(\'{}\', \'{}\', \'{}\', \'{}\', \'{}\', \'{}\', {}, {}, {}, {}, {})",
-            market.creation_datetime.to_rfc3339(),
-            market.program_pk,
-            market.market_pk,
-            market.market_name,
-            market.base_mint,
-            market.quote_mint,
-            market.base_decimals,
-            market.quote_decimals,
-            market.base_lot_size,
-            market.quote_lot_size,
-            false
-        );
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::fetch::{claim_market_imports, fetch_candles_from, fetch_market_imports},
+        structs::resolution::Resolution,
+        test_utils::{market, openbook_fill, test_pool},
+        utils::{to_timestampz, PROGRAM_CONFIG},
+    };
+    use chrono::{Duration, DurationRound};
 
-        if idx == 0 {
-            stmt = format!("{} {}", &stmt, val_str);
-        } else {
-            stmt = format!("{}, {}", &stmt, val_str);
-        }
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn candles_of_markets_with_quoted_names_are_upserted() {
+        let pool = test_pool("candles_of_markets_with_quoted_names_are_upserted").await;
+        let market_name = "BONK'); DROP TABLE candles; --";
+        let start_time = Utc::now().duration_trunc(Duration::minutes(1)).unwrap();
+        let mut candle = Candle {
+            market_name: market_name.to_string(),
+            start_time,
+            end_time: start_time + Duration::minutes(1),
+            resolution: Resolution::R1m.to_string(),
+            open: 1.0,
+            close: 2.0,
+            high: 2.0,
+            low: 1.0,
+            volume: 10.0,
+            complete: false,
+            program_pk: PROGRAM_CONFIG.program_ids[0].to_string(),
+            cluster: PROGRAM_CONFIG.cluster.clone(),
+        };
+        assert_eq!(upsert_candles(&pool, &[candle.clone()]).await.unwrap(), 1);
+
+        candle.close = 3.0;
+        candle.complete = true;
+        assert_eq!(upsert_candles(&pool, &[candle]).await.unwrap(), 1);
+
+        let candles = fetch_candles_from(
+            &pool,
+            market_name,
+            Resolution::R1m,
+            start_time,
+            start_time + Duration::minutes(1),
+        )
+        .await
+        .unwrap();
+        assert_eq!(candles.len(), 1);
+        assert_eq!(candles[0].close, 3.0);
+        assert!(candles[0].complete);
     }
-    let handle_conflict = "ON CONFLICT DO NOTHING";
-    stmt = format!("{} {}", stmt, handle_conflict);
-    stmt
-}
 
-pub fn build_transactions_processed_update_statement(
-    worker_id: i32,
-    processed_signatures: Vec<String>,
-) -> String {
-    let mut stmt = String::from(
-        "UPDATE transactions
-    SET processed = true
-    WHERE transactions.signature IN (",
-    );
-    for (idx, sig) in processed_signatures.iter().enumerate() {
-        let val_str = if idx == processed_signatures.len() - 1 {
-            format!("\'{}\'", sig,)
-        } else {
-            format!("\'{}\',", sig,)
-        };
-        stmt = format!("{} {}", &stmt, val_str);
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn new_fills_older_than_batched_candles_mark_them_incomplete() {
+        let pool = test_pool("new_fills_older_than_batched_candles_mark_them_incomplete").await;
+        let market = market();
+        insert_new_markets(&pool, vec![market.clone()])
+            .await
+            .unwrap();
+        let start_time = to_timestampz(1_700_000_040);
+        let candles: Vec<Candle> = (0..2)
+            .map(|minute| Candle {
+                start_time: start_time + Duration::minutes(minute),
+                end_time: start_time + Duration::minutes(minute + 1),
+                complete: true,
+                ..Candle::create_empty_candle(
+                    market.market_name.clone(),
+                    market.program_pk.clone(),
+                    market.cluster.clone(),
+                    Resolution::R1m,
+                )
+            })
+            .collect();
+        upsert_candles(&pool, &candles).await.unwrap();
+
+        // a fill of the second minute recovered after both minutes were batched
+        let fill = openbook_fill(&market, 1, 100, 1_700_000_130);
+        insert_atomically(
+            &pool,
+            vec![fill],
+            vec![],
+            OpenBookEvents::default(),
+            vec![],
+            vec![],
+            vec![],
+            vec![],
+        )
+        .await
+        .unwrap();
+
+        let candles = fetch_candles_from(
+            &pool,
+            &market.market_name,
+            Resolution::R1m,
+            start_time,
+            start_time + Duration::minutes(2),
+        )
+        .await
+        .unwrap();
+        let complete: Vec<bool> = candles.iter().map(|c| c.complete).collect();
+        assert_eq!(complete, vec![true, false]);
     }
 
-    let worker_stmt = format!(") AND worker_partition = {} ", worker_id);
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn stuck_market_imports_are_listed_retried_and_dropped() {
+        let pool = test_pool("stuck_market_imports_are_listed_retried_and_dropped").await;
+        let cluster = &PROGRAM_CONFIG.cluster;
+        let stuck = market().market_pk;
+        let queued = market().market_pk;
+        queue_market_imports(&pool, cluster, vec![stuck.clone(), queued.clone()])
+            .await
+            .unwrap();
+        for _ in 0..2 {
+            record_market_import_failure(&pool, &stuck, "market creation not found", 0)
+                .await
+                .unwrap();
+        }
 
-    stmt = format!("{} {}", stmt, worker_stmt);
-    stmt
+        let imports = fetch_market_imports(&pool, cluster, 2).await.unwrap();
+        let listed: Vec<(&str, i32, bool)> = imports
+            .iter()
+            .map(|i| (i.market_pk.as_str(), i.attempts, i.stuck))
+            .collect();
+        assert_eq!(
+            listed,
+            vec![(stuck.as_str(), 2, true), (queued.as_str(), 0, false)]
+        );
+        assert_eq!(
+            claim_market_imports(&pool, cluster, 10, 60, 2)
+                .await
+                .unwrap(),
+            vec![queued.clone()]
+        );
+
+        assert_eq!(
+            retry_market_imports(&pool, cluster, Some(vec![stuck.clone()]))
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            claim_market_imports(&pool, cluster, 10, 60, 2)
+                .await
+                .unwrap(),
+            vec![stuck.clone()]
+        );
+
+        assert_eq!(
+            drop_market_imports(&pool, cluster, vec![stuck, queued])
+                .await
+                .unwrap(),
+            2
+        );
+        assert!(fetch_market_imports(&pool, cluster, 2)
+            .await
+            .unwrap()
+            .is_empty());
+    }
 }
@@ -1,5 +1,10 @@
+// the solana client reports every rpc failure with its large `ClientError`
+#![allow(clippy::result_large_err)]
+
 pub mod database;
 pub mod scraper;
 pub mod structs;
+#[cfg(test)]
+mod test_utils;
 pub mod utils;
 pub mod worker;
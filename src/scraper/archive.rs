@@ -0,0 +1,134 @@
+use deadpool_postgres::Pool;
+use log::{info, warn};
+use solana_client::client_error::Result as ClientResult;
+use solana_transaction_status::EncodedConfirmedTransactionWithStatusMeta;
+use std::collections::HashMap;
+
+use crate::{
+    database::{
+        fetch::{fetch_all_markets, fetch_archived_transactions},
+        insert::replace_reparsed_events,
+    },
+    structs::{
+        archived_transaction::{ArchivedTransaction, ReparseSummary},
+        openbook_v2::OpenBookMarketMetadata,
+    },
+};
+
+use super::parsing::{parse_openbook_txns, transaction_account_keys, transaction_program_id};
+
+const REPARSE_PAGE_SIZE: i64 = 500;
+
+/// Reads `ARCHIVE_TRANSACTIONS`, false by default.
+pub fn archive_enabled() -> anyhow::Result<bool> {
+    match dotenv::var("ARCHIVE_TRANSACTIONS") {
+        Ok(s) => Ok(s.parse::<bool>()?),
+        Err(_) => Ok(false),
+    }
+}
+
+/// Compresses the fetched transactions among `signatures` for the archive. Transactions that
+/// fail to compress are left out of the archive rather than holding up ingestion.
+pub fn archive_fetched_transactions(
+    txns: &[ClientResult<EncodedConfirmedTransactionWithStatusMeta>],
+    sig_strings: &[String],
+    signatures: &[String],
+) -> Vec<ArchivedTransaction> {
+    txns.iter()
+        .zip(sig_strings.iter())
+        .filter(|(_, sig)| signatures.contains(sig))
+        .filter_map(|(txn, sig)| {
+            let txn = txn.as_ref().ok()?;
+            let archived = ArchivedTransaction::from_transaction(
+                sig,
+                &transaction_program_id(&txn.transaction),
+                transaction_account_keys(&txn.transaction),
+                txn,
+            );
+            match archived {
+                Ok(a) => Some(a),
+                Err(e) => {
+                    warn!("failed to archive transaction {}: {:?}", sig, e);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Rebuilds fills and events from the archive, without any RPC calls. With a market, only the
+/// archived transactions referencing it are reparsed and only its fills are replaced. Without
+/// one, every archived transaction in the slot range is reparsed for all known markets.
+pub async fn reparse_archived_transactions(
+    pool: &Pool,
+    market: Option<&OpenBookMarketMetadata>,
+    from_slot: u64,
+    to_slot: u64,
+) -> anyhow::Result<ReparseSummary> {
+    let target_markets: HashMap<String, OpenBookMarketMetadata> = match market {
+        Some(m) => HashMap::from([(m.market_pk.clone(), m.clone())]),
+        None => fetch_all_markets(pool)
+            .await?
+            .into_iter()
+            .map(|m| (m.market_pk.clone(), m))
+            .collect(),
+    };
+    let market_pks: Vec<String> = target_markets.keys().cloned().collect();
+    let account = market.map(|m| m.market_pk.as_str());
+
+    let mut summary = ReparseSummary::default();
+    let mut after = None;
+    loop {
+        let archived = fetch_archived_transactions(
+            pool,
+            account,
+            from_slot,
+            to_slot,
+            after,
+            REPARSE_PAGE_SIZE,
+        )
+        .await?;
+        let last = match archived.last() {
+            Some(a) => (a.slot, a.signature.clone()),
+            None => break,
+        };
+
+        let mut sig_strings = vec![];
+        let mut txns = vec![];
+        for a in archived.iter() {
+            match a.transaction() {
+                Ok(txn) => {
+                    sig_strings.push(a.signature.clone());
+                    txns.push(Ok(txn));
+                }
+                Err(e) => summary
+                    .failed
+                    .push((a.signature.clone(), format!("corrupt archive entry: {}", e))),
+            }
+        }
+        let parsed = parse_openbook_txns(&mut txns, sig_strings, &target_markets, false);
+
+        summary.transactions += parsed.completed_sigs.len() as u64;
+        summary.failed.extend(
+            parsed
+                .failed_sigs
+                .into_iter()
+                .map(|(sig, e)| (sig, e.to_string())),
+        );
+        summary.fills += replace_reparsed_events(
+            pool,
+            parsed.fills,
+            parsed.events,
+            parsed.completed_sigs,
+            market_pks.clone(),
+        )
+        .await?;
+        info!(
+            "Reparsed archived transactions up to slot {}, {} fills so far",
+            last.0, summary.fills
+        );
+        after = Some(last);
+    }
+
+    Ok(summary)
+}
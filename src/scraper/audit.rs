@@ -0,0 +1,236 @@
+use chrono::{Duration, Utc};
+use deadpool_postgres::Pool;
+use log::{info, warn};
+use solana_client::rpc_client::GetConfirmedSignaturesForAddress2Config;
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Signature};
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration as WaitDuration,
+};
+
+use crate::{
+    database::{
+        fetch::{fetch_fill_gaps, fetch_fill_sequence},
+        insert::requeue_transactions_for_rescrape,
+    },
+    structs::{fill_gap::FillGap, transaction::PgTransaction},
+    utils::{market_registry::MarketRegistry, PROGRAM_CONFIG},
+    worker::metrics::{METRIC_FILL_COMPLETENESS, METRIC_MISSING_FILLS, METRIC_RPC_ERRORS_TOTAL},
+};
+
+use super::source::{fill_missing_signature_block_times, TransactionSource};
+
+const AUDIT_INTERVAL: WaitDuration = WaitDuration::from_secs(300);
+const DEFAULT_AUDIT_WINDOW_HOURS: i64 = 24;
+/// Times a gap is re-scraped before the auditor gives up on it.
+const MAX_RESCRAPES_PER_GAP: u32 = 3;
+const SIGNATURE_PAGE_SIZE: usize = 1000;
+
+/// Checks that the `seq_num` of the fills of each ingested market is complete, which is every
+/// market when `SCRAPE_ALL_MARKETS` is true. Every run looks for gaps in the fills of the last
+/// `GAP_AUDIT_WINDOW_HOURS` hours (24 by default), publishes the completeness of each market,
+/// and queues the transactions that can hold the missing fills to be scraped again. Those are
+/// the signatures of the market between the two fills around the gap, or the stored
+/// transactions in the slots of the gap when the signatures of the fills are not known.
+pub async fn audit_fill_gaps(
+    source: &dyn TransactionSource,
+    pool: &Pool,
+    markets: &MarketRegistry,
+) -> anyhow::Result<()> {
+    let window = match dotenv::var("GAP_AUDIT_WINDOW_HOURS") {
+        Ok(s) => Duration::hours(s.parse::<i64>()?),
+        Err(_) => Duration::hours(DEFAULT_AUDIT_WINDOW_HOURS),
+    };
+    let mut rescrapes = GapRescrapes::default();
+
+    loop {
+        tokio::time::sleep(AUDIT_INTERVAL).await;
+        let since = Utc::now() - window;
+        let mut open_gaps = HashSet::new();
+
+        for market in markets.list().iter() {
+            let sequence = match fetch_fill_sequence(pool, &market.market_pk, since).await? {
+                Some(s) => s,
+                None => continue,
+            };
+            let gaps = fetch_fill_gaps(pool, &market.market_pk, since).await?;
+            let missing: u64 = gaps.iter().map(|g| g.missing_fills()).sum();
+            METRIC_FILL_COMPLETENESS
+                .with_label_values(&[&market.market_name])
+                .set(sequence.completeness());
+            METRIC_MISSING_FILLS
+                .with_label_values(&[&market.market_name])
+                .set(missing as i64);
+
+            for gap in gaps.iter() {
+                open_gaps.insert(GapRescrapes::key(gap));
+                if !rescrapes.try_rescrape(gap) {
+                    continue;
+                }
+
+                let num_queued = match rescrape_gap(source, pool, &market.program_pk, gap).await {
+                    Ok(n) => n,
+                    Err(e) => {
+                        warn!("failed to queue the re-scrape of a fill gap: {:?}", e);
+                        continue;
+                    }
+                };
+                info!(
+                    "{} is missing fills {} to {}, queued {} transactions between slots {} and {}",
+                    market.market_name,
+                    gap.from_seq_num + 1,
+                    gap.to_seq_num - 1,
+                    num_queued,
+                    gap.from_slot,
+                    gap.to_slot
+                );
+            }
+        }
+        rescrapes.retain_open(&open_gaps);
+    }
+}
+
+/// Counts the re-scrapes of each gap, so that a gap that re-scraping doesn't fill is given up
+/// on after `MAX_RESCRAPES_PER_GAP`. Gaps are keyed by their market and the `seq_num` of the
+/// fill before them.
+#[derive(Default)]
+struct GapRescrapes {
+    attempts: HashMap<(String, u64), u32>,
+}
+
+impl GapRescrapes {
+    fn key(gap: &FillGap) -> (String, u64) {
+        (gap.market_pk.clone(), gap.from_seq_num)
+    }
+
+    /// Returns whether the gap should be re-scraped, counting the attempt if so.
+    fn try_rescrape(&mut self, gap: &FillGap) -> bool {
+        let attempts = self.attempts.entry(Self::key(gap)).or_default();
+        if *attempts >= MAX_RESCRAPES_PER_GAP {
+            return false;
+        }
+        *attempts += 1;
+        true
+    }
+
+    /// Forgets the gaps that were filled or left the window.
+    fn retain_open(&mut self, open_gaps: &HashSet<(String, u64)>) {
+        self.attempts.retain(|key, _| open_gaps.contains(key));
+    }
+}
+
+async fn rescrape_gap(
+    source: &dyn TransactionSource,
+    pool: &Pool,
+    program_pk: &str,
+    gap: &FillGap,
+) -> anyhow::Result<u64> {
+    let (from_signature, to_signature) = match (&gap.from_signature, &gap.to_signature) {
+        (Some(from), Some(to)) => (from.parse::<Signature>()?, to.parse::<Signature>()?),
+        _ => {
+            return requeue_transactions_for_rescrape(
+                pool,
+                &PROGRAM_CONFIG.cluster,
+                vec![],
+                Some((gap.from_slot, gap.to_slot)),
+            )
+            .await
+        }
+    };
+
+    let market_pk = gap.market_pk.parse::<Pubkey>()?;
+    let program_pk = program_pk.parse::<Pubkey>()?;
+    let mut transactions = vec![];
+    let mut before = Some(to_signature);
+    loop {
+        let rpc_config = GetConfirmedSignaturesForAddress2Config {
+            before,
+            until: Some(from_signature),
+            limit: Some(SIGNATURE_PAGE_SIZE),
+            commitment: Some(CommitmentConfig::confirmed()),
+        };
+        let mut page = match source
+            .get_signatures_for_address(&market_pk, rpc_config)
+            .await
+        {
+            Ok(page) => page,
+            Err(e) => {
+                METRIC_RPC_ERRORS_TOTAL
+                    .with_label_values(&["getSignaturesForAddress"])
+                    .inc();
+                return Err(e.into());
+            }
+        };
+        fill_missing_signature_block_times(source, &mut page).await?;
+        let page_len = page.len();
+        before = page
+            .last()
+            .and_then(|s| s.signature.parse::<Signature>().ok());
+        transactions.extend(
+            page.into_iter()
+                .filter(|s| s.err.is_none())
+                .filter_map(|s| PgTransaction::from_rpc_confirmed_transaction(s, &program_pk).ok()),
+        );
+        if page_len < SIGNATURE_PAGE_SIZE || before.is_none() {
+            break;
+        }
+    }
+    requeue_transactions_for_rescrape(pool, &PROGRAM_CONFIG.cluster, transactions, None).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gap(market_pk: &str, from_seq_num: u64, to_seq_num: u64) -> FillGap {
+        FillGap {
+            market_pk: market_pk.to_string(),
+            from_seq_num,
+            to_seq_num,
+            from_slot: 100,
+            to_slot: 110,
+            from_signature: None,
+            to_signature: None,
+        }
+    }
+
+    #[test]
+    fn gaps_are_rescraped_at_most_three_times() {
+        let mut rescrapes = GapRescrapes::default();
+        let gap = gap("market", 10, 13);
+        for _ in 0..MAX_RESCRAPES_PER_GAP {
+            assert!(rescrapes.try_rescrape(&gap));
+        }
+        assert!(!rescrapes.try_rescrape(&gap));
+        assert!(!rescrapes.try_rescrape(&gap));
+    }
+
+    #[test]
+    fn gaps_are_counted_separately() {
+        let mut rescrapes = GapRescrapes::default();
+        let first = gap("market", 10, 13);
+        for _ in 0..MAX_RESCRAPES_PER_GAP {
+            assert!(rescrapes.try_rescrape(&first));
+        }
+        assert!(rescrapes.try_rescrape(&gap("market", 20, 22)));
+        assert!(rescrapes.try_rescrape(&gap("other", 10, 13)));
+        // a gap that shrank from its end is still the same gap
+        assert!(!rescrapes.try_rescrape(&gap("market", 10, 12)));
+    }
+
+    #[test]
+    fn filled_gaps_are_forgotten() {
+        let mut rescrapes = GapRescrapes::default();
+        let filled = gap("market", 10, 13);
+        let open = gap("market", 20, 22);
+        for _ in 0..MAX_RESCRAPES_PER_GAP {
+            rescrapes.try_rescrape(&filled);
+            rescrapes.try_rescrape(&open);
+        }
+
+        rescrapes.retain_open(&HashSet::from([GapRescrapes::key(&open)]));
+        assert!(!rescrapes.try_rescrape(&open));
+        // the same seq_nums going missing again is a new gap
+        assert!(rescrapes.try_rescrape(&filled));
+    }
+}
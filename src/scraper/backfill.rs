@@ -0,0 +1,203 @@
+use chrono::{DateTime, Utc};
+use deadpool_postgres::Pool;
+use log::{debug, info, warn};
+use solana_client::{
+    rpc_client::GetConfirmedSignaturesForAddress2Config,
+    rpc_response::RpcConfirmedTransactionStatusWithSignature,
+};
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Signature};
+use std::time::Duration as WaitDuration;
+
+use crate::{
+    database::{fetch::fetch_backfill_state, insert::insert_backfill_page},
+    structs::{backfill::BackfillState, transaction::PgTransaction},
+    utils::{to_timestampz, PROGRAM_CONFIG},
+    worker::metrics::{
+        METRIC_BACKFILL_SLOT, METRIC_RPC_ERRORS_TOTAL, METRIC_TRANSACTIONS_TOTAL,
+        METRIC_UNSTORABLE_SIGNATURES_TOTAL,
+    },
+};
+
+use super::{
+    scrape::scrape_addresses,
+    source::{fill_missing_signature_block_times, TransactionSource},
+};
+
+const BACKFILL_PAGE_SIZE: usize = 1000;
+
+/// Describes how far back signatures should be backfilled, and for which address.
+/// The address is the first configured program by default, but can be a market or event heap
+/// account of that program.
+#[derive(Clone, Debug)]
+pub struct BackfillConfig {
+    pub address: Pubkey,
+    pub program_id: Pubkey,
+    pub until_slot: Option<u64>,
+    pub until_datetime: Option<DateTime<Utc>>,
+}
+
+impl BackfillConfig {
+    /// Reads `BACKFILL_UNTIL_SLOT`, `BACKFILL_UNTIL_TIMESTAMP` and `BACKFILL_ADDRESS`, with one
+    /// config per address. Without `BACKFILL_ADDRESS`, the addresses of `SCRAPE_ADDRESSES` are
+    /// backfilled, or else the program. Returns no configs when no backfill target is set.
+    pub fn from_env() -> anyhow::Result<Vec<Self>> {
+        let until_slot = match dotenv::var("BACKFILL_UNTIL_SLOT") {
+            Ok(s) => Some(s.parse::<u64>()?),
+            Err(_) => None,
+        };
+        let until_datetime = match dotenv::var("BACKFILL_UNTIL_TIMESTAMP") {
+            Ok(s) => Some(to_timestampz(s.parse::<u64>()?)),
+            Err(_) => None,
+        };
+        if until_slot.is_none() && until_datetime.is_none() {
+            return Ok(vec![]);
+        }
+        let program_id = PROGRAM_CONFIG.program_ids[0];
+        let addresses = match dotenv::var("BACKFILL_ADDRESS") {
+            Ok(s) => vec![s.parse::<Pubkey>()?],
+            Err(_) => match scrape_addresses()? {
+                addresses if !addresses.is_empty() => addresses,
+                _ => vec![program_id],
+            },
+        };
+        Ok(addresses
+            .into_iter()
+            .map(|address| BackfillConfig {
+                address,
+                program_id,
+                until_slot,
+                until_datetime,
+            })
+            .collect())
+    }
+
+    fn is_past_target(&self, sig: &RpcConfirmedTransactionStatusWithSignature) -> bool {
+        let past_slot = matches!(self.until_slot, Some(s) if sig.slot < s);
+        let past_datetime = match (self.until_datetime, sig.block_time) {
+            (Some(dt), Some(bt)) => to_timestampz(bt as u64) < dt,
+            _ => false,
+        };
+        past_slot || past_datetime
+    }
+}
+
+/// Walks backwards through the signatures of the configured address with the `before`
+/// cursor, writing them to the transactions table until the target slot or date is reached.
+/// The cursor is persisted with every page, so a restarted backfill resumes where it stopped.
+pub async fn backfill_signatures(
+    source: &dyn TransactionSource,
+    pool: &Pool,
+    config: BackfillConfig,
+) -> anyhow::Result<()> {
+    let address = config.address.to_string();
+
+    let mut state = match fetch_backfill_state(pool, &address).await? {
+        Some(s) => s,
+        None => BackfillState {
+            address: address.clone(),
+            before_signature: None,
+            oldest_slot: None,
+            target_slot: None,
+            target_datetime: None,
+            complete: false,
+        },
+    };
+    if state.complete
+        && state.target_slot == config.until_slot
+        && state.target_datetime == config.until_datetime
+    {
+        info!("Backfill for {} already complete", address);
+        return Ok(());
+    }
+    state.target_slot = config.until_slot;
+    state.target_datetime = config.until_datetime;
+    state.complete = false;
+    info!(
+        "Backfilling {} from {:?} until slot {:?} / {:?}",
+        address, state.before_signature, state.target_slot, state.target_datetime
+    );
+
+    loop {
+        let before = match &state.before_signature {
+            Some(s) => Some(s.parse::<Signature>()?),
+            None => None,
+        };
+        let rpc_config = GetConfirmedSignaturesForAddress2Config {
+            before,
+            until: None,
+            limit: Some(BACKFILL_PAGE_SIZE),
+            commitment: Some(CommitmentConfig::confirmed()),
+        };
+
+        let mut sigs = match source
+            .get_signatures_for_address(&config.address, rpc_config)
+            .await
+        {
+            Ok(sigs) => sigs,
+            Err(e) => {
+                warn!("rpc error in get_signatures_for_address_with_config: {}", e);
+                METRIC_RPC_ERRORS_TOTAL
+                    .with_label_values(&["getSignaturesForAddress"])
+                    .inc();
+                tokio::time::sleep(WaitDuration::from_secs(1)).await;
+                continue;
+            }
+        };
+        if let Err(e) = fill_missing_signature_block_times(source, &mut sigs).await {
+            warn!("rpc error in get_block_time: {}", e);
+            METRIC_RPC_ERRORS_TOTAL
+                .with_label_values(&["getBlockTime"])
+                .inc();
+            tokio::time::sleep(WaitDuration::from_secs(1)).await;
+            continue;
+        }
+
+        if sigs.is_empty() {
+            info!("Backfill for {} reached the first signature", address);
+            state.complete = true;
+            insert_backfill_page(pool, vec![], &state).await?;
+            return Ok(());
+        }
+
+        // a signature that can't be stored fails the page, so it is fetched again rather than
+        // skipped
+        let reached_target = sigs.iter().any(|s| config.is_past_target(s));
+        let oldest = sigs.last().unwrap();
+        let (before_signature, oldest_slot) = (oldest.signature.clone(), oldest.slot);
+        let transactions: Result<Vec<PgTransaction>, _> = sigs
+            .into_iter()
+            .filter(|s| !config.is_past_target(s))
+            .map(|s| PgTransaction::from_rpc_confirmed_transaction(s, &config.program_id))
+            .collect();
+        let transactions = match transactions {
+            Ok(transactions) => transactions,
+            Err(e) => {
+                warn!("failed to store backfilled signature of {}: {}", address, e);
+                METRIC_UNSTORABLE_SIGNATURES_TOTAL
+                    .with_label_values(&[&address])
+                    .inc();
+                tokio::time::sleep(WaitDuration::from_secs(1)).await;
+                continue;
+            }
+        };
+        state.before_signature = Some(before_signature);
+        state.oldest_slot = Some(oldest_slot);
+        state.complete = reached_target;
+
+        debug!(
+            "Backfill writing: {:?} txns to DB, oldest slot {:?}",
+            transactions.len(),
+            state.oldest_slot
+        );
+        let num_txns = insert_backfill_page(pool, transactions, &state).await?;
+        METRIC_TRANSACTIONS_TOTAL.inc_by(num_txns);
+        METRIC_BACKFILL_SLOT
+            .with_label_values(&[&address])
+            .set(state.oldest_slot.unwrap_or_default() as i64);
+
+        if state.complete {
+            info!("Backfill for {} reached its target", address);
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,399 @@
+use deadpool_postgres::Pool;
+use futures::future::join_all;
+use log::{info, warn};
+use solana_client::client_error::Result as ClientResult;
+use solana_transaction_status::EncodedConfirmedTransactionWithStatusMeta;
+use std::{collections::VecDeque, time::Duration as WaitDuration};
+
+use crate::{
+    database::{
+        fetch::fetch_block_scan_ranges_from,
+        insert::{insert_block_scan_range, insert_block_scan_transactions},
+    },
+    structs::{block_scan::BlockScanRange, transaction::PgTransaction},
+    utils::{market_registry::MarketRegistry, to_timestampz, PROGRAM_CONFIG},
+    worker::metrics::{METRIC_BLOCK_SCAN_SLOT, METRIC_RPC_ERRORS_TOTAL, METRIC_TRANSACTIONS_TOTAL},
+};
+
+use super::{
+    archive::archive_enabled,
+    parsing::{transaction_account_keys, transaction_program_id, transaction_signature},
+    scrape::{ingest_transactions, ClaimConfig},
+    source::TransactionSource,
+};
+
+const DEFAULT_BLOCK_SCAN_BATCH_SLOTS: u64 = 20;
+const SCAN_POLL_INTERVAL: WaitDuration = WaitDuration::from_secs(1);
+
+/// Which slots the block scanner reads.
+#[derive(Clone, Debug)]
+pub struct BlockScanConfig {
+    pub start_slot: u64,
+    pub end_slot: Option<u64>,
+    pub batch_slots: u64,
+}
+
+impl BlockScanConfig {
+    /// Reads `BLOCK_SCAN_START_SLOT`, `BLOCK_SCAN_END_SLOT` and `BLOCK_SCAN_BATCH_SLOTS`.
+    /// Returns None when no start slot is configured. Without an end slot, the scanner keeps
+    /// following the finalized tip.
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let start_slot = match dotenv::var("BLOCK_SCAN_START_SLOT") {
+            Ok(s) => s.parse::<u64>()?,
+            Err(_) => return Ok(None),
+        };
+        let end_slot = match dotenv::var("BLOCK_SCAN_END_SLOT") {
+            Ok(s) => Some(s.parse::<u64>()?),
+            Err(_) => None,
+        };
+        let batch_slots = match dotenv::var("BLOCK_SCAN_BATCH_SLOTS") {
+            Ok(s) => s.parse::<u64>()?.max(1),
+            Err(_) => DEFAULT_BLOCK_SCAN_BATCH_SLOTS,
+        };
+        Ok(Some(BlockScanConfig {
+            start_slot,
+            end_slot,
+            batch_slots,
+        }))
+    }
+}
+
+/// Reads every finalized block from the start slot on and ingests the transactions calling a
+/// configured program, so none are missed the way signature listing can miss them. Each range of
+/// slots is recorded in `block_scan_ranges` once all of its transactions are stored and
+/// ingested, which proves which slots were read, and lets a restarted scanner skip them. A range
+/// that can't be read or stored is scanned again. A range with transactions that failed to
+/// parse is left unrecorded, its transactions are retried like listed ones, and a restarted
+/// scanner reads it again.
+pub async fn scan_blocks(
+    source: &dyn TransactionSource,
+    pool: &Pool,
+    markets: &MarketRegistry,
+    config: BlockScanConfig,
+    claim_config: &ClaimConfig,
+) -> anyhow::Result<()> {
+    let claimant = format!("{}:blocks", claim_config.process_name);
+    let archive = archive_enabled()?;
+    let cluster = PROGRAM_CONFIG.cluster.clone();
+
+    let mut next_slot = config.start_slot;
+    let mut recorded: VecDeque<(u64, u64)> =
+        fetch_block_scan_ranges_from(pool, &cluster, config.start_slot)
+            .await?
+            .into_iter()
+            .map(|r| (r.start_slot, r.end_slot))
+            .collect();
+    skip_recorded_ranges(&mut recorded, &mut next_slot);
+    info!("Scanning blocks from slot {}", next_slot);
+
+    loop {
+        skip_recorded_ranges(&mut recorded, &mut next_slot);
+        if matches!(config.end_slot, Some(end_slot) if next_slot > end_slot) {
+            info!("Block scan reached slot {}", next_slot - 1);
+            return Ok(());
+        }
+        let finalized_slot = match source.get_finalized_slot().await {
+            Ok(slot) => slot,
+            Err(e) => {
+                warn!("rpc error in get_slot: {}", e);
+                METRIC_RPC_ERRORS_TOTAL
+                    .with_label_values(&["getSlot"])
+                    .inc();
+                tokio::time::sleep(SCAN_POLL_INTERVAL).await;
+                continue;
+            }
+        };
+        let end_slot = (next_slot + config.batch_slots - 1)
+            .min(finalized_slot)
+            .min(config.end_slot.unwrap_or(u64::MAX))
+            .min(
+                recorded
+                    .front()
+                    .map_or(u64::MAX, |(start_slot, _)| start_slot - 1),
+            );
+        if end_slot < next_slot {
+            tokio::time::sleep(SCAN_POLL_INTERVAL).await;
+            continue;
+        }
+
+        let (range, txns) = match scan_slot_range(source, &cluster, next_slot, end_slot).await {
+            Ok(scanned) => scanned,
+            Err(e) => {
+                warn!(
+                    "rpc error in get_block for slots {} to {}: {}",
+                    next_slot, end_slot, e
+                );
+                METRIC_RPC_ERRORS_TOTAL
+                    .with_label_values(&["getBlock"])
+                    .inc();
+                tokio::time::sleep(SCAN_POLL_INTERVAL).await;
+                continue;
+            }
+        };
+
+        let scanned = ingest_slot_range(
+            pool,
+            markets,
+            archive,
+            &claimant,
+            claim_config.lease_secs,
+            &range,
+            txns,
+        )
+        .await;
+        match scanned {
+            Ok(num_txns) => METRIC_TRANSACTIONS_TOTAL.inc_by(num_txns),
+            Err(e) => {
+                warn!(
+                    "failed to ingest slots {} to {}: {:?}",
+                    next_slot, end_slot, e
+                );
+                tokio::time::sleep(SCAN_POLL_INTERVAL).await;
+                continue;
+            }
+        }
+        METRIC_BLOCK_SCAN_SLOT.set(end_slot as i64);
+        next_slot = end_slot + 1;
+    }
+}
+
+/// Moves past the recorded ranges that start at or before the next slot.
+fn skip_recorded_ranges(recorded: &mut VecDeque<(u64, u64)>, next_slot: &mut u64) {
+    while let Some((start_slot, end_slot)) = recorded.front().copied() {
+        if start_slot > *next_slot {
+            break;
+        }
+        *next_slot = (*next_slot).max(end_slot + 1);
+        recorded.pop_front();
+    }
+}
+
+/// Stores and ingests the transactions of a scanned range, then records the range if every
+/// transaction was ingested. Fails without recording the range if any transaction can't be
+/// stored, so that it is scanned again.
+async fn ingest_slot_range(
+    pool: &Pool,
+    markets: &MarketRegistry,
+    archive: bool,
+    claimant: &str,
+    lease_secs: i64,
+    range: &BlockScanRange,
+    txns: Vec<EncodedConfirmedTransactionWithStatusMeta>,
+) -> anyhow::Result<u64> {
+    // failed transactions are stored like listed signatures are, but never parsed
+    let mut transactions = vec![];
+    let mut sig_strings = vec![];
+    let mut ingested = vec![];
+    for txn in txns.into_iter() {
+        let signature = transaction_signature(&txn.transaction)
+            .ok_or_else(|| anyhow::anyhow!("transaction without signature in slot {}", txn.slot))?;
+        let program_pk = transaction_program_id(&txn.transaction);
+        let transaction = PgTransaction::from_block_transaction(&signature, &program_pk, &txn)?;
+        if !transaction.err {
+            sig_strings.push(signature);
+            ingested.push(Ok(txn));
+        }
+        transactions.push(transaction);
+    }
+
+    let num_txns = insert_block_scan_transactions(pool, transactions, claimant, lease_secs).await?;
+    let num_failed = if ingested.is_empty() {
+        0
+    } else {
+        ingest_transactions(pool, markets, archive, ingested, sig_strings).await?
+    };
+    if num_failed > 0 {
+        warn!(
+            "{} transactions of slots {} to {} failed to parse, leaving the range unrecorded",
+            num_failed, range.start_slot, range.end_slot
+        );
+    } else {
+        insert_block_scan_range(pool, range).await?;
+    }
+    Ok(num_txns)
+}
+
+/// Reads the blocks between two slots, inclusive, keeping the transactions that call a
+/// configured program. Fails if any block can't be read, so that a range is never recorded with
+/// blocks missing.
+async fn scan_slot_range(
+    source: &dyn TransactionSource,
+    cluster: &str,
+    start_slot: u64,
+    end_slot: u64,
+) -> ClientResult<(
+    BlockScanRange,
+    Vec<EncodedConfirmedTransactionWithStatusMeta>,
+)> {
+    let slots = source.get_finalized_blocks(start_slot, end_slot).await?;
+    let blocks = join_all(slots.iter().map(|s| source.get_block_transactions(*s))).await;
+
+    let mut block_times = vec![];
+    let mut txns = vec![];
+    for (slot, block) in slots.iter().zip(blocks) {
+        let mut block = block?;
+        if block.iter().any(|t| t.block_time.is_none()) {
+            let block_time = source.get_block_time(*slot).await?;
+            block
+                .iter_mut()
+                .for_each(|t| t.block_time = Some(block_time));
+        }
+        block_times.extend(block.first().and_then(|t| t.block_time));
+        txns.extend(block.into_iter().filter(|t| {
+            transaction_account_keys(&t.transaction)
+                .iter()
+                .any(|k| PROGRAM_CONFIG.is_program(k))
+        }));
+    }
+
+    let range = BlockScanRange {
+        cluster: cluster.to_string(),
+        start_slot,
+        end_slot,
+        start_datetime: block_times.iter().min().map(|t| to_timestampz(*t as u64)),
+        end_datetime: block_times.iter().max().map(|t| to_timestampz(*t as u64)),
+        num_blocks: slots.len() as i64,
+        num_transactions: txns.len() as i64,
+    };
+    Ok((range, txns))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::fetch::fetch_block_scan_ranges_between,
+        structs::{block_scan::BlockScanCoverage, openbook_v2::FillLog},
+        test_utils::{fill_log, fill_transaction, market, recorded_transaction, test_pool},
+    };
+    use anchor_lang::{__private::base64, Discriminator};
+
+    fn range(start_slot: u64, end_slot: u64, times: (u64, u64), num_blocks: i64) -> BlockScanRange {
+        BlockScanRange {
+            cluster: PROGRAM_CONFIG.cluster.clone(),
+            start_slot,
+            end_slot,
+            start_datetime: Some(to_timestampz(times.0)),
+            end_datetime: Some(to_timestampz(times.1)),
+            num_blocks,
+            num_transactions: 1,
+        }
+    }
+
+    #[test]
+    fn recorded_ranges_are_skipped() {
+        let mut recorded = VecDeque::from([(100, 119), (140, 159)]);
+        let mut next_slot = 100;
+        skip_recorded_ranges(&mut recorded, &mut next_slot);
+        assert_eq!(next_slot, 120);
+        assert_eq!(recorded, VecDeque::from([(140, 159)]));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn window_starting_between_the_blocks_of_two_ranges_is_covered() {
+        let pool = test_pool("window_starting_between_the_blocks_of_two_ranges_is_covered").await;
+        // recorded out of order, so the two ranges are kept apart
+        insert_block_scan_range(&pool, &range(120, 139, (106, 114), 20))
+            .await
+            .unwrap();
+        insert_block_scan_range(&pool, &range(100, 119, (90, 98), 20))
+            .await
+            .unwrap();
+
+        let (from, to) = (to_timestampz(100), to_timestampz(110));
+        let ranges = fetch_block_scan_ranges_between(&pool, &PROGRAM_CONFIG.cluster, from, to)
+            .await
+            .unwrap();
+        let coverage = BlockScanCoverage::from_ranges(from, to, &ranges);
+        assert!(coverage.covered);
+        assert_eq!(coverage.ranges, vec![(100, 139)]);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn rescanned_ranges_keep_their_counts() {
+        let pool = test_pool("rescanned_ranges_keep_their_counts").await;
+        insert_block_scan_range(&pool, &range(100, 119, (90, 98), 20))
+            .await
+            .unwrap();
+        insert_block_scan_range(&pool, &range(100, 129, (90, 102), 30))
+            .await
+            .unwrap();
+        insert_block_scan_range(&pool, &range(100, 109, (90, 94), 10))
+            .await
+            .unwrap();
+
+        let ranges = fetch_block_scan_ranges_from(&pool, &PROGRAM_CONFIG.cluster, 0)
+            .await
+            .unwrap();
+        assert_eq!(ranges, vec![range(100, 129, (90, 102), 30)]);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn ranges_are_recorded_once_every_transaction_is_ingested() {
+        let pool = test_pool("ranges_are_recorded_once_every_transaction_is_ingested").await;
+        let market = market();
+        let markets = MarketRegistry::new(vec![market.clone()], None);
+        let program_id = PROGRAM_CONFIG.program_ids[0];
+        let malformed = [FillLog::DISCRIMINATOR.as_slice(), &[0; 4]].concat();
+        let malformed_txn = recorded_transaction(
+            101,
+            Some(1_700_000_000),
+            std::slice::from_ref(&market.market_pk),
+            vec![
+                format!("Program {} invoke [1]", program_id),
+                format!("Program data: {}", base64::encode(malformed)),
+                format!("Program {} success", program_id),
+            ],
+        );
+        let fill_txn = |slot| {
+            fill_transaction(
+                &market,
+                slot,
+                Some(1_700_000_000),
+                &[fill_log(&market, slot, 20_000, 10)],
+            )
+        };
+
+        let scanned = range(100, 119, (1_700_000_000, 1_700_000_000), 2);
+        let num_txns = ingest_slot_range(
+            &pool,
+            &markets,
+            false,
+            "test",
+            60,
+            &scanned,
+            vec![fill_txn(100), malformed_txn],
+        )
+        .await
+        .unwrap();
+        assert_eq!(num_txns, 2);
+        assert!(
+            fetch_block_scan_ranges_from(&pool, &PROGRAM_CONFIG.cluster, 0)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+
+        let scanned = range(120, 139, (1_700_000_000, 1_700_000_000), 1);
+        ingest_slot_range(
+            &pool,
+            &markets,
+            false,
+            "test",
+            60,
+            &scanned,
+            vec![fill_txn(120)],
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            fetch_block_scan_ranges_from(&pool, &PROGRAM_CONFIG.cluster, 0)
+                .await
+                .unwrap(),
+            vec![scanned]
+        );
+    }
+}
@@ -0,0 +1,121 @@
+use anchor_lang::{AnchorDeserialize, Discriminator};
+use lazy_static::lazy_static;
+use log::debug;
+use std::collections::HashMap;
+
+use crate::{
+    structs::{
+        openbook_v2::{
+            DepositLog, FillLog, MarketMetaDataLog, OpenOrdersPositionLog, SetDelegateLog,
+            SettleFundsLog, SweepFeesLog, TotalOrderFillEvent,
+        },
+        parse_error::ParseError,
+    },
+    worker::metrics::{METRIC_EVENT_DECODE_ERRORS_TOTAL, METRIC_UNKNOWN_EVENTS_TOTAL},
+};
+
+pub const DISCRIMINATOR_LEN: usize = 8;
+
+/// An OpenBook event decoded from its Anchor payload.
+#[derive(Debug)]
+pub enum OpenBookEvent {
+    Fill(FillLog),
+    MarketMetaData(MarketMetaDataLog),
+    Deposit(DepositLog),
+    SettleFunds(SettleFundsLog),
+    SweepFees(SweepFeesLog),
+    OpenOrdersPosition(OpenOrdersPositionLog),
+    SetDelegate(SetDelegateLog),
+    TotalOrderFill(TotalOrderFillEvent),
+}
+
+type EventDecoder = Box<dyn Fn(&[u8]) -> Option<OpenBookEvent> + Send + Sync>;
+
+struct RegisteredEvent {
+    name: &'static str,
+    decode: EventDecoder,
+}
+
+/// Decoders keyed by the 8 byte Anchor event discriminator. A payload is only ever decoded as
+/// the event type its discriminator names, so events with compatible layouts can't be mixed up.
+pub struct EventRegistry {
+    events: HashMap<[u8; DISCRIMINATOR_LEN], RegisteredEvent>,
+}
+
+impl EventRegistry {
+    pub fn new() -> Self {
+        EventRegistry {
+            events: HashMap::new(),
+        }
+    }
+
+    pub fn register<T>(&mut self, name: &'static str, wrap: fn(T) -> OpenBookEvent)
+    where
+        T: AnchorDeserialize + Discriminator + 'static,
+    {
+        let decode: EventDecoder =
+            Box::new(move |mut data: &[u8]| T::deserialize(&mut data).ok().map(wrap));
+        self.events
+            .insert(T::DISCRIMINATOR, RegisteredEvent { name, decode });
+    }
+
+    /// Decodes a payload of discriminator followed by the borsh serialized event. Payloads too
+    /// short for a discriminator and unknown discriminators are skipped, the latter are counted.
+    /// A payload of a known event that fails to decode is counted and returned as an error.
+    pub fn decode(
+        &self,
+        payload: &[u8],
+        log_index: u32,
+    ) -> Result<Option<OpenBookEvent>, ParseError> {
+        if payload.len() < DISCRIMINATOR_LEN {
+            return Ok(None);
+        }
+        let (discriminator, data) = payload.split_at(DISCRIMINATOR_LEN);
+        match self.events.get(discriminator) {
+            Some(event) => match (event.decode)(data) {
+                Some(decoded) => Ok(Some(decoded)),
+                None => {
+                    METRIC_EVENT_DECODE_ERRORS_TOTAL
+                        .with_label_values(&[event.name])
+                        .inc();
+                    Err(ParseError::MalformedEvent {
+                        event: event.name,
+                        log_index,
+                    })
+                }
+            },
+            None => {
+                debug!("unknown event discriminator {}", to_hex(discriminator));
+                METRIC_UNKNOWN_EVENTS_TOTAL.inc();
+                Ok(None)
+            }
+        }
+    }
+}
+
+impl Default for EventRegistry {
+    fn default() -> Self {
+        let mut registry = EventRegistry::new();
+        registry.register::<FillLog>("FillLog", OpenBookEvent::Fill);
+        registry.register::<MarketMetaDataLog>("MarketMetaDataLog", OpenBookEvent::MarketMetaData);
+        registry.register::<DepositLog>("DepositLog", OpenBookEvent::Deposit);
+        registry.register::<SettleFundsLog>("SettleFundsLog", OpenBookEvent::SettleFunds);
+        registry.register::<SweepFeesLog>("SweepFeesLog", OpenBookEvent::SweepFees);
+        registry.register::<OpenOrdersPositionLog>(
+            "OpenOrdersPositionLog",
+            OpenBookEvent::OpenOrdersPosition,
+        );
+        registry.register::<SetDelegateLog>("SetDelegateLog", OpenBookEvent::SetDelegate);
+        registry
+            .register::<TotalOrderFillEvent>("TotalOrderFillEvent", OpenBookEvent::TotalOrderFill);
+        registry
+    }
+}
+
+lazy_static! {
+    pub static ref OPENBOOK_EVENTS: EventRegistry = EventRegistry::default();
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
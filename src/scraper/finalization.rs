@@ -0,0 +1,152 @@
+use deadpool_postgres::Pool;
+use log::{info, warn};
+use std::{collections::HashSet, time::Duration as WaitDuration};
+
+use crate::{
+    database::{fetch::fetch_unfinalized_fill_slots, insert::reconcile_fill_commitment},
+    utils::PROGRAM_CONFIG,
+    worker::metrics::{METRIC_FILL_COMMITMENT_TOTAL, METRIC_RPC_ERRORS_TOTAL},
+};
+
+use super::source::TransactionSource;
+
+const FINALIZATION_INTERVAL: WaitDuration = WaitDuration::from_secs(30);
+/// How many distinct fill slots are reconciled per run.
+const FINALIZATION_SLOT_LIMIT: i64 = 5000;
+/// The widest range accepted by `getBlocks`.
+const MAX_GET_BLOCKS_RANGE: u64 = 500_000;
+
+/// Fills are ingested at confirmed commitment, so a fill can come from a slot that is later
+/// skipped by the cluster. Each run takes the confirmed fills in slots at or below the latest
+/// finalized slot and checks those slots against the finalized chain: fills in finalized blocks
+/// are marked as finalized, the others are tombstoned as orphaned and their candles rebatched.
+pub async fn reconcile_finalized_fills(
+    source: &dyn TransactionSource,
+    pool: &Pool,
+) -> anyhow::Result<()> {
+    loop {
+        tokio::time::sleep(FINALIZATION_INTERVAL).await;
+
+        let finalized_slot = match source.get_finalized_slot().await {
+            Ok(slot) => slot,
+            Err(e) => {
+                warn!("rpc error in get_slot: {}", e);
+                METRIC_RPC_ERRORS_TOTAL
+                    .with_label_values(&["getSlot"])
+                    .inc();
+                continue;
+            }
+        };
+        let slots = fetch_unfinalized_fill_slots(
+            pool,
+            &PROGRAM_CONFIG.cluster,
+            finalized_slot,
+            FINALIZATION_SLOT_LIMIT,
+        )
+        .await?;
+        if slots.is_empty() {
+            continue;
+        }
+
+        let finalized_blocks = match fetch_finalized_blocks(source, &slots).await {
+            Ok(blocks) => blocks,
+            Err(e) => {
+                warn!("rpc error in get_blocks: {}", e);
+                METRIC_RPC_ERRORS_TOTAL
+                    .with_label_values(&["getBlocks"])
+                    .inc();
+                continue;
+            }
+        };
+        let (finalized_slots, orphaned_slots): (Vec<u64>, Vec<u64>) = slots
+            .into_iter()
+            .partition(|s| finalized_blocks.contains(s));
+
+        let (num_finalized, num_orphaned) = reconcile_fill_commitment(
+            pool,
+            &PROGRAM_CONFIG.cluster,
+            &finalized_slots,
+            &orphaned_slots,
+        )
+        .await?;
+        METRIC_FILL_COMMITMENT_TOTAL
+            .with_label_values(&["finalized"])
+            .inc_by(num_finalized);
+        METRIC_FILL_COMMITMENT_TOTAL
+            .with_label_values(&["orphaned"])
+            .inc_by(num_orphaned);
+        if num_orphaned > 0 {
+            warn!(
+                "Orphaned {} fills from skipped slots {:?}",
+                num_orphaned, orphaned_slots
+            );
+        }
+        info!(
+            "Finalized {} fills up to slot {}",
+            num_finalized, finalized_slot
+        );
+    }
+}
+
+/// Fetches the finalized blocks spanning the given ascending slots, in chunks that `getBlocks`
+/// accepts.
+async fn fetch_finalized_blocks(
+    source: &dyn TransactionSource,
+    slots: &[u64],
+) -> anyhow::Result<HashSet<u64>> {
+    let mut blocks = HashSet::new();
+    let mut remaining = slots;
+    while let Some(start_slot) = remaining.first().copied() {
+        let end_slot = start_slot + MAX_GET_BLOCKS_RANGE - 1;
+        let in_range = remaining.partition_point(|s| *s <= end_slot);
+        let last_slot = remaining[in_range - 1];
+        blocks.extend(source.get_finalized_blocks(start_slot, last_slot).await?);
+        remaining = &remaining[in_range..];
+    }
+    Ok(blocks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::insert::insert_streamed_fills,
+        structs::openbook_v2::OpenBookMarketMetadata,
+        test_utils::{market, openbook_fill, test_pool},
+    };
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn fills_of_other_clusters_are_left_unfinalized() {
+        let pool = test_pool("fills_of_other_clusters_are_left_unfinalized").await;
+        let own_market = market();
+        let other_market = OpenBookMarketMetadata {
+            cluster: "other-cluster".to_string(),
+            ..market()
+        };
+        insert_streamed_fills(
+            &pool,
+            vec![
+                openbook_fill(&own_market, 1, 100, 1_700_000_000),
+                openbook_fill(&other_market, 1, 90, 1_700_000_000),
+                openbook_fill(&other_market, 2, 100, 1_700_000_000),
+            ],
+        )
+        .await
+        .unwrap();
+
+        let slots = fetch_unfinalized_fill_slots(&pool, &PROGRAM_CONFIG.cluster, 200, 10)
+            .await
+            .unwrap();
+        assert_eq!(slots, vec![100]);
+        let reconciled = reconcile_fill_commitment(&pool, &PROGRAM_CONFIG.cluster, &[], &slots)
+            .await
+            .unwrap();
+        assert_eq!(reconciled, (0, 1));
+
+        let other_slots = fetch_unfinalized_fill_slots(&pool, "other-cluster", 200, 10)
+            .await
+            .unwrap();
+        assert_eq!(other_slots, vec![90, 100]);
+    }
+}
@@ -0,0 +1,233 @@
+use deadpool_postgres::Pool;
+use log::{info, warn};
+use solana_client::rpc_client::GetConfirmedSignaturesForAddress2Config;
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Signature};
+use std::time::Duration as WaitDuration;
+
+use crate::{
+    database::{
+        fetch::claim_market_imports,
+        insert::{complete_market_import, record_market_import_failure},
+    },
+    structs::openbook_v2::OpenBookMarketMetadata,
+    utils::{market_registry::MarketRegistry, to_timestampz, PROGRAM_CONFIG},
+    worker::metrics::METRIC_MARKET_IMPORTS_TOTAL,
+};
+
+use super::{
+    events::OpenBookEvent,
+    parsing::{decode_transaction_events, transaction_program_id},
+    source::{fill_missing_block_times, TransactionSource},
+};
+
+const IMPORT_PAGE_SIZE: usize = 1000;
+/// Pages of signatures walked back before the importer gives up on a market. Markets with a
+/// longer history can still be imported with `admin import`, which walks all of it.
+const MAX_IMPORT_SIGNATURE_PAGES: usize = 100;
+/// Number of transactions fetched at a time while looking for the market creation.
+const IMPORT_FETCH_SIZE: usize = 10;
+const IMPORT_POLL_INTERVAL: WaitDuration = WaitDuration::from_secs(10);
+const IMPORT_BATCH_SIZE: i64 = 5;
+/// Walking back the history of a busy market can take a while.
+const IMPORT_LEASE_SECS: i64 = 600;
+const IMPORT_RETRY_SECS: i64 = 600;
+/// Failed imports are left in the queue past this many attempts, see `admin imports`.
+pub const MAX_IMPORT_ATTEMPTS: i32 = 5;
+
+/// Imports the markets queued by the transaction scraper when it finds fills of markets that
+/// are not in `market_metadata`. Imported markets are inserted inactive, and the registry is
+/// refreshed so that their transactions succeed when they are retried.
+pub async fn import_unknown_markets(
+    source: &dyn TransactionSource,
+    pool: &Pool,
+    markets: &MarketRegistry,
+) -> anyhow::Result<()> {
+    loop {
+        tokio::time::sleep(IMPORT_POLL_INTERVAL).await;
+        let market_pks = claim_market_imports(
+            pool,
+            &PROGRAM_CONFIG.cluster,
+            IMPORT_BATCH_SIZE,
+            IMPORT_LEASE_SECS,
+            MAX_IMPORT_ATTEMPTS,
+        )
+        .await?;
+        if market_pks.is_empty() {
+            continue;
+        }
+
+        for market_pk in market_pks.iter() {
+            let result = match market_pk.parse::<Pubkey>() {
+                Ok(pubkey) => {
+                    find_market_creation(source, &pubkey, Some(MAX_IMPORT_SIGNATURE_PAGES)).await
+                }
+                Err(e) => Err(e.into()),
+            };
+            match result {
+                Ok(Some(market)) => {
+                    info!("Imported market {} ({})", market.market_name, market_pk);
+                    complete_market_import(pool, market).await?;
+                    METRIC_MARKET_IMPORTS_TOTAL
+                        .with_label_values(&["imported"])
+                        .inc();
+                }
+                Ok(None) => {
+                    warn!("could not find the creation of market {}", market_pk);
+                    record_market_import_failure(
+                        pool,
+                        market_pk,
+                        "market creation not found",
+                        IMPORT_RETRY_SECS,
+                    )
+                    .await?;
+                    METRIC_MARKET_IMPORTS_TOTAL
+                        .with_label_values(&["failed"])
+                        .inc();
+                }
+                Err(e) => {
+                    warn!("failed to import market {}: {:?}", market_pk, e);
+                    record_market_import_failure(
+                        pool,
+                        market_pk,
+                        &e.to_string(),
+                        IMPORT_RETRY_SECS,
+                    )
+                    .await?;
+                    METRIC_MARKET_IMPORTS_TOTAL
+                        .with_label_values(&["failed"])
+                        .inc();
+                }
+            }
+        }
+        // the imported markets are picked up by the periodic refresh otherwise
+        if let Err(e) = markets.refresh(pool).await {
+            warn!("failed to refresh markets after imports: {:?}", e);
+        }
+    }
+}
+
+/// Finds the metadata of a market by walking its signatures back to the oldest ones and
+/// decoding the `MarketMetaDataLog` emitted when the market was created. Fails if the market
+/// has more than `max_pages` pages of signatures.
+pub async fn find_market_creation(
+    source: &dyn TransactionSource,
+    market_pk: &Pubkey,
+    max_pages: Option<usize>,
+) -> anyhow::Result<Option<OpenBookMarketMetadata>> {
+    // the oldest page of signatures, newest first
+    let mut oldest_page = vec![];
+    let mut before: Option<Signature> = None;
+    let mut num_pages = 0;
+    loop {
+        if max_pages.is_some_and(|max_pages| num_pages == max_pages) {
+            return Err(anyhow::anyhow!(
+                "more than {} signatures before the market creation",
+                num_pages * IMPORT_PAGE_SIZE
+            ));
+        }
+        num_pages += 1;
+        let config = GetConfirmedSignaturesForAddress2Config {
+            before,
+            until: None,
+            limit: Some(IMPORT_PAGE_SIZE),
+            commitment: Some(CommitmentConfig::confirmed()),
+        };
+        let sigs = source.get_signatures_for_address(market_pk, config).await?;
+        if sigs.is_empty() {
+            break;
+        }
+        before = Some(sigs.last().unwrap().signature.parse::<Signature>()?);
+        let last_page = sigs.len() < IMPORT_PAGE_SIZE;
+        oldest_page = sigs;
+        if last_page {
+            break;
+        }
+    }
+
+    // the market can be referenced before it is created, so try the oldest transactions first
+    let signatures: Vec<Signature> = oldest_page
+        .iter()
+        .rev()
+        .filter(|s| s.err.is_none())
+        .map(|s| s.signature.parse::<Signature>())
+        .collect::<Result<_, _>>()?;
+    for chunk in signatures.chunks(IMPORT_FETCH_SIZE) {
+        let mut txns = source.get_transactions(chunk).await;
+        fill_missing_block_times(source, &mut txns).await;
+        for txn in txns.into_iter() {
+            let txn = txn?;
+            // transactions with malformed events are skipped
+            let decoded = match decode_transaction_events(&txn.transaction) {
+                Ok(decoded) => decoded,
+                Err(_) => continue,
+            };
+            for (_, event) in decoded {
+                if let OpenBookEvent::MarketMetaData(log) = event {
+                    if log.market == *market_pk {
+                        // fail the import so that it is retried rather than dated 1970
+                        let block_time = txn.block_time.ok_or_else(|| {
+                            anyhow::anyhow!("no block time for slot {}", txn.slot)
+                        })?;
+                        let block_datetime = to_timestampz(block_time as u64);
+                        return Ok(Some(OpenBookMarketMetadata::from_event(
+                            log,
+                            &transaction_program_id(&txn.transaction),
+                            block_datetime,
+                        )));
+                    }
+                }
+            }
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        scraper::source::ReplayTransactionSource,
+        structs::openbook_v2::MarketMetaDataLog,
+        test_utils::{market, program_logs, recorded_transaction},
+    };
+
+    #[tokio::test]
+    async fn market_creation_walk_is_bounded() {
+        let market = market();
+        let creation = MarketMetaDataLog {
+            market: market.market_pk.parse().unwrap(),
+            name: "SOL-USDC\0\0".to_string(),
+            base_mint: market.base_mint.parse().unwrap(),
+            quote_mint: market.quote_mint.parse().unwrap(),
+            base_decimals: 9,
+            quote_decimals: 6,
+            base_lot_size: 1_000_000,
+            quote_lot_size: 1,
+        };
+        let account_keys = [market.market_pk.clone()];
+        // the creation and a page of later transactions make two pages of signatures
+        let mut recorded = vec![recorded_transaction(
+            1,
+            Some(1_700_000_000),
+            &account_keys,
+            program_logs(&[creation]),
+        )];
+        recorded
+            .extend((2..=IMPORT_PAGE_SIZE as u64 + 1).map(|slot| {
+                recorded_transaction(slot, Some(1_700_000_000), &account_keys, vec![])
+            }));
+        let source = ReplayTransactionSource::from_transactions(recorded).unwrap();
+        let market_pk = market.market_pk.parse().unwrap();
+
+        assert!(find_market_creation(&source, &market_pk, Some(1))
+            .await
+            .is_err());
+        let found = find_market_creation(&source, &market_pk, Some(2))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.market_name, "SOL-USDC");
+        assert_eq!(found.creation_datetime, to_timestampz(1_700_000_000));
+        assert!(!found.scraper_active);
+    }
+}
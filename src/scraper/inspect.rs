@@ -0,0 +1,356 @@
+use solana_transaction_status::{
+    option_serializer::OptionSerializer, EncodedConfirmedTransactionWithStatusMeta,
+};
+use std::collections::HashMap;
+
+use crate::{
+    structs::{
+        inspected_transaction::{InspectedEvent, InspectedTransaction},
+        openbook_v2::{token_factor, ui_base_quantity, ui_quote_quantity, OpenBookMarketMetadata},
+    },
+    utils::to_timestampz,
+};
+
+use super::parsing::{parse_openbook_txns, undecoded_program_data, ParsedTransactions};
+
+/// Runs a transaction through the same parsing as the transaction scraper, without writing
+/// anything, and lists the events it decodes. Markets created in the transaction are used to
+/// scale its other events.
+pub fn inspect_transaction(
+    signature: &str,
+    txn: EncodedConfirmedTransactionWithStatusMeta,
+    markets: &HashMap<String, OpenBookMarketMetadata>,
+) -> InspectedTransaction {
+    let mut inspected = InspectedTransaction {
+        signature: signature.to_string(),
+        slot: txn.slot,
+        block_datetime: txn.block_time.map(|t| to_timestampz(t as u64).to_rfc3339()),
+        ..Default::default()
+    };
+    if let Some(meta) = &txn.transaction.meta {
+        if let OptionSerializer::Some(logs) = &meta.log_messages {
+            inspected.undecoded_program_data = undecoded_program_data(logs);
+        }
+    }
+
+    // a first pass finds the markets created in the transaction
+    let mut txns = vec![Ok(txn)];
+    let created = parse_openbook_txns(&mut txns, vec![signature.to_string()], markets, false);
+    let mut markets = markets.clone();
+    for market in created.new_markets.iter() {
+        markets
+            .entry(market.market_pk.clone())
+            .or_insert_with(|| market.clone());
+    }
+
+    let ParsedTransactions {
+        fills,
+        new_markets,
+        events,
+        truncated_sigs,
+        failed_sigs,
+        unknown_markets,
+        ..
+    } = parse_openbook_txns(&mut txns, vec![signature.to_string()], &markets, false);
+    inspected.log_truncated = !truncated_sigs.is_empty();
+    inspected.error = failed_sigs.first().map(|(_, e)| e.to_string());
+    inspected.unknown_markets = unknown_markets;
+
+    for market in new_markets.into_iter() {
+        inspected.events.push(InspectedEvent {
+            log_index: None,
+            kind: "MarketMetaData",
+            market_pk: Some(market.market_pk),
+            fields: vec![
+                ("name", market.market_name),
+                ("base_mint", market.base_mint),
+                ("quote_mint", market.quote_mint),
+                ("base_decimals", market.base_decimals.to_string()),
+                ("quote_decimals", market.quote_decimals.to_string()),
+                ("base_lot_size", market.base_lot_size.to_string()),
+                ("quote_lot_size", market.quote_lot_size.to_string()),
+            ],
+        });
+    }
+    for fill in fills.into_iter() {
+        inspected.events.push(InspectedEvent {
+            log_index: Some(fill.log_index),
+            kind: "Fill",
+            market_pk: Some(fill.market_pk),
+            fields: vec![
+                ("seq_num", fill.seq_num.to_string()),
+                ("price", fill.price.to_string()),
+                ("quantity", fill.quantity.to_string()),
+                (
+                    "taker_side",
+                    if fill.taker_side == 0 { "bid" } else { "ask" }.to_string(),
+                ),
+                ("maker", fill.maker),
+                (
+                    "maker_client_order_id",
+                    fill.maker_client_order_id.to_string(),
+                ),
+                ("maker_fee", fill.maker_fee.to_string()),
+                ("maker_out", fill.maker_out.to_string()),
+                ("taker", fill.taker),
+                (
+                    "taker_client_order_id",
+                    fill.taker_client_order_id.to_string(),
+                ),
+                ("taker_fee", fill.taker_fee.to_string()),
+            ],
+        });
+    }
+    for (ctx, e) in events.deposits.into_iter() {
+        inspected.events.push(InspectedEvent {
+            log_index: Some(ctx.log_index),
+            kind: "Deposit",
+            market_pk: None,
+            fields: vec![
+                ("open_orders_account", e.open_orders_account.to_string()),
+                ("signer", e.signer.to_string()),
+                ("base_amount_native", e.base_amount.to_string()),
+                ("quote_amount_native", e.quote_amount.to_string()),
+            ],
+        });
+    }
+    for (ctx, e) in events.settle_funds.into_iter() {
+        inspected.events.push(InspectedEvent {
+            log_index: Some(ctx.log_index),
+            kind: "SettleFunds",
+            market_pk: None,
+            fields: vec![
+                ("open_orders_account", e.open_orders_account.to_string()),
+                ("base_native", e.base_native.to_string()),
+                ("quote_native", e.quote_native.to_string()),
+                ("referrer_rebate_native", e.referrer_rebate.to_string()),
+                ("referrer", optional_to_string(e.referrer)),
+            ],
+        });
+    }
+    for (ctx, e) in events.sweep_fees.into_iter() {
+        let market = markets.get(&e.market.to_string());
+        inspected.events.push(InspectedEvent {
+            log_index: Some(ctx.log_index),
+            kind: "SweepFees",
+            market_pk: Some(e.market.to_string()),
+            fields: vec![
+                quote_native_field("amount", e.amount as u128, market),
+                ("receiver", e.receiver.to_string()),
+            ],
+        });
+    }
+    for (ctx, e) in events.open_orders_positions.into_iter() {
+        let market = markets.get(&e.market.to_string());
+        inspected.events.push(InspectedEvent {
+            log_index: Some(ctx.log_index),
+            kind: "OpenOrdersPosition",
+            market_pk: Some(e.market.to_string()),
+            fields: vec![
+                ("owner", e.owner.to_string()),
+                (
+                    "open_orders_account_num",
+                    e.open_orders_account_num.to_string(),
+                ),
+                base_lots_field("bids_base", e.bids_base_lots, market),
+                quote_lots_field("bids_quote", e.bids_quote_lots, market),
+                base_lots_field("asks_base", e.asks_base_lots, market),
+                base_native_field("base_free", e.base_free_native as u128, market),
+                quote_native_field("quote_free", e.quote_free_native as u128, market),
+                quote_native_field("locked_maker_fees", e.locked_maker_fees as u128, market),
+                quote_native_field(
+                    "referrer_rebates_available",
+                    e.referrer_rebates_available as u128,
+                    market,
+                ),
+                quote_native_field("maker_volume", e.maker_volume, market),
+                quote_native_field("taker_volume", e.taker_volume, market),
+            ],
+        });
+    }
+    for (ctx, e) in events.set_delegates.into_iter() {
+        inspected.events.push(InspectedEvent {
+            log_index: Some(ctx.log_index),
+            kind: "SetDelegate",
+            market_pk: None,
+            fields: vec![
+                ("open_orders_account", e.open_orders_account.to_string()),
+                ("delegate", optional_to_string(e.delegate)),
+            ],
+        });
+    }
+    for (ctx, e) in events.total_order_fills.into_iter() {
+        inspected.events.push(InspectedEvent {
+            log_index: Some(ctx.log_index),
+            kind: "TotalOrderFill",
+            market_pk: None,
+            fields: vec![
+                ("side", if e.side == 0 { "bid" } else { "ask" }.to_string()),
+                ("taker", e.taker.to_string()),
+                (
+                    "total_quantity_paid_native",
+                    e.total_quantity_paid.to_string(),
+                ),
+                (
+                    "total_quantity_received_native",
+                    e.total_quantity_received.to_string(),
+                ),
+                ("fees_native", e.fees.to_string()),
+            ],
+        });
+    }
+    // market creations first, then in the order the events were emitted
+    inspected.events.sort_by_key(|e| e.log_index);
+    inspected
+}
+
+fn optional_to_string<T: ToString>(value: Option<T>) -> String {
+    value.map_or("-".to_string(), |v| v.to_string())
+}
+
+fn base_lots_field(
+    name: &'static str,
+    lots: i64,
+    market: Option<&OpenBookMarketMetadata>,
+) -> (&'static str, String) {
+    match market {
+        Some(m) => (name, ui_base_quantity(lots, m).to_string()),
+        None => (name, format!("{} lots", lots)),
+    }
+}
+
+fn quote_lots_field(
+    name: &'static str,
+    lots: i64,
+    market: Option<&OpenBookMarketMetadata>,
+) -> (&'static str, String) {
+    match market {
+        Some(m) => (name, ui_quote_quantity(lots, m).to_string()),
+        None => (name, format!("{} lots", lots)),
+    }
+}
+
+fn base_native_field(
+    name: &'static str,
+    native: u128,
+    market: Option<&OpenBookMarketMetadata>,
+) -> (&'static str, String) {
+    match market {
+        Some(m) => (
+            name,
+            (native as f64 / token_factor(m.base_decimals)).to_string(),
+        ),
+        None => (name, format!("{} native", native)),
+    }
+}
+
+fn quote_native_field(
+    name: &'static str,
+    native: u128,
+    market: Option<&OpenBookMarketMetadata>,
+) -> (&'static str, String) {
+    match market {
+        Some(m) => (
+            name,
+            (native as f64 / token_factor(m.quote_decimals)).to_string(),
+        ),
+        None => (name, format!("{} native", native)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        structs::openbook_v2::MarketMetaDataLog,
+        test_utils::{fill_log, fill_transaction, market, program_logs, recorded_transaction},
+    };
+    use anchor_lang::__private::base64;
+
+    fn field<'a>(event: &'a InspectedEvent, name: &str) -> &'a str {
+        &event.fields.iter().find(|(n, _)| *n == name).unwrap().1
+    }
+
+    #[test]
+    fn fills_of_known_markets_are_scaled() {
+        let market = market();
+        let txn = fill_transaction(
+            &market,
+            100,
+            Some(1_700_000_000),
+            &[fill_log(&market, 7, 20_000, 10)],
+        );
+        let markets = HashMap::from([(market.market_pk.clone(), market.clone())]);
+
+        let inspected = inspect_transaction("sig", txn, &markets);
+
+        assert_eq!(inspected.slot, 100);
+        assert_eq!(
+            inspected.block_datetime.as_deref(),
+            Some("2023-11-14T22:13:20+00:00")
+        );
+        assert_eq!(inspected.error, None);
+        assert_eq!(inspected.events.len(), 1);
+        let fill = &inspected.events[0];
+        assert_eq!(fill.kind, "Fill");
+        assert_eq!(fill.market_pk.as_deref(), Some(market.market_pk.as_str()));
+        assert_eq!(field(fill, "seq_num"), "7");
+        assert_eq!(field(fill, "price"), "20");
+        assert_eq!(field(fill, "quantity"), "0.01");
+    }
+
+    #[test]
+    fn markets_created_in_the_transaction_are_listed_and_used() {
+        let market = market();
+        let creation = MarketMetaDataLog {
+            market: market.market_pk.parse().unwrap(),
+            name: "SOL-USDC".to_string(),
+            base_mint: market.base_mint.parse().unwrap(),
+            quote_mint: market.quote_mint.parse().unwrap(),
+            base_decimals: 9,
+            quote_decimals: 6,
+            base_lot_size: 1_000_000,
+            quote_lot_size: 1,
+        };
+        let mut logs = program_logs(&[creation]);
+        logs.pop();
+        logs.extend(
+            program_logs(&[fill_log(&market, 1, 20_000, 10)])
+                .into_iter()
+                .skip(1),
+        );
+        let txn = recorded_transaction(
+            100,
+            Some(1_700_000_000),
+            std::slice::from_ref(&market.market_pk),
+            logs,
+        );
+
+        let inspected = inspect_transaction("sig", txn, &HashMap::new());
+
+        let kinds: Vec<&str> = inspected.events.iter().map(|e| e.kind).collect();
+        assert_eq!(kinds, vec!["MarketMetaData", "Fill"]);
+        assert_eq!(field(&inspected.events[0], "name"), "SOL-USDC");
+        assert_eq!(field(&inspected.events[1], "price"), "20");
+        assert!(inspected.unknown_markets.is_empty());
+    }
+
+    #[test]
+    fn transactions_without_block_time_are_reported() {
+        let market = market();
+        let mut logs = program_logs(&[fill_log(&market, 1, 20_000, 10)]);
+        logs.insert(1, format!("Program data: {}", base64::encode([1u8; 12])));
+        let txn = recorded_transaction(100, None, std::slice::from_ref(&market.market_pk), logs);
+        let markets = HashMap::from([(market.market_pk.clone(), market.clone())]);
+
+        let inspected = inspect_transaction("sig", txn, &markets);
+
+        assert_eq!(inspected.block_datetime, None);
+        assert_eq!(
+            inspected.error.as_deref(),
+            Some("no block time for slot 100")
+        );
+        assert!(inspected.events.is_empty());
+        assert_eq!(inspected.undecoded_program_data.len(), 1);
+    }
+}
@@ -1,62 +1,179 @@
-
-use openbook_offchain_services::database::fetch::fetch_active_markets;
-
-
-use openbook_offchain_services::scraper::scrape::{scrape_signatures, scrape_transactions};
-
-use openbook_offchain_services::structs::openbook_v2::OpenBookMarketMetadata;
-use openbook_offchain_services::structs::transaction::NUM_TRANSACTION_PARTITIONS;
-
-use openbook_offchain_services::worker::metrics::{
-    serve_metrics,
-};
-use openbook_offchain_services::{
-    database::initialize::{connect_to_database, setup_database},
+use log::error;
+use openbook_offchain_services::database::initialize::{connect_to_database, setup_database};
+use openbook_offchain_services::scraper::audit::audit_fill_gaps;
+use openbook_offchain_services::scraper::backfill::{backfill_signatures, BackfillConfig};
+use openbook_offchain_services::scraper::block_scan::{scan_blocks, BlockScanConfig};
+use openbook_offchain_services::scraper::finalization::reconcile_finalized_fills;
+use openbook_offchain_services::scraper::import::import_unknown_markets;
+use openbook_offchain_services::scraper::scrape::{
+    scrape_signatures, scrape_transactions, signature_addresses, ClaimConfig,
 };
-
-use std::{collections::HashMap};
+use openbook_offchain_services::scraper::source::transaction_source_from_env;
+use openbook_offchain_services::scraper::stream::stream_fills;
+use openbook_offchain_services::utils::{market_registry::MarketRegistry, PROGRAM_CONFIG};
+use openbook_offchain_services::worker::metrics::serve_metrics;
 
 #[tokio::main(flavor = "multi_thread", worker_threads = 10)]
 async fn main() -> anyhow::Result<()> {
     env_logger::init();
     dotenv::dotenv().ok();
 
-    let rpc_url: String = dotenv::var("RPC_URL").unwrap();
     let pool = connect_to_database().await?;
     setup_database(&pool).await?;
-    let mut handles = vec![];
+    let mut tasks = vec![];
 
-    // fetch markets
-    let markets = fetch_active_markets(&pool).await?;
-    let target_markets: HashMap<String, OpenBookMarketMetadata> = markets
-        .into_iter()
-        .map(|m| (m.market_pk.clone(), m))
-        .collect();
-
-    // signature scraping
-    let rpc_clone = rpc_url.clone();
+    // fetch markets, and keep them up to date while scraping
+    let markets =
+        MarketRegistry::load_for_ingestion(&pool, Some(PROGRAM_CONFIG.cluster.clone())).await?;
+    let refresh_markets = markets.clone();
     let pool_clone = pool.clone();
-    handles.push(tokio::spawn(async move {
-        scrape_signatures(rpc_clone, &pool_clone).await.unwrap();
-    }));
+    tasks.push((
+        "market refresh".to_string(),
+        tokio::spawn(async move { refresh_markets.refresh_periodically(&pool_clone).await }),
+    ));
+
+    let source = transaction_source_from_env()?;
+
+    // signature scraping, one task per program or per scraped market address
+    for (address, program_id) in signature_addresses()? {
+        let source_clone = source.clone();
+        let pool_clone = pool.clone();
+        tasks.push((
+            format!("signature scraping of {}", address),
+            tokio::spawn(async move {
+                scrape_signatures(source_clone.as_ref(), &pool_clone, address, program_id).await
+            }),
+        ));
+    }
+
+    // historical signature backfill, runs alongside live scraping
+    for backfill_config in BackfillConfig::from_env()?.into_iter() {
+        let source_clone = source.clone();
+        let pool_clone = pool.clone();
+        tasks.push((
+            "signature backfill".to_string(),
+            tokio::spawn(async move {
+                backfill_signatures(source_clone.as_ref(), &pool_clone, backfill_config).await
+            }),
+        ));
+    }
 
-    // transaction scraping
-    for id in 0..NUM_TRANSACTION_PARTITIONS {
-        let rpc_clone = rpc_url.clone();
+    // transaction scraping, sharing the backlog with any other scraper processes
+    let claim_config = ClaimConfig::from_env()?;
+    for task in 0..claim_config.tasks {
+        let claim_config = claim_config.clone();
+        let source_clone = source.clone();
         let pool_clone = pool.clone();
-        let markets_clone = target_markets.clone();
-        handles.push(tokio::spawn(async move {
-            scrape_transactions(id as i32, rpc_clone, &pool_clone, &markets_clone)
+        let markets_clone = markets.clone();
+        tasks.push((
+            format!("transaction scraping {}", task),
+            tokio::spawn(async move {
+                scrape_transactions(
+                    task,
+                    &claim_config,
+                    source_clone.as_ref(),
+                    &pool_clone,
+                    &markets_clone,
+                )
                 .await
-                .unwrap();
-        }));
+            }),
+        ));
+    }
+
+    // optional block scanning, reading every block of a slot range instead of listing signatures
+    if let Some(block_scan_config) = BlockScanConfig::from_env()? {
+        let claim_config = claim_config.clone();
+        let source_clone = source.clone();
+        let pool_clone = pool.clone();
+        let markets_clone = markets.clone();
+        tasks.push((
+            "block scan".to_string(),
+            tokio::spawn(async move {
+                scan_blocks(
+                    source_clone.as_ref(),
+                    &pool_clone,
+                    &markets_clone,
+                    block_scan_config,
+                    &claim_config,
+                )
+                .await
+            }),
+        ));
+    }
+
+    // import of markets first seen in fills, when every market is scraped
+    if markets.all_markets() {
+        let source_clone = source.clone();
+        let pool_clone = pool.clone();
+        let markets_clone = markets.clone();
+        tasks.push((
+            "market import".to_string(),
+            tokio::spawn(async move {
+                import_unknown_markets(source_clone.as_ref(), &pool_clone, &markets_clone).await
+            }),
+        ));
     }
 
-    handles.push(tokio::spawn(async move {
-        serve_metrics().await.unwrap().await.unwrap();
-    }));
+    // finalization of fills ingested at confirmed commitment
+    let source_clone = source.clone();
+    let pool_clone = pool.clone();
+    tasks.push((
+        "fill finalization".to_string(),
+        tokio::spawn(
+            async move { reconcile_finalized_fills(source_clone.as_ref(), &pool_clone).await },
+        ),
+    ));
 
-    futures::future::join_all(handles).await;
+    // audit of the fill seq_num of each market, re-scraping any gaps
+    let source_clone = source.clone();
+    let pool_clone = pool.clone();
+    let markets_clone = markets.clone();
+    tasks.push((
+        "fill audit".to_string(),
+        tokio::spawn(async move {
+            audit_fill_gaps(source_clone.as_ref(), &pool_clone, &markets_clone).await
+        }),
+    ));
 
-    Ok(())
+    // optional low latency fills from the logs subscription
+    if let Ok(ws_url) = dotenv::var("WS_URL") {
+        for program_id in PROGRAM_CONFIG.program_ids.iter().copied() {
+            let ws_url = ws_url.clone();
+            let source_clone = source.clone();
+            let pool_clone = pool.clone();
+            let markets_clone = markets.clone();
+            tasks.push((
+                format!("fill stream of {}", program_id),
+                tokio::spawn(async move {
+                    stream_fills(
+                        ws_url,
+                        program_id,
+                        source_clone.as_ref(),
+                        &pool_clone,
+                        &markets_clone,
+                    )
+                    .await
+                }),
+            ));
+        }
+    }
+
+    tasks.push((
+        "metrics server".to_string(),
+        tokio::spawn(async move {
+            serve_metrics().await?.await?;
+            Ok(())
+        }),
+    ));
+
+    // every task runs for the life of the process, so the scraper stops once any of them ends
+    let (names, handles): (Vec<String>, Vec<_>) = tasks.into_iter().unzip();
+    let (result, index, _) = futures::future::select_all(handles).await;
+    let name = &names[index];
+    match result {
+        Ok(Ok(())) => error!("{} task stopped", name),
+        Ok(Err(e)) => error!("{} task failed: {:?}", name, e),
+        Err(e) => error!("{} task panicked: {}", name, e),
+    }
+    Err(anyhow::anyhow!("{} task ended, shutting down", name))
 }
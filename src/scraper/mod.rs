@@ -1,2 +1,13 @@
+pub mod archive;
+pub mod audit;
+pub mod backfill;
+pub mod block_scan;
+pub mod events;
+pub mod finalization;
+pub mod import;
+pub mod inspect;
 pub mod parsing;
+pub mod rpc_pool;
 pub mod scrape;
+pub mod source;
+pub mod stream;
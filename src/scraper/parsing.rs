@@ -1,104 +1,325 @@
+use anchor_lang::event::EVENT_IX_TAG_LE;
 use log::warn;
 use solana_client::client_error::Result as ClientResult;
 use solana_transaction_status::{
-    option_serializer::OptionSerializer, EncodedConfirmedTransactionWithStatusMeta, UiInstruction,
-    UiParsedInstruction, UiTransactionStatusMeta,
+    option_serializer::OptionSerializer, EncodedConfirmedTransactionWithStatusMeta,
+    EncodedTransaction, EncodedTransactionWithStatusMeta, UiInstruction, UiMessage,
+    UiParsedInstruction,
 };
-use std::{collections::HashMap, io::Error};
+use std::collections::{HashMap, HashSet};
 
 use crate::{
-    structs::openbook_v2::{FillLog, MarketMetaDataLog, OpenBookFill, OpenBookMarketMetadata},
-    utils::to_timestampz,
-    worker::metrics::METRIC_RPC_ERRORS_TOTAL,
+    structs::{
+        openbook_v2::{EventContext, OpenBookEvents, OpenBookFill, OpenBookMarketMetadata},
+        parse_error::ParseError,
+    },
+    utils::{to_timestampz, PROGRAM_CONFIG},
+    worker::metrics::{METRIC_RPC_ERRORS_TOTAL, METRIC_TRUNCATED_LOGS_TOTAL},
 };
 
+use super::events::{OpenBookEvent, OPENBOOK_EVENTS};
+
+const PROGRAM: &str = "Program ";
 const PROGRAM_DATA: &str = "Program data: ";
+const INVOKE: &str = "invoke [";
+const SUCCESS: &str = "success";
+const FAILED: &str = "failed";
+const LOG_TRUNCATED: &str = "Log truncated";
+
+/// What `parse_openbook_txns` read from a batch of fetched transactions.
+pub struct ParsedTransactions {
+    pub fills: Vec<OpenBookFill>,
+    /// Markets created in the transactions.
+    pub new_markets: Vec<OpenBookMarketMetadata>,
+    pub events: OpenBookEvents,
+    /// Signatures that were fetched and parsed successfully.
+    pub completed_sigs: Vec<String>,
+    /// The completed signatures whose logs were truncated.
+    pub truncated_sigs: Vec<String>,
+    /// Signatures that could not be fetched or parsed, along with their error.
+    pub failed_sigs: Vec<(String, ParseError)>,
+    /// Markets with fills that are not in the target markets.
+    pub unknown_markets: Vec<String>,
+}
 
+/// Parses the fetched transactions. Transactions missing their block time should have it
+/// filled in with `fill_missing_block_times` first.
+///
+/// Fills of unknown markets are dropped, unless `fail_unknown_markets` is set. Transactions
+/// with such fills then fail as a whole, so they are retried once the market is imported.
 pub fn parse_openbook_txns(
-    txns: &mut Vec<ClientResult<EncodedConfirmedTransactionWithStatusMeta>>,
+    txns: &mut [ClientResult<EncodedConfirmedTransactionWithStatusMeta>],
     mut sig_strings: Vec<String>,
     target_markets: &HashMap<String, OpenBookMarketMetadata>,
-) -> (Vec<OpenBookFill>, Vec<OpenBookMarketMetadata>, Vec<String>) {
+    fail_unknown_markets: bool,
+) -> ParsedTransactions {
     let mut fills_vector = Vec::<OpenBookFill>::new();
     let mut markets_vector = Vec::<OpenBookMarketMetadata>::new();
+    let mut events = OpenBookEvents::default();
     let mut failed_sigs = vec![];
+    let mut truncated_sigs = vec![];
+    let mut unknown_markets = HashSet::new();
     for (idx, txn) in txns.iter_mut().enumerate() {
         match txn {
             Ok(t) => {
-                if let Some(m) = &t.transaction.meta {
-                    let maybe_new_market = try_parse_new_market(m, t.block_time.unwrap());
-                    match &m.log_messages {
-                        OptionSerializer::Some(logs) => {
-                            match try_parse_openbook_fills_from_logs(
-                                logs,
-                                target_markets,
-                                t.block_time.unwrap(),
-                                t.slot,
-                            ) {
-                                Some(mut events) => fills_vector.append(&mut events),
-                                None => {}
-                            }
-                        }
-                        OptionSerializer::None => {}
-                        OptionSerializer::Skip => {}
+                let block_time = match t.block_time {
+                    Some(block_time) => block_time,
+                    None => {
+                        warn!("transaction {} has no block time", sig_strings[idx]);
+                        failed_sigs.push((
+                            sig_strings[idx].clone(),
+                            ParseError::MissingBlockTime { slot: t.slot },
+                        ));
+                        continue;
+                    }
+                };
+                let m = match &t.transaction.meta {
+                    Some(m) => m,
+                    None => {
+                        warn!("transaction {} has no status meta", sig_strings[idx]);
+                        failed_sigs.push((sig_strings[idx].clone(), ParseError::MissingMeta));
+                        continue;
+                    }
+                };
+                let decoded = match decode_transaction_events(&t.transaction) {
+                    Ok(decoded) => decoded,
+                    Err(e) => {
+                        warn!("failed to decode transaction {}: {}", sig_strings[idx], e);
+                        failed_sigs.push((sig_strings[idx].clone(), e));
+                        continue;
                     }
-                    match maybe_new_market {
-                        Some(mut markets) => markets_vector.append(&mut markets),
-                        None => {}
+                };
+                let program_pk = transaction_program_id(&t.transaction);
+                let sorted = sort_openbook_events(
+                    decoded,
+                    target_markets,
+                    &sig_strings[idx],
+                    &program_pk,
+                    block_time,
+                    t.slot,
+                );
+                let (mut fills, mut markets, mut other_events, unknown) = match sorted {
+                    Ok(sorted) => sorted,
+                    Err(e) => {
+                        warn!("failed to parse transaction {}: {}", sig_strings[idx], e);
+                        failed_sigs.push((sig_strings[idx].clone(), e));
+                        continue;
+                    }
+                };
+                if fail_unknown_markets && !unknown.is_empty() {
+                    unknown_markets.extend(unknown.iter().cloned());
+                    failed_sigs.push((
+                        sig_strings[idx].clone(),
+                        ParseError::UnknownMarkets(unknown),
+                    ));
+                    continue;
+                }
+                unknown_markets.extend(unknown);
+                if let OptionSerializer::Some(logs) = &m.log_messages {
+                    if logs_truncated(logs) {
+                        METRIC_TRUNCATED_LOGS_TOTAL.inc();
+                        truncated_sigs.push(sig_strings[idx].clone());
                     }
                 }
+                fills_vector.append(&mut fills);
+                markets_vector.append(&mut markets);
+                events.append(&mut other_events);
             }
             Err(e) => {
                 warn!("rpc error in get_transaction {}", e);
-                failed_sigs.push(sig_strings[idx].clone());
+                failed_sigs.push((sig_strings[idx].clone(), ParseError::Rpc(e.to_string())));
                 METRIC_RPC_ERRORS_TOTAL
                     .with_label_values(&["getTransaction"])
                     .inc();
             }
         }
     }
-    sig_strings.retain(|s| !failed_sigs.contains(s));
-    (fills_vector, markets_vector, sig_strings)
+    sig_strings.retain(|s| !failed_sigs.iter().any(|(f, _)| f == s));
+    ParsedTransactions {
+        fills: fills_vector,
+        new_markets: markets_vector,
+        events,
+        completed_sigs: sig_strings,
+        truncated_sigs,
+        failed_sigs,
+        unknown_markets: unknown_markets.into_iter().collect(),
+    }
+}
+
+/// Solana stops recording logs past the log size limit and ends them with "Log truncated", so
+/// events after that point are missing from the `Program data:` lines.
+pub fn logs_truncated(logs: &[String]) -> bool {
+    logs.iter().any(|l| l == LOG_TRUNCATED)
+}
+
+/// Decodes the events of a transaction from both the `Program data:` log lines and the
+/// `emit_cpi!` inner instructions. Logs are truncated on busy transactions while the inner
+/// instructions are not, so events missing from the logs are recovered from the instructions.
+/// An event present in both places is only returned once. Events are indexed in log order,
+/// followed by the events only found in the inner instructions. Fails on the first malformed
+/// event, since the fills of a transaction are only complete if all of its events decode.
+pub fn decode_transaction_events(
+    txn: &EncodedTransactionWithStatusMeta,
+) -> Result<Vec<(u32, OpenBookEvent)>, ParseError> {
+    let log_payloads = match txn.meta.as_ref().map(|m| &m.log_messages) {
+        Some(OptionSerializer::Some(logs)) => program_data_payloads(logs),
+        _ => vec![],
+    };
+    let cpi_payloads = cpi_event_payloads(txn);
+    decode_payloads(merge_event_payloads(log_payloads, cpi_payloads))
+}
+
+/// Decodes the `Program data:` lines of the configured programs with the event registry. Each
+/// event is returned with the index of its line among those program data lines.
+pub fn decode_program_data_logs(logs: &[String]) -> Result<Vec<(u32, OpenBookEvent)>, ParseError> {
+    decode_payloads(program_data_payloads(logs))
+}
+
+/// Returns the `Program data:` lines that don't decode to a known event, with the same index
+/// as `decode_program_data_logs` gives them. Lines that aren't valid base64 have no index.
+pub fn undecoded_program_data(logs: &[String]) -> Vec<(Option<u32>, String)> {
+    let mut undecoded = vec![];
+    let mut log_index = 0;
+    for log in program_data_lines(logs).into_iter() {
+        match anchor_lang::__private::base64::decode(log) {
+            Ok(payload) => {
+                if !matches!(OPENBOOK_EVENTS.decode(&payload, log_index), Ok(Some(_))) {
+                    undecoded.push((Some(log_index), log.to_string()));
+                }
+                log_index += 1;
+            }
+            Err(_) => undecoded.push((None, log.to_string())),
+        }
+    }
+    undecoded
+}
+
+fn decode_payloads(payloads: Vec<Vec<u8>>) -> Result<Vec<(u32, OpenBookEvent)>, ParseError> {
+    let mut events = vec![];
+    for (log_index, payload) in payloads.iter().enumerate() {
+        if let Some(event) = OPENBOOK_EVENTS.decode(payload, log_index as u32)? {
+            events.push((log_index as u32, event));
+        }
+    }
+    Ok(events)
+}
+
+fn program_data_payloads(logs: &[String]) -> Vec<Vec<u8>> {
+    program_data_lines(logs)
+        .into_iter()
+        .filter_map(|log| anchor_lang::__private::base64::decode(log).ok())
+        .collect()
+}
+
+/// Returns the data of the `Program data:` lines logged by the configured programs. The
+/// `invoke` and `success`/`failed` lines are followed to know which program logged a line, so
+/// that data logged by other programs, including ones the configured programs call, is left out.
+fn program_data_lines(logs: &[String]) -> Vec<&str> {
+    let mut invoked: Vec<&str> = vec![];
+    let mut lines = vec![];
+    for log in logs.iter() {
+        if let Some(data) = log.strip_prefix(PROGRAM_DATA) {
+            if invoked.last().is_some_and(|p| PROGRAM_CONFIG.is_program(p)) {
+                lines.push(data);
+            }
+            continue;
+        }
+        let (program_id, status) = match log.strip_prefix(PROGRAM).and_then(|l| l.split_once(' ')) {
+            Some(split) => split,
+            None => continue,
+        };
+        if status.starts_with(INVOKE) {
+            invoked.push(program_id);
+        } else if status == SUCCESS || status.starts_with(FAILED) {
+            invoked.pop();
+        }
+    }
+    lines
+}
+
+/// Returns the event payloads of the `emit_cpi!` self-invocations of the configured programs,
+/// with the event instruction tag stripped.
+fn cpi_event_payloads(txn: &EncodedTransactionWithStatusMeta) -> Vec<Vec<u8>> {
+    let inner_instructions = match txn.meta.as_ref().map(|m| &m.inner_instructions) {
+        Some(OptionSerializer::Some(ixs)) => ixs,
+        _ => return vec![],
+    };
+    let account_keys = transaction_account_keys(txn);
+
+    let mut payloads = vec![];
+    for i in inner_instructions
+        .iter()
+        .flat_map(|x| x.instructions.iter())
+    {
+        let (program_id, data) = match i {
+            UiInstruction::Compiled(ix) => (
+                account_keys.get(ix.program_id_index as usize),
+                ix.data.as_str(),
+            ),
+            UiInstruction::Parsed(UiParsedInstruction::PartiallyDecoded(ix)) => {
+                (Some(&ix.program_id), ix.data.as_str())
+            }
+            UiInstruction::Parsed(UiParsedInstruction::Parsed(_)) => continue,
+        };
+        if !program_id.is_some_and(|p| PROGRAM_CONFIG.is_program(p)) {
+            continue;
+        }
+        let bytes = match bs58::decode(data).into_vec() {
+            Ok(b) => b,
+            Err(_) => continue,
+        };
+        if let Some(payload) = bytes.strip_prefix(&EVENT_IX_TAG_LE) {
+            payloads.push(payload.to_vec());
+        }
+    }
+    payloads
+}
+
+/// Keeps every log payload and adds the CPI payloads that the logs don't account for. Payloads
+/// are compared byte for byte, so an event emitted twice with the same contents is kept twice.
+fn merge_event_payloads(log_payloads: Vec<Vec<u8>>, cpi_payloads: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+    let mut unmatched: HashMap<&[u8], usize> = HashMap::new();
+    for payload in log_payloads.iter() {
+        *unmatched.entry(payload.as_slice()).or_default() += 1;
+    }
+    let mut missing = vec![];
+    for payload in cpi_payloads.into_iter() {
+        match unmatched.get_mut(payload.as_slice()) {
+            Some(count) if *count > 0 => *count -= 1,
+            _ => missing.push(payload),
+        }
+    }
+    let mut payloads = log_payloads;
+    payloads.append(&mut missing);
+    payloads
 }
 
 pub fn try_parse_openbook_fills_from_logs(
-    logs: &Vec<String>,
+    logs: &[String],
     target_markets: &HashMap<String, OpenBookMarketMetadata>,
+    signature: &str,
     block_time: i64,
     slot: u64,
 ) -> Option<Vec<OpenBookFill>> {
-    let mut fills_vector = Vec::<OpenBookFill>::new();
-    for l in logs.iter() {
-        match l.strip_prefix(PROGRAM_DATA) {
-            Some(log) => {
-                let borsh_bytes = match anchor_lang::__private::base64::decode(log) {
-                    Ok(borsh_bytes) => borsh_bytes,
-                    _ => continue,
+    let block_datetime = to_timestampz(block_time as u64);
+    // transactions with malformed events are left to the transaction scraper, which records them
+    let fills_vector: Vec<OpenBookFill> = decode_program_data_logs(logs)
+        .ok()?
+        .into_iter()
+        .filter_map(|(log_index, event)| match event {
+            OpenBookEvent::Fill(f) => target_markets.get(&f.market.to_string()).map(|market| {
+                let ctx = EventContext {
+                    signature: signature.to_string(),
+                    log_index,
+                    slot,
+                    block_datetime,
                 };
-                let mut slice: &[u8] = &borsh_bytes[8..];
-                let fill_log: Result<FillLog, Error> =
-                    anchor_lang::AnchorDeserialize::deserialize(&mut slice);
-
-                match fill_log {
-                    Ok(f) => {
-                        if target_markets.contains_key(&f.market.to_string()) {
-                            let market_metadata =
-                                target_markets.get(&f.market.to_string()).unwrap();
-                            let fill_event = OpenBookFill::from_log(
-                                f,
-                                market_metadata,
-                                slot,
-                                to_timestampz(block_time as u64),
-                            );
-                            fills_vector.push(fill_event);
-                        }
-                    }
-                    _ => continue,
-                }
-            }
-            _ => (),
-        }
-    }
+                OpenBookFill::from_log(f, market, &ctx)
+            }),
+            _ => None,
+        })
+        .collect::<Result<_, _>>()
+        .ok()?;
 
     if !fills_vector.is_empty() {
         Some(fills_vector)
@@ -107,86 +328,311 @@ pub fn try_parse_openbook_fills_from_logs(
     }
 }
 
-fn try_parse_new_market(
-    txn_meta: &UiTransactionStatusMeta,
+/// Fills, created markets, other events and unknown markets of a transaction, see
+/// `sort_openbook_events`.
+pub type SortedEvents = (
+    Vec<OpenBookFill>,
+    Vec<OpenBookMarketMetadata>,
+    OpenBookEvents,
+    Vec<String>,
+);
+
+/// Splits the decoded events of a transaction into fills of the target markets, newly
+/// created markets and the events that go to the other event tables. Also returns the markets
+/// of the fills that were left out because they are not target markets. Fails if a fill of a
+/// target market is malformed.
+pub fn sort_openbook_events(
+    decoded: Vec<(u32, OpenBookEvent)>,
+    target_markets: &HashMap<String, OpenBookMarketMetadata>,
+    signature: &str,
+    program_pk: &str,
     block_time: i64,
-) -> Option<Vec<OpenBookMarketMetadata>> {
-    let mut markets_vector = Vec::<OpenBookMarketMetadata>::new();
-    match &txn_meta.log_messages {
-        OptionSerializer::Some(logs) => {
-            if logs.iter().any(|s| s.contains("CreateMarket")) {
-                match &txn_meta.inner_instructions {
-                    OptionSerializer::Some(ixs) => {
-                        ixs.iter().for_each(|x| {
-                            x.instructions.iter().for_each(|i| match i {
-                                UiInstruction::Compiled(ix) => {
-                                    let maybe_market =
-                                        parse_market_from_data(ix.data.clone(), block_time);
-                                    match maybe_market {
-                                        Some(market) => markets_vector.push(market),
-                                        None => {}
-                                    }
-                                }
-                                UiInstruction::Parsed(ix) => match ix {
-                                    UiParsedInstruction::Parsed(x) => match x.parsed.get("data") {
-                                        Some(d) => match d {
-                                            serde_json::Value::String(data) => {
-                                                let maybe_market = parse_market_from_data(
-                                                    data.to_string(),
-                                                    block_time,
-                                                );
-                                                match maybe_market {
-                                                    Some(market) => markets_vector.push(market),
-                                                    None => {}
-                                                }
-                                            }
-                                            _ => {}
-                                        },
-                                        None => {}
-                                    },
-                                    UiParsedInstruction::PartiallyDecoded(d) => {
-                                        let maybe_market =
-                                            parse_market_from_data(d.data.clone(), block_time);
-                                        match maybe_market {
-                                            Some(market) => markets_vector.push(market),
-                                            None => {}
-                                        }
-                                    }
-                                },
-                            })
-                        });
+    slot: u64,
+) -> Result<SortedEvents, ParseError> {
+    let block_datetime = to_timestampz(block_time as u64);
+    let mut fills = Vec::<OpenBookFill>::new();
+    let mut markets = Vec::<OpenBookMarketMetadata>::new();
+    let mut events = OpenBookEvents::default();
+    let mut unknown_markets = Vec::<String>::new();
+    for (log_index, event) in decoded.into_iter() {
+        let ctx = EventContext {
+            signature: signature.to_string(),
+            log_index,
+            slot,
+            block_datetime,
+        };
+        match event {
+            OpenBookEvent::Fill(f) => {
+                let market_pk = f.market.to_string();
+                match target_markets.get(&market_pk) {
+                    Some(market) => fills.push(OpenBookFill::from_log(f, market, &ctx)?),
+                    None if !unknown_markets.contains(&market_pk) => {
+                        unknown_markets.push(market_pk)
                     }
-                    OptionSerializer::None => {}
-                    OptionSerializer::Skip => {}
-                };
-            };
+                    None => {}
+                }
+            }
+            OpenBookEvent::MarketMetaData(e) => markets.push(OpenBookMarketMetadata::from_event(
+                e,
+                program_pk,
+                block_datetime,
+            )),
+            OpenBookEvent::Deposit(e) => events.deposits.push((ctx, e)),
+            OpenBookEvent::SettleFunds(e) => events.settle_funds.push((ctx, e)),
+            OpenBookEvent::SweepFees(e) => events.sweep_fees.push((ctx, e)),
+            OpenBookEvent::OpenOrdersPosition(e) => events.open_orders_positions.push((ctx, e)),
+            OpenBookEvent::SetDelegate(e) => events.set_delegates.push((ctx, e)),
+            OpenBookEvent::TotalOrderFill(e) => events.total_order_fills.push((ctx, e)),
         }
-        OptionSerializer::None => {}
-        OptionSerializer::Skip => {}
     }
-    if markets_vector.is_empty() {
-        None
-    } else {
-        Some(markets_vector)
+    Ok((fills, markets, events, unknown_markets))
+}
+
+/// Returns the first configured program the transaction calls, which is the program its
+/// events are attributed to.
+pub fn transaction_program_id(txn: &EncodedTransactionWithStatusMeta) -> String {
+    transaction_account_keys(txn)
+        .into_iter()
+        .find(|k| PROGRAM_CONFIG.is_program(k))
+        .unwrap_or_else(|| PROGRAM_CONFIG.program_ids[0].to_string())
+}
+
+/// Returns the first signature of a transaction, which identifies it.
+pub fn transaction_signature(txn: &EncodedTransactionWithStatusMeta) -> Option<String> {
+    match &txn.transaction {
+        EncodedTransaction::Json(t) => t.signatures.first().cloned(),
+        EncodedTransaction::Accounts(t) => t.signatures.first().cloned(),
+        encoded => encoded
+            .decode()
+            .and_then(|t| t.signatures.first().map(|s| s.to_string())),
     }
 }
 
-fn parse_market_from_data(data: String, block_time: i64) -> Option<OpenBookMarketMetadata> {
-    let bytes = match bs58::decode(data).into_vec() {
-        Ok(b) => b,
-        Err(_) => return None,
+/// Returns the static account keys of a transaction followed by any keys loaded from address
+/// lookup tables, in the order used by instruction account and program id indexes.
+pub fn transaction_account_keys(txn: &EncodedTransactionWithStatusMeta) -> Vec<String> {
+    let mut account_keys: Vec<String> = match &txn.transaction {
+        EncodedTransaction::Json(t) => match &t.message {
+            UiMessage::Raw(m) => m.account_keys.clone(),
+            UiMessage::Parsed(m) => m.account_keys.iter().map(|a| a.pubkey.clone()).collect(),
+        },
+        EncodedTransaction::Accounts(t) => {
+            t.account_keys.iter().map(|a| a.pubkey.clone()).collect()
+        }
+        encoded => match encoded.decode() {
+            Some(t) => t
+                .message
+                .static_account_keys()
+                .iter()
+                .map(|k| k.to_string())
+                .collect(),
+            None => vec![],
+        },
     };
-    let mut slice: &[u8] = &bytes[16..];
+    if let Some(meta) = &txn.meta {
+        if let OptionSerializer::Some(loaded) = &meta.loaded_addresses {
+            account_keys.extend(loaded.writable.iter().cloned());
+            account_keys.extend(loaded.readonly.iter().cloned());
+        }
+    }
+    account_keys
+}
 
-    let event: Result<MarketMetaDataLog, Error> =
-        anchor_lang::AnchorDeserialize::deserialize(&mut slice);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{
+        fill_log, market, program_logs, recorded_transaction, with_cpi_events,
+    };
+    use solana_sdk::pubkey::Pubkey;
 
-    match event {
-        Ok(e) => {
-            let datetime = to_timestampz(block_time as u64);
-            let new_market = OpenBookMarketMetadata::from_event(e, datetime);
-            Some(new_market)
-        }
-        _ => None,
+    fn invoke(program_id: &str, depth: u8) -> String {
+        format!("Program {} invoke [{}]", program_id, depth)
+    }
+
+    fn success(program_id: &str) -> String {
+        format!("Program {} success", program_id)
+    }
+
+    fn data(data: &str) -> String {
+        format!("{}{}", PROGRAM_DATA, data)
+    }
+
+    #[test]
+    fn program_data_of_the_configured_program_is_kept() {
+        let openbook = PROGRAM_CONFIG.program_ids[0].to_string();
+        let logs = vec![
+            invoke(&openbook, 1),
+            "Program log: Instruction: PlaceOrder".to_string(),
+            data("AQ=="),
+            data("Ag=="),
+            format!("Program {} consumed 2000 of 200000 compute units", openbook),
+            success(&openbook),
+        ];
+        assert_eq!(program_data_lines(&logs), vec!["AQ==", "Ag=="]);
+    }
+
+    #[test]
+    fn program_data_of_other_programs_is_skipped() {
+        let openbook = PROGRAM_CONFIG.program_ids[0].to_string();
+        let other = Pubkey::new_unique().to_string();
+        let logs = vec![
+            invoke(&other, 1),
+            data("AA=="),
+            success(&other),
+            invoke(&openbook, 1),
+            data("AQ=="),
+            // a program called by the configured program
+            invoke(&other, 2),
+            data("AA=="),
+            success(&other),
+            data("Ag=="),
+            success(&openbook),
+            invoke(&other, 1),
+            format!("Program {} failed: custom program error: 0x1", other),
+            data("AA=="),
+        ];
+        assert_eq!(program_data_lines(&logs), vec!["AQ==", "Ag=="]);
+    }
+
+    #[test]
+    fn configured_program_called_by_another_program_is_kept() {
+        let openbook = PROGRAM_CONFIG.program_ids[0].to_string();
+        let other = Pubkey::new_unique().to_string();
+        let logs = vec![
+            invoke(&other, 1),
+            invoke(&openbook, 2),
+            data("AQ=="),
+            success(&openbook),
+            data("AA=="),
+            success(&other),
+        ];
+        assert_eq!(program_data_lines(&logs), vec!["AQ=="]);
+    }
+
+    #[test]
+    fn events_missing_from_the_logs_are_appended() {
+        let log_payloads = vec![vec![1], vec![2]];
+        let cpi_payloads = vec![vec![1], vec![2], vec![3]];
+        assert_eq!(
+            merge_event_payloads(log_payloads, cpi_payloads),
+            vec![vec![1], vec![2], vec![3]]
+        );
+    }
+
+    #[test]
+    fn events_in_both_places_are_returned_once() {
+        let log_payloads = vec![vec![1], vec![2]];
+        let cpi_payloads = vec![vec![2], vec![1]];
+        assert_eq!(
+            merge_event_payloads(log_payloads, cpi_payloads),
+            vec![vec![1], vec![2]]
+        );
+    }
+
+    #[test]
+    fn identical_events_are_matched_one_to_one() {
+        // two identical events, only one of which made it into the truncated logs
+        let log_payloads = vec![vec![1]];
+        let cpi_payloads = vec![vec![1], vec![1]];
+        assert_eq!(
+            merge_event_payloads(log_payloads, cpi_payloads),
+            vec![vec![1], vec![1]]
+        );
+    }
+
+    #[test]
+    fn events_only_in_the_logs_are_kept() {
+        let log_payloads = vec![vec![1], vec![2]];
+        assert_eq!(
+            merge_event_payloads(log_payloads, vec![]),
+            vec![vec![1], vec![2]]
+        );
+    }
+
+    fn fill(market: &OpenBookMarketMetadata, price: i64, quantity: i64) -> OpenBookEvent {
+        OpenBookEvent::Fill(fill_log(market, 1, price, quantity))
+    }
+
+    fn sort_fills(
+        market: &OpenBookMarketMetadata,
+        event: OpenBookEvent,
+    ) -> Result<Vec<OpenBookFill>, ParseError> {
+        let target_markets = HashMap::from([(market.market_pk.clone(), market.clone())]);
+        sort_openbook_events(
+            vec![(3, event)],
+            &target_markets,
+            "signature",
+            &market.program_pk,
+            0,
+            1,
+        )
+        .map(|(fills, _, _, _)| fills)
+    }
+
+    #[test]
+    fn fills_are_sorted_into_fills() {
+        let market = market();
+        let fills = sort_fills(&market, fill(&market, 20, 1_000_000)).unwrap();
+        assert_eq!(fills.len(), 1);
+        assert_eq!(fills[0].log_index, 3);
+    }
+
+    #[test]
+    fn fills_with_a_zero_price_are_malformed() {
+        let market = market();
+        assert_eq!(
+            sort_fills(&market, fill(&market, 0, 10)),
+            Err(ParseError::MalformedEvent {
+                event: "FillLog",
+                log_index: 3
+            })
+        );
+    }
+
+    #[test]
+    fn fills_with_overflowing_fees_are_malformed() {
+        let market = market();
+        assert_eq!(
+            sort_fills(&market, fill(&market, 1, i64::MAX)),
+            Err(ParseError::MalformedEvent {
+                event: "FillLog",
+                log_index: 3
+            })
+        );
+    }
+
+    #[test]
+    fn fills_cut_from_truncated_logs_are_recovered_from_inner_instructions() {
+        let market = market();
+        let fills = [
+            fill_log(&market, 1, 20, 1_000_000),
+            fill_log(&market, 2, 20, 1_000_000),
+        ];
+        // the logs were cut off after the first fill
+        let mut logs = program_logs(&fills[..1]);
+        logs.pop();
+        logs.push(LOG_TRUNCATED.to_string());
+        let txn = recorded_transaction(
+            100,
+            Some(1_700_000_000),
+            std::slice::from_ref(&market.market_pk),
+            logs,
+        );
+        let txn = with_cpi_events(txn, &fills);
+        let signature = transaction_signature(&txn.transaction).unwrap();
+        let target_markets = HashMap::from([(market.market_pk.clone(), market)]);
+
+        let parsed = parse_openbook_txns(
+            &mut [Ok(txn)],
+            vec![signature.clone()],
+            &target_markets,
+            false,
+        );
+        let seq_nums: Vec<u64> = parsed.fills.iter().map(|f| f.seq_num).collect();
+        assert_eq!(seq_nums, vec![1, 2]);
+        assert_eq!(parsed.completed_sigs, vec![signature.clone()]);
+        assert_eq!(parsed.truncated_sigs, vec![signature]);
+        assert!(parsed.failed_sigs.is_empty());
     }
 }
@@ -0,0 +1,1032 @@
+use async_trait::async_trait;
+use futures::future::join_all;
+use log::{info, warn};
+use serde_json::{json, Value};
+use solana_client::{
+    client_error::{
+        reqwest::{self, header::CONTENT_TYPE, Url},
+        ClientError, ClientErrorKind, Result as ClientResult,
+    },
+    nonblocking::rpc_client::RpcClient,
+    rpc_client::GetConfirmedSignaturesForAddress2Config,
+    rpc_config::{RpcBlockConfig, RpcTransactionConfig},
+    rpc_custom_error::{
+        JSON_RPC_SCAN_ERROR, JSON_RPC_SERVER_ERROR_BLOCK_CLEANED_UP,
+        JSON_RPC_SERVER_ERROR_BLOCK_NOT_AVAILABLE,
+        JSON_RPC_SERVER_ERROR_BLOCK_STATUS_NOT_AVAILABLE_YET,
+        JSON_RPC_SERVER_ERROR_KEY_EXCLUDED_FROM_SECONDARY_INDEX,
+        JSON_RPC_SERVER_ERROR_LONG_TERM_STORAGE_SLOT_SKIPPED,
+        JSON_RPC_SERVER_ERROR_MIN_CONTEXT_SLOT_NOT_REACHED, JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY,
+        JSON_RPC_SERVER_ERROR_NO_SNAPSHOT,
+        JSON_RPC_SERVER_ERROR_SEND_TRANSACTION_PREFLIGHT_FAILURE,
+        JSON_RPC_SERVER_ERROR_SLOT_SKIPPED,
+        JSON_RPC_SERVER_ERROR_TRANSACTION_HISTORY_NOT_AVAILABLE,
+        JSON_RPC_SERVER_ERROR_TRANSACTION_PRECOMPILE_VERIFICATION_FAILURE,
+        JSON_RPC_SERVER_ERROR_TRANSACTION_SIGNATURE_LEN_MISMATCH,
+        JSON_RPC_SERVER_ERROR_TRANSACTION_SIGNATURE_VERIFICATION_FAILURE,
+        JSON_RPC_SERVER_ERROR_UNSUPPORTED_TRANSACTION_VERSION,
+    },
+    rpc_request::{RpcError, RpcRequest, RpcResponseErrorData},
+    rpc_response::RpcConfirmedTransactionStatusWithSignature,
+};
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Signature};
+use solana_transaction_status::{
+    EncodedConfirmedTransactionWithStatusMeta, TransactionDetails, UiTransactionEncoding,
+};
+use std::{
+    future::Future,
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::worker::metrics::{METRIC_RPC_ENDPOINT_ERRORS_TOTAL, METRIC_RPC_ENDPOINT_HEALTH};
+
+use super::source::TransactionSource;
+
+const DEFAULT_SIGNATURE_LIMIT: usize = 1000;
+/// `getTransaction` calls sent per JSON-RPC batch request, matching the transactions a scraper
+/// worker takes per poll.
+const DEFAULT_RPC_BATCH_SIZE: usize = 50;
+const RPC_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const MIN_BACKOFF: Duration = Duration::from_millis(250);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// Weight of the previous health score when a new result is recorded.
+const HEALTH_DECAY: f64 = 0.8;
+/// Unhealthy endpoints still get a share of the traffic, so they can recover.
+const MIN_HEALTH: f64 = 0.05;
+/// The JSON-RPC internal error, which Solana nodes answer when a request fails on their side.
+const JSON_RPC_INTERNAL_ERROR: i64 = -32603;
+
+/// One endpoint of the pool, written as `url[;weight=N][;rps=N]`. Requests are spread over the
+/// endpoints in proportion to their weight and health, and `rps` caps the requests per second
+/// sent to the endpoint.
+#[derive(Clone, Debug)]
+pub struct RpcEndpointConfig {
+    pub url: String,
+    pub weight: u32,
+    pub requests_per_second: Option<u32>,
+}
+
+impl FromStr for RpcEndpointConfig {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().split(';');
+        let url = parts.next().unwrap_or_default().trim().to_string();
+        if url.is_empty() {
+            return Err(anyhow::anyhow!("missing url in rpc endpoint {:?}", s));
+        }
+        let mut config = RpcEndpointConfig {
+            url,
+            weight: 1,
+            requests_per_second: None,
+        };
+        for option in parts {
+            match option.trim().split_once('=') {
+                Some(("weight", weight)) => config.weight = weight.parse()?,
+                Some(("rps", rps)) => config.requests_per_second = Some(rps.parse()?),
+                _ => return Err(anyhow::anyhow!("unknown rpc endpoint option {:?}", option)),
+            }
+        }
+        if config.weight == 0 || config.requests_per_second == Some(0) {
+            return Err(anyhow::anyhow!(
+                "weight and rps must be positive in {:?}",
+                s
+            ));
+        }
+        Ok(config)
+    }
+}
+
+/// How a failed request reflects on the endpoint that served it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum RpcFailure {
+    /// The endpoint is down, overloaded or behind; another endpoint may succeed.
+    Endpoint,
+    /// The endpoint does not have the requested history; an archive endpoint may have it.
+    Unavailable,
+    /// The request itself failed, any other endpoint would fail it too.
+    Request,
+}
+
+fn classify_error(err: &ClientError) -> RpcFailure {
+    match err.kind() {
+        ClientErrorKind::Io(_) | ClientErrorKind::Reqwest(_) => RpcFailure::Endpoint,
+        // null answers, for a transaction or block the node does not have
+        ClientErrorKind::RpcError(RpcError::ForUser(_)) => RpcFailure::Unavailable,
+        ClientErrorKind::RpcError(RpcError::RpcRequestError(_)) => RpcFailure::Endpoint,
+        ClientErrorKind::RpcError(RpcError::RpcResponseError { code, .. }) => match *code {
+            JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY
+            | JSON_RPC_SERVER_ERROR_NO_SNAPSHOT
+            | JSON_RPC_SERVER_ERROR_MIN_CONTEXT_SLOT_NOT_REACHED => RpcFailure::Endpoint,
+            JSON_RPC_SERVER_ERROR_BLOCK_CLEANED_UP
+            | JSON_RPC_SERVER_ERROR_BLOCK_NOT_AVAILABLE
+            | JSON_RPC_SERVER_ERROR_BLOCK_STATUS_NOT_AVAILABLE_YET
+            | JSON_RPC_SERVER_ERROR_LONG_TERM_STORAGE_SLOT_SKIPPED
+            | JSON_RPC_SERVER_ERROR_TRANSACTION_HISTORY_NOT_AVAILABLE => RpcFailure::Unavailable,
+            JSON_RPC_SERVER_ERROR_SLOT_SKIPPED
+            | JSON_RPC_SERVER_ERROR_SEND_TRANSACTION_PREFLIGHT_FAILURE
+            | JSON_RPC_SERVER_ERROR_TRANSACTION_SIGNATURE_VERIFICATION_FAILURE
+            | JSON_RPC_SERVER_ERROR_TRANSACTION_PRECOMPILE_VERIFICATION_FAILURE
+            | JSON_RPC_SERVER_ERROR_KEY_EXCLUDED_FROM_SECONDARY_INDEX
+            | JSON_RPC_SCAN_ERROR
+            | JSON_RPC_SERVER_ERROR_TRANSACTION_SIGNATURE_LEN_MISMATCH
+            | JSON_RPC_SERVER_ERROR_UNSUPPORTED_TRANSACTION_VERSION => RpcFailure::Request,
+            // internal errors, and the server errors providers add such as rate limits
+            JSON_RPC_INTERNAL_ERROR | -32099..=-32000 => RpcFailure::Endpoint,
+            _ => RpcFailure::Request,
+        },
+        _ => RpcFailure::Request,
+    }
+}
+
+struct RpcEndpoint {
+    name: String,
+    config: RpcEndpointConfig,
+    client: RpcClient,
+    http_client: reqwest::Client,
+    archive: bool,
+}
+
+impl RpcEndpoint {
+    async fn get_transaction(
+        &self,
+        signature: &Signature,
+        config: RpcTransactionConfig,
+    ) -> ClientResult<EncodedConfirmedTransactionWithStatusMeta> {
+        let result: Value = self
+            .client
+            .send(
+                RpcRequest::GetTransaction,
+                json!([signature.to_string(), config]),
+            )
+            .await?;
+        transaction_from_result(&result, signature)
+    }
+
+    /// Sends one `getTransaction` call per signature in a single JSON-RPC batch request. An
+    /// error for the whole request fails the batch, otherwise there is one result per signature.
+    async fn get_transaction_batch(
+        &self,
+        signatures: &[Signature],
+        config: RpcTransactionConfig,
+    ) -> ClientResult<Vec<ClientResult<EncodedConfirmedTransactionWithStatusMeta>>> {
+        let body: Vec<Value> = signatures
+            .iter()
+            .enumerate()
+            .map(|(id, s)| {
+                RpcRequest::GetTransaction
+                    .build_request_json(id as u64, json!([s.to_string(), config]))
+            })
+            .collect();
+        let response = self
+            .http_client
+            .post(&self.config.url)
+            .header(CONTENT_TYPE, "application/json")
+            .body(serde_json::to_vec(&body)?)
+            .send()
+            .await?
+            .error_for_status()?;
+        let responses = match serde_json::from_slice::<Value>(&response.bytes().await?)? {
+            Value::Array(responses) => responses,
+            // endpoints without batch support answer with a single error
+            response => return Err(rpc_response_error(&response["error"]).into()),
+        };
+
+        let mut results: Vec<Option<ClientResult<EncodedConfirmedTransactionWithStatusMeta>>> =
+            signatures.iter().map(|_| None).collect();
+        for response in responses.into_iter() {
+            let idx = match response["id"].as_u64() {
+                Some(id) if (id as usize) < results.len() => id as usize,
+                _ => continue,
+            };
+            results[idx] = Some(if response["error"].is_null() {
+                transaction_from_result(&response["result"], &signatures[idx])
+            } else {
+                Err(rpc_response_error(&response["error"]).into())
+            });
+        }
+        Ok(results
+            .into_iter()
+            .zip(signatures.iter())
+            .map(|(result, s)| {
+                result.unwrap_or_else(|| {
+                    Err(ClientError::from(ClientErrorKind::Custom(format!(
+                        "no response for transaction {} in batch",
+                        s
+                    ))))
+                })
+            })
+            .collect())
+    }
+}
+
+/// `getTransaction` answers null for a transaction the node does not have, which is reported
+/// like the null answers of the other methods.
+fn transaction_from_result(
+    result: &Value,
+    signature: &Signature,
+) -> ClientResult<EncodedConfirmedTransactionWithStatusMeta> {
+    if result.is_null() {
+        return Err(
+            RpcError::ForUser(format!("Transaction Not Found: signature={}", signature)).into(),
+        );
+    }
+    serde_json::from_value(result.clone()).map_err(ClientError::from)
+}
+
+fn rpc_response_error(error: &Value) -> RpcError {
+    RpcError::RpcResponseError {
+        code: error["code"].as_i64().unwrap_or_default(),
+        message: error["message"]
+            .as_str()
+            .unwrap_or("invalid batch response")
+            .to_string(),
+        data: RpcResponseErrorData::Empty,
+    }
+}
+
+struct EndpointState {
+    health: f64,
+    /// Smooth weighted round robin counter.
+    current_weight: f64,
+    consecutive_failures: u32,
+    backoff_until: Instant,
+    next_request_at: Instant,
+}
+
+/// Spreads requests over several RPC endpoints. Each endpoint has its own rate limit, and
+/// failing endpoints are backed off exponentially and lose health, which lowers their share of
+/// the traffic. A request that fails because of its endpoint fails over to the next one. Requests
+/// for history that the regular endpoints have pruned go to the archive endpoint, if configured.
+pub struct RpcPool {
+    endpoints: Vec<RpcEndpoint>,
+    states: Mutex<Vec<EndpointState>>,
+    batch_size: usize,
+}
+
+impl RpcPool {
+    pub fn new(
+        endpoints: Vec<RpcEndpointConfig>,
+        archive: Option<RpcEndpointConfig>,
+        batch_size: usize,
+    ) -> anyhow::Result<Self> {
+        if endpoints.is_empty() {
+            return Err(anyhow::anyhow!("no rpc endpoints configured"));
+        }
+        let now = Instant::now();
+        let http_client = reqwest::Client::builder()
+            .timeout(RPC_REQUEST_TIMEOUT)
+            .build()?;
+        let endpoints: Vec<RpcEndpoint> = endpoints
+            .into_iter()
+            .map(|c| (c, false))
+            .chain(archive.into_iter().map(|c| (c, true)))
+            .map(|(config, archive)| RpcEndpoint {
+                name: endpoint_name(&config.url),
+                client: RpcClient::new_with_timeout_and_commitment(
+                    config.url.clone(),
+                    RPC_REQUEST_TIMEOUT,
+                    CommitmentConfig::confirmed(),
+                ),
+                http_client: http_client.clone(),
+                config,
+                archive,
+            })
+            .collect();
+        let states = endpoints
+            .iter()
+            .map(|e| {
+                METRIC_RPC_ENDPOINT_HEALTH
+                    .with_label_values(&[&e.name])
+                    .set(1.0);
+                EndpointState {
+                    health: 1.0,
+                    current_weight: 0.0,
+                    consecutive_failures: 0,
+                    backoff_until: now,
+                    next_request_at: now,
+                }
+            })
+            .collect();
+        Ok(RpcPool {
+            endpoints,
+            states: Mutex::new(states),
+            batch_size: batch_size.max(1),
+        })
+    }
+
+    /// Reads the comma separated endpoints of `RPC_URLS`, falling back to `RPC_URL`, the
+    /// optional `ARCHIVE_RPC_URL` and the `RPC_BATCH_SIZE`.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let endpoints = match (dotenv::var("RPC_URLS"), dotenv::var("RPC_URL")) {
+            (Ok(s), _) => s
+                .split(',')
+                .filter(|e| !e.trim().is_empty())
+                .map(|e| e.parse::<RpcEndpointConfig>())
+                .collect::<anyhow::Result<Vec<_>>>()?,
+            (Err(_), Ok(rpc_url)) => vec![rpc_url.parse()?],
+            (Err(_), Err(_)) => return Err(anyhow::anyhow!("neither RPC_URLS nor RPC_URL is set")),
+        };
+        let archive = match dotenv::var("ARCHIVE_RPC_URL") {
+            Ok(s) => Some(s.parse::<RpcEndpointConfig>()?),
+            Err(_) => None,
+        };
+        let batch_size = match dotenv::var("RPC_BATCH_SIZE") {
+            Ok(s) => s.parse::<usize>()?,
+            Err(_) => DEFAULT_RPC_BATCH_SIZE,
+        };
+        let pool = Self::new(endpoints, archive, batch_size)?;
+        for endpoint in pool.endpoints.iter() {
+            info!(
+                "Using {}rpc endpoint {} with weight {}",
+                if endpoint.archive { "archive " } else { "" },
+                endpoint.name,
+                endpoint.config.weight
+            );
+        }
+        Ok(pool)
+    }
+
+    fn has_archive(&self) -> bool {
+        self.endpoints.iter().any(|e| e.archive)
+    }
+
+    /// Picks the next endpoint among those not tried yet, and reserves a request of `calls` RPC
+    /// calls on it. Returns the endpoint and when the request may be sent, after its backoff and
+    /// rate limit. A batch request takes a share of the rate limit for each call in it.
+    fn reserve(&self, tried: &[usize], archive: bool, calls: u32) -> Option<(usize, Instant)> {
+        let now = Instant::now();
+        let mut states = self.states.lock().unwrap();
+        let candidates: Vec<usize> = (0..self.endpoints.len())
+            .filter(|idx| self.endpoints[*idx].archive == archive && !tried.contains(idx))
+            .collect();
+        let ready: Vec<usize> = candidates
+            .iter()
+            .copied()
+            .filter(|idx| states[*idx].backoff_until <= now)
+            .collect();
+
+        let idx = if ready.is_empty() {
+            // every endpoint is backing off, wait for the first one to come back
+            candidates
+                .into_iter()
+                .min_by_key(|idx| states[*idx].backoff_until)?
+        } else {
+            let mut total = 0.0;
+            for idx in ready.iter() {
+                let state = &mut states[*idx];
+                let effective = self.endpoints[*idx].config.weight as f64 * state.health;
+                state.current_weight += effective;
+                total += effective;
+            }
+            let idx = ready
+                .into_iter()
+                .max_by(|a, b| {
+                    states[*a]
+                        .current_weight
+                        .total_cmp(&states[*b].current_weight)
+                })
+                .unwrap();
+            states[idx].current_weight -= total;
+            idx
+        };
+
+        let state = &mut states[idx];
+        let ready_at = now.max(state.backoff_until).max(state.next_request_at);
+        if let Some(rps) = self.endpoints[idx].config.requests_per_second {
+            state.next_request_at = ready_at + Duration::from_secs(1) * calls.max(1) / rps;
+        }
+        Some((idx, ready_at))
+    }
+
+    fn record_result(&self, idx: usize, success: bool) {
+        let mut states = self.states.lock().unwrap();
+        let state = &mut states[idx];
+        let sample = if success { 1.0 } else { 0.0 };
+        state.health =
+            (state.health * HEALTH_DECAY + sample * (1.0 - HEALTH_DECAY)).max(MIN_HEALTH);
+        if success {
+            state.consecutive_failures = 0;
+        } else {
+            state.consecutive_failures += 1;
+            let backoff = MIN_BACKOFF
+                .saturating_mul(1 << state.consecutive_failures.min(16).saturating_sub(1))
+                .min(MAX_BACKOFF);
+            state.backoff_until = Instant::now() + backoff;
+        }
+        METRIC_RPC_ENDPOINT_HEALTH
+            .with_label_values(&[&self.endpoints[idx].name])
+            .set(state.health);
+    }
+
+    /// Sends a request to each regular endpoint in turn until one answers, then to the archive
+    /// endpoint if none of them had the requested history.
+    async fn call<'a, T, F, Fut>(&'a self, method: &str, request: F) -> ClientResult<T>
+    where
+        F: Fn(&'a RpcEndpoint) -> Fut,
+        Fut: Future<Output = ClientResult<T>> + 'a,
+    {
+        match self.call_endpoints(method, false, 1, &request).await {
+            Err((_, RpcFailure::Unavailable)) if self.has_archive() => self
+                .call_endpoints(method, true, 1, &request)
+                .await
+                .map_err(|(err, _)| err),
+            result => result.map_err(|(err, _)| err),
+        }
+    }
+
+    async fn call_endpoints<'a, T, F, Fut>(
+        &'a self,
+        method: &str,
+        archive: bool,
+        calls: u32,
+        request: &F,
+    ) -> Result<T, (ClientError, RpcFailure)>
+    where
+        F: Fn(&'a RpcEndpoint) -> Fut,
+        Fut: Future<Output = ClientResult<T>> + 'a,
+    {
+        let mut tried = vec![];
+        let mut last_err = None;
+        let mut unavailable = false;
+        while let Some((idx, ready_at)) = self.reserve(&tried, archive, calls) {
+            tokio::time::sleep_until(ready_at.into()).await;
+            let endpoint = &self.endpoints[idx];
+            let err = match request(endpoint).await {
+                Ok(value) => {
+                    self.record_result(idx, true);
+                    return Ok(value);
+                }
+                Err(err) => err,
+            };
+            let failure = classify_error(&err);
+            match failure {
+                RpcFailure::Endpoint => {
+                    warn!("rpc error in {} from {}: {}", method, endpoint.name, err);
+                    METRIC_RPC_ENDPOINT_ERRORS_TOTAL
+                        .with_label_values(&[&endpoint.name, method])
+                        .inc();
+                    self.record_result(idx, false);
+                }
+                RpcFailure::Unavailable => self.record_result(idx, true),
+                RpcFailure::Request => {
+                    self.record_result(idx, true);
+                    return Err((err, failure));
+                }
+            }
+            tried.push(idx);
+            unavailable |= failure == RpcFailure::Unavailable;
+            last_err = Some(err);
+        }
+        let err = last_err.expect("rpc pool has no endpoints");
+        if unavailable {
+            Err((err, RpcFailure::Unavailable))
+        } else {
+            Err((err, RpcFailure::Endpoint))
+        }
+    }
+}
+
+impl RpcPool {
+    /// Fetches a batch of transactions with one request, failing over to the next endpoint if
+    /// the request fails. Transactions that the endpoint did not have are fetched again from
+    /// the archive endpoint, if configured.
+    async fn get_transaction_batch(
+        &self,
+        signatures: &[Signature],
+        config: RpcTransactionConfig,
+    ) -> Vec<ClientResult<EncodedConfirmedTransactionWithStatusMeta>> {
+        let mut results = match self
+            .call_endpoints(
+                "getTransaction",
+                false,
+                signatures.len() as u32,
+                &|endpoint| endpoint.get_transaction_batch(signatures, config),
+            )
+            .await
+        {
+            Ok(results) => results,
+            Err((err, _)) => {
+                return signatures
+                    .iter()
+                    .map(|_| {
+                        Err(ClientError::from(ClientErrorKind::Custom(format!(
+                            "batch getTransaction failed: {}",
+                            err
+                        ))))
+                    })
+                    .collect()
+            }
+        };
+        if !self.has_archive() {
+            return results;
+        }
+
+        let unavailable: Vec<usize> = results
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| matches!(r, Err(e) if classify_error(e) == RpcFailure::Unavailable))
+            .map(|(idx, _)| idx)
+            .collect();
+        if unavailable.is_empty() {
+            return results;
+        }
+        let archive_signatures: Vec<Signature> =
+            unavailable.iter().map(|idx| signatures[*idx]).collect();
+        match self
+            .call_endpoints(
+                "getTransaction",
+                true,
+                archive_signatures.len() as u32,
+                &|endpoint| endpoint.get_transaction_batch(&archive_signatures, config),
+            )
+            .await
+        {
+            Ok(archived) => {
+                for (idx, result) in unavailable.into_iter().zip(archived) {
+                    if result.is_ok() {
+                        results[idx] = result;
+                    }
+                }
+            }
+            Err((err, _)) => warn!("archive rpc error in getTransaction: {}", err),
+        }
+        results
+    }
+}
+
+/// The host of an endpoint, used in logs and metrics so that api keys in the url are not exposed.
+fn endpoint_name(url: &str) -> String {
+    Url::parse(url)
+        .ok()
+        .and_then(|u| u.host_str().map(|h| h.to_string()))
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+#[async_trait]
+impl TransactionSource for RpcPool {
+    async fn get_signatures_for_address(
+        &self,
+        address: &Pubkey,
+        config: GetConfirmedSignaturesForAddress2Config,
+    ) -> ClientResult<Vec<RpcConfirmedTransactionStatusWithSignature>> {
+        let request_config = || GetConfirmedSignaturesForAddress2Config {
+            before: config.before,
+            until: config.until,
+            limit: config.limit,
+            commitment: config.commitment,
+        };
+        let sigs = self
+            .call("getSignaturesForAddress", |endpoint| {
+                endpoint
+                    .client
+                    .get_signatures_for_address_with_config(address, request_config())
+            })
+            .await?;
+
+        // A pruned node ends the page early when paging back without a lower bound runs into
+        // the oldest slot it still has. Ask the archive whether there is more history.
+        let limit = config.limit.unwrap_or(DEFAULT_SIGNATURE_LIMIT);
+        if config.before.is_some()
+            && config.until.is_none()
+            && sigs.len() < limit
+            && self.has_archive()
+        {
+            match self
+                .call_endpoints(
+                    "getSignaturesForAddress",
+                    true,
+                    1,
+                    &|endpoint: &RpcEndpoint| {
+                        endpoint
+                            .client
+                            .get_signatures_for_address_with_config(address, request_config())
+                    },
+                )
+                .await
+            {
+                Ok(archived) if archived.len() > sigs.len() => return Ok(archived),
+                Ok(_) => {}
+                Err((err, _)) => {
+                    warn!("archive rpc error in getSignaturesForAddress: {}", err)
+                }
+            }
+        }
+        Ok(sigs)
+    }
+
+    async fn get_transactions(
+        &self,
+        signatures: &[Signature],
+    ) -> Vec<ClientResult<EncodedConfirmedTransactionWithStatusMeta>> {
+        let txn_config = RpcTransactionConfig {
+            encoding: Some(UiTransactionEncoding::Json),
+            commitment: Some(CommitmentConfig::confirmed()),
+            max_supported_transaction_version: Some(1),
+        };
+
+        if self.batch_size == 1 {
+            let txn_futs: Vec<_> = signatures
+                .iter()
+                .map(|s| {
+                    self.call("getTransaction", move |endpoint| {
+                        endpoint.get_transaction(s, txn_config)
+                    })
+                })
+                .collect();
+            return join_all(txn_futs).await;
+        }
+
+        let batch_futs: Vec<_> = signatures
+            .chunks(self.batch_size)
+            .map(|batch| self.get_transaction_batch(batch, txn_config))
+            .collect();
+        join_all(batch_futs).await.into_iter().flatten().collect()
+    }
+
+    async fn get_finalized_slot(&self) -> ClientResult<u64> {
+        self.call("getSlot", |endpoint| {
+            endpoint
+                .client
+                .get_slot_with_commitment(CommitmentConfig::finalized())
+        })
+        .await
+    }
+
+    async fn get_finalized_blocks(&self, start_slot: u64, end_slot: u64) -> ClientResult<Vec<u64>> {
+        self.call("getBlocks", |endpoint| {
+            endpoint.client.get_blocks_with_commitment(
+                start_slot,
+                Some(end_slot),
+                CommitmentConfig::finalized(),
+            )
+        })
+        .await
+    }
+
+    async fn get_block_time(&self, slot: u64) -> ClientResult<i64> {
+        self.call("getBlockTime", |endpoint| {
+            endpoint.client.get_block_time(slot)
+        })
+        .await
+    }
+
+    async fn get_block_transactions(
+        &self,
+        slot: u64,
+    ) -> ClientResult<Vec<EncodedConfirmedTransactionWithStatusMeta>> {
+        let block_config = RpcBlockConfig {
+            encoding: Some(UiTransactionEncoding::Json),
+            transaction_details: Some(TransactionDetails::Full),
+            rewards: Some(false),
+            commitment: Some(CommitmentConfig::finalized()),
+            max_supported_transaction_version: Some(1),
+        };
+        let block = self
+            .call("getBlock", |endpoint| {
+                endpoint.client.get_block_with_config(slot, block_config)
+            })
+            .await?;
+        Ok(block
+            .transactions
+            .unwrap_or_default()
+            .into_iter()
+            .map(|transaction| EncodedConfirmedTransactionWithStatusMeta {
+                slot,
+                transaction,
+                block_time: block.block_time,
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    fn response_error(code: i64) -> ClientError {
+        rpc_response_error(&json!({ "code": code, "message": "error" })).into()
+    }
+
+    fn endpoint(url: &str, weight: u32) -> RpcEndpointConfig {
+        RpcEndpointConfig {
+            url: url.to_string(),
+            weight,
+            requests_per_second: None,
+        }
+    }
+
+    /// A JSON-RPC server answering each request, or each call of a batch request, with the
+    /// `(status, body)` of `respond`. Counts the HTTP requests it receives.
+    async fn serve_rpc<F>(respond: F) -> (String, Arc<AtomicUsize>)
+    where
+        F: Fn(&Value) -> (u16, Value) + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let respond = Arc::new(respond);
+        let counter = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let respond = respond.clone();
+                let counter = counter.clone();
+                tokio::spawn(async move {
+                    let mut buf = vec![];
+                    loop {
+                        // headers, then a body of content-length bytes
+                        let header_end = loop {
+                            if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                                break end + 4;
+                            }
+                            let mut chunk = [0u8; 4096];
+                            match stream.read(&mut chunk).await {
+                                Ok(0) | Err(_) => return,
+                                Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                            }
+                        };
+                        let headers = String::from_utf8_lossy(&buf[..header_end]).to_lowercase();
+                        let content_length: usize = headers
+                            .lines()
+                            .find_map(|l| l.strip_prefix("content-length:"))
+                            .map_or(0, |l| l.trim().parse().unwrap());
+                        while buf.len() < header_end + content_length {
+                            let mut chunk = [0u8; 4096];
+                            match stream.read(&mut chunk).await {
+                                Ok(0) | Err(_) => return,
+                                Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                            }
+                        }
+                        let request: Value =
+                            serde_json::from_slice(&buf[header_end..header_end + content_length])
+                                .unwrap();
+                        buf.drain(..header_end + content_length);
+                        counter.fetch_add(1, Ordering::SeqCst);
+
+                        let (status, body) = match &request {
+                            Value::Array(calls) => {
+                                let responses: Vec<Value> =
+                                    calls.iter().map(|c| respond(c).1).collect();
+                                (200, Value::Array(responses))
+                            }
+                            call => respond(call),
+                        };
+                        let body = body.to_string();
+                        let response = format!(
+                            "HTTP/1.1 {} OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+                            status,
+                            body.len(),
+                            body
+                        );
+                        if stream.write_all(response.as_bytes()).await.is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        (url, requests)
+    }
+
+    fn result(call: &Value, result: Value) -> (u16, Value) {
+        (
+            200,
+            json!({ "jsonrpc": "2.0", "result": result, "id": call["id"] }),
+        )
+    }
+
+    fn error(call: &Value, code: i64) -> (u16, Value) {
+        (
+            200,
+            json!({
+                "jsonrpc": "2.0",
+                "error": { "code": code, "message": "error" },
+                "id": call["id"],
+            }),
+        )
+    }
+
+    #[test]
+    fn errors_of_the_endpoint_fail_over() {
+        for code in [
+            JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY,
+            JSON_RPC_SERVER_ERROR_MIN_CONTEXT_SLOT_NOT_REACHED,
+            JSON_RPC_INTERNAL_ERROR,
+            -32029,
+        ] {
+            assert_eq!(classify_error(&response_error(code)), RpcFailure::Endpoint);
+        }
+        let io = std::io::Error::new(std::io::ErrorKind::ConnectionReset, "reset");
+        assert_eq!(classify_error(&ClientError::from(io)), RpcFailure::Endpoint);
+    }
+
+    #[test]
+    fn missing_history_is_unavailable() {
+        for code in [
+            JSON_RPC_SERVER_ERROR_BLOCK_CLEANED_UP,
+            JSON_RPC_SERVER_ERROR_BLOCK_NOT_AVAILABLE,
+            JSON_RPC_SERVER_ERROR_LONG_TERM_STORAGE_SLOT_SKIPPED,
+            JSON_RPC_SERVER_ERROR_TRANSACTION_HISTORY_NOT_AVAILABLE,
+        ] {
+            assert_eq!(
+                classify_error(&response_error(code)),
+                RpcFailure::Unavailable
+            );
+        }
+        let not_found = transaction_from_result(&Value::Null, &Signature::default()).unwrap_err();
+        assert_eq!(classify_error(&not_found), RpcFailure::Unavailable);
+    }
+
+    #[test]
+    fn errors_of_the_request_are_not_retried() {
+        for code in [
+            JSON_RPC_SERVER_ERROR_SLOT_SKIPPED,
+            JSON_RPC_SERVER_ERROR_UNSUPPORTED_TRANSACTION_VERSION,
+            -32602,
+        ] {
+            assert_eq!(classify_error(&response_error(code)), RpcFailure::Request);
+        }
+        let malformed =
+            transaction_from_result(&json!({ "slot": "x" }), &Signature::default()).unwrap_err();
+        assert_eq!(classify_error(&malformed), RpcFailure::Request);
+    }
+
+    #[test]
+    fn requests_are_shared_by_weight() {
+        let pool = RpcPool::new(
+            vec![
+                endpoint("http://a.example.com", 3),
+                endpoint("http://b.example.com", 1),
+            ],
+            None,
+            1,
+        )
+        .unwrap();
+        let mut counts = [0; 2];
+        for _ in 0..8 {
+            let (idx, _) = pool.reserve(&[], false, 1).unwrap();
+            counts[idx] += 1;
+        }
+        assert_eq!(counts, [6, 2]);
+    }
+
+    #[test]
+    fn failing_endpoints_back_off_and_lose_health() {
+        let pool = RpcPool::new(
+            vec![
+                endpoint("http://a.example.com", 1),
+                endpoint("http://b.example.com", 1),
+            ],
+            None,
+            1,
+        )
+        .unwrap();
+        pool.record_result(0, false);
+        pool.record_result(0, false);
+        {
+            let states = pool.states.lock().unwrap();
+            assert_eq!(states[0].consecutive_failures, 2);
+            assert!((states[0].health - 0.64).abs() < 1e-9);
+            let backoff = states[0].backoff_until - Instant::now();
+            assert!(backoff > MIN_BACKOFF && backoff <= MIN_BACKOFF * 2);
+        }
+        // only the healthy endpoint is ready
+        for _ in 0..4 {
+            assert_eq!(pool.reserve(&[], false, 1).unwrap().0, 1);
+        }
+
+        pool.record_result(0, true);
+        let states = pool.states.lock().unwrap();
+        assert_eq!(states[0].consecutive_failures, 0);
+        assert!(states[0].health > 0.64);
+    }
+
+    #[test]
+    fn health_has_a_floor() {
+        let pool = RpcPool::new(vec![endpoint("http://a.example.com", 1)], None, 1).unwrap();
+        for _ in 0..50 {
+            pool.record_result(0, false);
+        }
+        let states = pool.states.lock().unwrap();
+        assert_eq!(states[0].health, MIN_HEALTH);
+        assert!(states[0].backoff_until - Instant::now() <= MAX_BACKOFF);
+    }
+
+    #[tokio::test]
+    async fn unhealthy_endpoints_fail_over() {
+        let (down, down_requests) = serve_rpc(|_| (503, json!({}))).await;
+        let (unhealthy, unhealthy_requests) =
+            serve_rpc(|call| error(call, JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY)).await;
+        let (up, up_requests) = serve_rpc(|call| result(call, json!(1_700_000_000))).await;
+        let pool = RpcPool::new(
+            vec![
+                endpoint(&down, 100),
+                endpoint(&unhealthy, 10),
+                endpoint(&up, 1),
+            ],
+            None,
+            1,
+        )
+        .unwrap();
+
+        assert_eq!(pool.get_block_time(100).await.unwrap(), 1_700_000_000);
+        assert_eq!(down_requests.load(Ordering::SeqCst), 1);
+        assert_eq!(unhealthy_requests.load(Ordering::SeqCst), 1);
+        assert_eq!(up_requests.load(Ordering::SeqCst), 1);
+        let states = pool.states.lock().unwrap();
+        assert!(states[0].health < 1.0 && states[1].health < 1.0);
+        assert_eq!(states[2].health, 1.0);
+    }
+
+    #[tokio::test]
+    async fn request_errors_do_not_fail_over() {
+        let (first, first_requests) =
+            serve_rpc(|call| error(call, JSON_RPC_SERVER_ERROR_SLOT_SKIPPED)).await;
+        let (second, second_requests) = serve_rpc(|call| result(call, json!(1))).await;
+        let pool =
+            RpcPool::new(vec![endpoint(&first, 100), endpoint(&second, 1)], None, 1).unwrap();
+
+        assert!(pool.get_block_time(100).await.is_err());
+        assert_eq!(first_requests.load(Ordering::SeqCst), 1);
+        assert_eq!(second_requests.load(Ordering::SeqCst), 0);
+        assert_eq!(pool.states.lock().unwrap()[0].health, 1.0);
+    }
+
+    #[tokio::test]
+    async fn missing_transactions_are_fetched_from_the_archive() {
+        let (pruned, _) = serve_rpc(|call| result(call, Value::Null)).await;
+        let (archive, archive_requests) = serve_rpc(|call| {
+            result(
+                call,
+                json!({
+                    "slot": 100,
+                    "blockTime": 1_700_000_000,
+                    "transaction": {
+                        "signatures": [Signature::default().to_string()],
+                        "message": {
+                            "header": {
+                                "numRequiredSignatures": 1,
+                                "numReadonlySignedAccounts": 0,
+                                "numReadonlyUnsignedAccounts": 0,
+                            },
+                            "accountKeys": [Pubkey::default().to_string()],
+                            "recentBlockhash": "11111111111111111111111111111111",
+                            "instructions": [],
+                        },
+                    },
+                    "meta": null,
+                }),
+            )
+        })
+        .await;
+        let pool =
+            RpcPool::new(vec![endpoint(&pruned, 1)], Some(endpoint(&archive, 1)), 10).unwrap();
+
+        let txns = pool
+            .get_transactions(&[Signature::default(), Signature::new_unique()])
+            .await;
+        assert_eq!(txns.len(), 2);
+        assert!(txns.iter().all(|t| t.as_ref().unwrap().slot == 100));
+        assert_eq!(archive_requests.load(Ordering::SeqCst), 1);
+        assert_eq!(pool.states.lock().unwrap()[0].health, 1.0);
+    }
+
+    #[test]
+    fn rate_limit_is_charged_per_call() {
+        let mut config = endpoint("http://a.example.com", 1);
+        config.requests_per_second = Some(10);
+        let pool = RpcPool::new(vec![config], None, 1).unwrap();
+
+        let (_, first) = pool.reserve(&[], false, 1).unwrap();
+        let (_, second) = pool.reserve(&[], false, 5).unwrap();
+        let (_, third) = pool.reserve(&[], false, 1).unwrap();
+        assert_eq!(second - first, Duration::from_millis(100));
+        assert_eq!(third - second, Duration::from_millis(500));
+    }
+
+    #[tokio::test]
+    async fn batches_take_the_rate_limit_of_their_calls() {
+        let (url, requests) = serve_rpc(|call| result(call, Value::Null)).await;
+        let mut config = endpoint(&url, 1);
+        config.requests_per_second = Some(10);
+        let pool = RpcPool::new(vec![config], None, 20).unwrap();
+
+        let signatures: Vec<Signature> = (0..20).map(|_| Signature::new_unique()).collect();
+        let sent_at = Instant::now();
+        pool.get_transactions(&signatures).await;
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        let next_request_at = pool.states.lock().unwrap()[0].next_request_at;
+        assert!(next_request_at - sent_at >= Duration::from_secs(2));
+    }
+}
@@ -1,121 +1,537 @@
 use deadpool_postgres::Pool;
-use futures::future::join_all;
-use log::{debug, warn};
+use log::{debug, info, warn};
 use solana_client::{
-    nonblocking::rpc_client::RpcClient, rpc_client::GetConfirmedSignaturesForAddress2Config,
-    rpc_config::RpcTransactionConfig,
+    client_error::Result as ClientResult, rpc_client::GetConfirmedSignaturesForAddress2Config,
+    rpc_response::RpcConfirmedTransactionStatusWithSignature,
 };
-use solana_sdk::{commitment_config::CommitmentConfig, signature::Signature};
-use solana_transaction_status::UiTransactionEncoding;
-use std::{collections::HashMap, time::Duration as WaitDuration};
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Signature};
+use solana_transaction_status::EncodedConfirmedTransactionWithStatusMeta;
+use std::time::Duration as WaitDuration;
 
 use crate::{
     database::{
-        fetch::fetch_worker_transactions,
-        insert::{build_transactions_insert_statement, insert_atomically},
+        fetch::{claim_transactions, fetch_scraper_state},
+        insert::{
+            insert_atomically, insert_signatures_with_checkpoint, queue_market_imports,
+            record_failed_transactions,
+        },
+    },
+    structs::{parse_error::ParseError, scraper_state::ScraperState, transaction::PgTransaction},
+    utils::{market_registry::MarketRegistry, PROGRAM_CONFIG},
+    worker::metrics::{
+        METRIC_FILLS_TOTAL, METRIC_RPC_ERRORS_TOTAL, METRIC_TRANSACTIONS_TOTAL,
+        METRIC_UNSTORABLE_SIGNATURES_TOTAL,
     },
-    structs::{openbook_v2::OpenBookMarketMetadata, transaction::PgTransaction},
-    utils::{AnyhowWrap, OPENBOOK_KEY},
-    worker::metrics::{METRIC_FILLS_TOTAL, METRIC_RPC_ERRORS_TOTAL, METRIC_TRANSACTIONS_TOTAL},
 };
 
-use super::parsing::parse_openbook_txns;
+use super::{
+    archive::{archive_enabled, archive_fetched_transactions},
+    parsing::{parse_openbook_txns, ParsedTransactions},
+    source::{fill_missing_block_times, fill_missing_signature_block_times, TransactionSource},
+};
 
-pub async fn scrape_signatures(rpc_url: String, pool: &Pool) -> anyhow::Result<()> {
-    let rpc_client = RpcClient::new_with_commitment(rpc_url.clone(), CommitmentConfig::confirmed());
+const SIGNATURE_PAGE_SIZE: usize = 1000;
+const MIN_POLL_INTERVAL: WaitDuration = WaitDuration::from_millis(500);
+const MAX_POLL_INTERVAL: WaitDuration = WaitDuration::from_secs(10);
+/// Polls returning more signatures than this shorten the poll interval.
+const BUSY_POLL_SIGNATURES: usize = 200;
+/// Transactions claimed by a scraping task at a time.
+const CLAIM_BATCH_SIZE: i64 = 50;
+const DEFAULT_SCRAPER_TASKS: usize = 10;
+const DEFAULT_CLAIM_LEASE_SECS: i64 = 120;
 
-    loop {
-        let rpc_config = GetConfirmedSignaturesForAddress2Config {
-            before: None,
-            until: None,
-            limit: None,
-            commitment: Some(CommitmentConfig::confirmed()),
+/// How a process takes part in transaction scraping. Each of its tasks claims batches of
+/// unprocessed transactions under its own claimant name, so any number of processes can share
+/// the backlog of one database.
+#[derive(Clone, Debug)]
+pub struct ClaimConfig {
+    pub tasks: usize,
+    pub lease_secs: i64,
+    pub process_name: String,
+}
+
+impl ClaimConfig {
+    /// Reads `SCRAPER_TASKS` and `CLAIM_LEASE_SECS`. Processes are named after `HOSTNAME` and
+    /// their pid.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let tasks = match dotenv::var("SCRAPER_TASKS") {
+            Ok(s) => s.parse::<usize>()?,
+            Err(_) => DEFAULT_SCRAPER_TASKS,
+        };
+        let lease_secs = match dotenv::var("CLAIM_LEASE_SECS") {
+            Ok(s) => s.parse::<i64>()?,
+            Err(_) => DEFAULT_CLAIM_LEASE_SECS,
         };
+        let host = dotenv::var("HOSTNAME").unwrap_or_else(|_| "localhost".to_string());
+        Ok(ClaimConfig {
+            tasks,
+            lease_secs,
+            process_name: format!("{}:{}", host, std::process::id()),
+        })
+    }
 
-        let sigs = match rpc_client
-            .get_signatures_for_address_with_config(&OPENBOOK_KEY, rpc_config)
-            .await
-        {
+    pub fn claimant(&self, task: usize) -> String {
+        format!("{}:{}", self.process_name, task)
+    }
+}
+
+/// Reads `SCRAPE_ADDRESSES`, a comma separated list of market or event heap accounts. When
+/// set, their signatures are polled instead of the signatures of the whole program, so only
+/// the transactions of those markets are scraped.
+pub fn scrape_addresses() -> anyhow::Result<Vec<Pubkey>> {
+    match dotenv::var("SCRAPE_ADDRESSES") {
+        Ok(s) => s
+            .split(',')
+            .map(|a| a.trim())
+            .filter(|a| !a.is_empty())
+            .map(|a| Ok(a.parse::<Pubkey>()?))
+            .collect(),
+        Err(_) => Ok(vec![]),
+    }
+}
+
+/// The addresses whose signatures are polled, each with the program its transactions belong
+/// to: every configured program, or the `SCRAPE_ADDRESSES` of the first program when set.
+pub fn signature_addresses() -> anyhow::Result<Vec<(Pubkey, Pubkey)>> {
+    let addresses = scrape_addresses()?;
+    if addresses.is_empty() {
+        return Ok(PROGRAM_CONFIG
+            .program_ids
+            .iter()
+            .map(|p| (*p, *p))
+            .collect());
+    }
+    let program_id = PROGRAM_CONFIG.program_ids[0];
+    Ok(addresses.into_iter().map(|a| (a, program_id)).collect())
+}
+
+/// Polls the signatures of one address, either a program or an account of `program_id`. Each
+/// address is polled by its own task, with its own checkpoint. Transactions share the
+/// transactions table whichever address they were found through.
+pub async fn scrape_signatures(
+    source: &dyn TransactionSource,
+    pool: &Pool,
+    address: Pubkey,
+    program_id: Pubkey,
+) -> anyhow::Result<()> {
+    let mut checkpoint = fetch_scraper_state(pool, &address.to_string()).await?;
+    match &checkpoint {
+        Some(c) => info!(
+            "Resuming signature scraping for {} from slot {}",
+            address, c.last_slot
+        ),
+        None => info!("No checkpoint found for {}, starting at the tip", address),
+    }
+    let mut poll_interval = MIN_POLL_INTERVAL;
+
+    loop {
+        tokio::time::sleep(poll_interval).await;
+
+        let until = match &checkpoint {
+            Some(c) => Some(c.last_signature.parse::<Signature>()?),
+            None => None,
+        };
+        let sigs = match fetch_signatures_until(source, &address, until).await {
             Ok(sigs) => sigs,
             Err(e) => {
                 warn!("rpc error in get_signatures_for_address_with_config: {}", e);
                 METRIC_RPC_ERRORS_TOTAL
                     .with_label_values(&["getSignaturesForAddress"])
                     .inc();
+                poll_interval = MAX_POLL_INTERVAL;
                 continue;
             }
         };
+        poll_interval = next_poll_interval(poll_interval, sigs.len());
         if sigs.is_empty() {
-            debug!("No signatures found, trying again");
+            debug!("No new signatures found, trying again");
             continue;
         }
-        let transactions: Vec<PgTransaction> = sigs
+
+        // signatures are returned newest first
+        let new_checkpoint = ScraperState {
+            address: address.to_string(),
+            last_signature: sigs[0].signature.clone(),
+            last_slot: sigs[0].slot,
+        };
+        // the checkpoint only moves past signatures that were stored, so a page with a signature
+        // that can't be stored is polled again
+        let transactions: Result<Vec<PgTransaction>, _> = sigs
             .into_iter()
-            .map(PgTransaction::from_rpc_confirmed_transaction)
+            .map(|s| PgTransaction::from_rpc_confirmed_transaction(s, &program_id))
             .collect();
+        let transactions = match transactions {
+            Ok(transactions) => transactions,
+            Err(e) => {
+                warn!("failed to store signature of {}: {}", address, e);
+                METRIC_UNSTORABLE_SIGNATURES_TOTAL
+                    .with_label_values(&[&address.to_string()])
+                    .inc();
+                poll_interval = MAX_POLL_INTERVAL;
+                continue;
+            }
+        };
 
         debug!("Scraper writing: {:?} txns to DB\n", transactions.len());
-        let upsert_statement = build_transactions_insert_statement(transactions);
-        let client = pool.get().await?;
-        let num_txns = client
-            .execute(&upsert_statement, &[])
-            .await
-            .map_err_anyhow()?;
+        let num_txns =
+            insert_signatures_with_checkpoint(pool, transactions, &new_checkpoint).await?;
         METRIC_TRANSACTIONS_TOTAL.inc_by(num_txns);
+        checkpoint = Some(new_checkpoint);
     }
     // TODO: graceful shutdown
 }
 
+/// Pages backwards from the tip until the `until` signature is reached, so no signatures are
+/// skipped when more than one page arrives between polls. Without `until` only the newest page
+/// is fetched, older history is left to the backfill.
+async fn fetch_signatures_until(
+    source: &dyn TransactionSource,
+    address: &Pubkey,
+    until: Option<Signature>,
+) -> ClientResult<Vec<RpcConfirmedTransactionStatusWithSignature>> {
+    let mut sigs = vec![];
+    let mut before = None;
+    loop {
+        let rpc_config = GetConfirmedSignaturesForAddress2Config {
+            before,
+            until,
+            limit: Some(SIGNATURE_PAGE_SIZE),
+            commitment: Some(CommitmentConfig::confirmed()),
+        };
+        let mut page = source
+            .get_signatures_for_address(address, rpc_config)
+            .await?;
+        fill_missing_signature_block_times(source, &mut page).await?;
+        let page_len = page.len();
+        before = page
+            .last()
+            .and_then(|s| s.signature.parse::<Signature>().ok());
+        sigs.extend(page);
+
+        if until.is_none() || page_len < SIGNATURE_PAGE_SIZE || before.is_none() {
+            return Ok(sigs);
+        }
+    }
+}
+
+fn next_poll_interval(current: WaitDuration, num_sigs: usize) -> WaitDuration {
+    let next = if num_sigs == 0 {
+        current * 2
+    } else if num_sigs > BUSY_POLL_SIGNATURES {
+        current / 2
+    } else {
+        current
+    };
+    next.clamp(MIN_POLL_INTERVAL, MAX_POLL_INTERVAL)
+}
+
+/// Claims batches of unprocessed transactions, fetches and parses them, and writes their fills.
+/// Transactions that could not be fetched or parsed are retried later with backoff.
 pub async fn scrape_transactions(
-    worker_id: i32,
-    rpc_url: String,
+    task: usize,
+    config: &ClaimConfig,
+    source: &dyn TransactionSource,
     pool: &Pool,
-    target_markets: &HashMap<String, OpenBookMarketMetadata>,
+    markets: &MarketRegistry,
 ) -> anyhow::Result<()> {
-    debug!("Scraper {} started \n", worker_id);
-    let rpc_client = RpcClient::new_with_commitment(rpc_url, CommitmentConfig::confirmed());
+    let claimant = config.claimant(task);
+    let archive = archive_enabled()?;
+    debug!("Scraper {} started \n", claimant);
 
     loop {
-        let transactions = fetch_worker_transactions(worker_id, pool).await?;
+        let transactions = claim_transactions(
+            pool,
+            &PROGRAM_CONFIG.cluster,
+            &claimant,
+            CLAIM_BATCH_SIZE,
+            config.lease_secs,
+        )
+        .await?;
         if transactions.is_empty() {
-            debug!("No signatures found by scraper {}", worker_id);
+            debug!("No signatures found by scraper {}", claimant);
             tokio::time::sleep(WaitDuration::from_secs(1)).await;
             continue;
         };
+        scrape_claimed_transactions(source, pool, markets, archive, transactions).await?;
+    }
+}
+
+/// Fetches a batch of claimed transactions and ingests them.
+async fn scrape_claimed_transactions(
+    source: &dyn TransactionSource,
+    pool: &Pool,
+    markets: &MarketRegistry,
+    archive: bool,
+    transactions: Vec<PgTransaction>,
+) -> anyhow::Result<()> {
+    let mut sig_strings = vec![];
+    let mut signatures = vec![];
+    let mut invalid_sigs = vec![];
+    for t in transactions.into_iter() {
+        match t.signature.parse::<Signature>() {
+            Ok(signature) => {
+                signatures.push(signature);
+                sig_strings.push(t.signature);
+            }
+            Err(e) => {
+                warn!("invalid signature {}: {}", t.signature, e);
+                invalid_sigs.push((t.signature, ParseError::InvalidSignature(e.to_string())));
+            }
+        }
+    }
+    if !invalid_sigs.is_empty() {
+        record_failed_transactions(pool, invalid_sigs).await?;
+    }
+
+    // for each signature, fetch the transaction
+    let mut txns = source.get_transactions(&signatures).await;
+    fill_missing_block_times(source, &mut txns).await;
+
+    ingest_transactions(pool, markets, archive, txns, sig_strings).await?;
+    Ok(())
+}
+
+/// Parses fetched transactions and writes their fills, events and new markets in one database
+/// transaction, marking them processed. Transactions that failed to parse get a retry instead.
+/// Returns the number of failed transactions.
+pub async fn ingest_transactions(
+    pool: &Pool,
+    markets: &MarketRegistry,
+    archive: bool,
+    mut txns: Vec<ClientResult<EncodedConfirmedTransactionWithStatusMeta>>,
+    sig_strings: Vec<String>,
+) -> anyhow::Result<usize> {
+    let target_markets = markets.snapshot();
+    let archive_sigs = sig_strings.clone();
+    let ParsedTransactions {
+        fills,
+        new_markets,
+        events,
+        completed_sigs,
+        truncated_sigs,
+        failed_sigs,
+        unknown_markets,
+    } = parse_openbook_txns(
+        &mut txns,
+        sig_strings,
+        &target_markets,
+        markets.all_markets(),
+    );
+    let archived = if archive {
+        archive_fetched_transactions(&txns, &archive_sigs, &completed_sigs)
+    } else {
+        vec![]
+    };
+    // every market is scraped, so markets missing from the registry have to be imported
+    if markets.all_markets() && !unknown_markets.is_empty() {
+        let num_queued =
+            queue_market_imports(pool, &PROGRAM_CONFIG.cluster, unknown_markets).await?;
+        if num_queued > 0 {
+            info!("Queued {} unknown markets for import", num_queued);
+        }
+    }
+    for fill in fills.iter() {
+        let market_metadata = target_markets.get(&fill.market_pk).unwrap();
+        METRIC_FILLS_TOTAL
+            .with_label_values(&[&market_metadata.market_name])
+            .inc();
+    }
+    let num_failed = failed_sigs.len();
+    // Write to the database, and update properly fetched transactions as processed
+    insert_atomically(
+        pool,
+        fills,
+        new_markets,
+        events,
+        completed_sigs,
+        truncated_sigs,
+        failed_sigs,
+        archived,
+    )
+    .await?;
+    Ok(num_failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::{
+            fetch::{fetch_dead_letter_transactions, fetch_fills_from},
+            insert::requeue_transactions_for_rescrape,
+        },
+        scraper::source::ReplayTransactionSource,
+        test_utils::{fill_log, fill_transaction, market, test_pool},
+        utils::to_timestampz,
+    };
+
+    #[test]
+    fn poll_interval_backs_off_when_quiet() {
+        let interval = next_poll_interval(WaitDuration::from_secs(1), 0);
+        assert_eq!(interval, WaitDuration::from_secs(2));
+        assert_eq!(next_poll_interval(MAX_POLL_INTERVAL, 0), MAX_POLL_INTERVAL);
+    }
+
+    #[test]
+    fn poll_interval_shortens_when_busy() {
+        let interval = next_poll_interval(WaitDuration::from_secs(4), BUSY_POLL_SIGNATURES + 1);
+        assert_eq!(interval, WaitDuration::from_secs(2));
+        assert_eq!(
+            next_poll_interval(MIN_POLL_INTERVAL, BUSY_POLL_SIGNATURES + 1),
+            MIN_POLL_INTERVAL
+        );
+    }
+
+    #[test]
+    fn poll_interval_holds_under_normal_traffic() {
+        let interval = next_poll_interval(WaitDuration::from_secs(3), BUSY_POLL_SIGNATURES);
+        assert_eq!(interval, WaitDuration::from_secs(3));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn replayed_transactions_are_ingested() {
+        let pool = test_pool("replayed_transactions_are_ingested").await;
+        let market = market();
+        let markets = MarketRegistry::new(vec![market.clone()], None);
+        let source = ReplayTransactionSource::from_transactions(vec![
+            fill_transaction(
+                &market,
+                100,
+                Some(1_700_000_000),
+                &[fill_log(&market, 1, 20, 1_000_000)],
+            ),
+            fill_transaction(
+                &market,
+                101,
+                Some(1_700_000_060),
+                &[
+                    fill_log(&market, 2, 20, 1_000_000),
+                    fill_log(&market, 3, 21, 1_000_000),
+                ],
+            ),
+        ])
+        .unwrap();
+        let address: Pubkey = market.market_pk.parse().unwrap();
+
+        // poll the signatures of the market, as `scrape_signatures` does
+        let sigs = fetch_signatures_until(&source, &address, None)
+            .await
+            .unwrap();
+        let checkpoint = ScraperState {
+            address: address.to_string(),
+            last_signature: sigs[0].signature.clone(),
+            last_slot: sigs[0].slot,
+        };
+        let transactions = sigs
+            .into_iter()
+            .map(|s| {
+                PgTransaction::from_rpc_confirmed_transaction(s, &PROGRAM_CONFIG.program_ids[0])
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        insert_signatures_with_checkpoint(&pool, transactions, &checkpoint)
+            .await
+            .unwrap();
 
-        // for each signature, fetch the transaction
-        let txn_config = RpcTransactionConfig {
-            encoding: Some(UiTransactionEncoding::Json),
-            commitment: Some(CommitmentConfig::confirmed()),
-            max_supported_transaction_version: Some(1),
+        // then claim and ingest them, as `scrape_transactions` does
+        let claimed =
+            claim_transactions(&pool, &PROGRAM_CONFIG.cluster, "test", CLAIM_BATCH_SIZE, 60)
+                .await
+                .unwrap();
+        assert_eq!(claimed.len(), 2);
+        scrape_claimed_transactions(&source, &pool, &markets, false, claimed)
+            .await
+            .unwrap();
+
+        let fills = fetch_fills_from(
+            &pool,
+            &market.market_pk,
+            to_timestampz(1_699_999_000),
+            to_timestampz(1_700_001_000),
+        )
+        .await
+        .unwrap();
+        let mut seq_nums: Vec<(u64, u64)> = fills.iter().map(|f| (f.seq_num, f.slot)).collect();
+        seq_nums.sort();
+        assert_eq!(seq_nums, vec![(1, 100), (2, 101), (3, 101)]);
+        assert!(
+            claim_transactions(&pool, &PROGRAM_CONFIG.cluster, "test", CLAIM_BATCH_SIZE, 60)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn only_transactions_of_the_cluster_are_requeued_and_claimed() {
+        let pool = test_pool("only_transactions_of_the_cluster_are_requeued_and_claimed").await;
+        let transaction = |cluster: &str| PgTransaction {
+            signature: Signature::new_unique().to_string(),
+            program_pk: PROGRAM_CONFIG.program_ids[0].to_string(),
+            block_datetime: to_timestampz(1_700_000_000),
+            slot: 100,
+            err: false,
+            processed: true,
+            cluster: cluster.to_string(),
         };
+        let own = transaction(&PROGRAM_CONFIG.cluster);
+        let other = transaction("other-cluster");
 
-        let sig_strings = transactions
-            .iter()
-            .map(|t| t.signature.clone())
-            .collect::<Vec<String>>();
+        let num_queued = requeue_transactions_for_rescrape(
+            &pool,
+            &PROGRAM_CONFIG.cluster,
+            vec![own.clone(), other],
+            Some((100, 100)),
+        )
+        .await
+        .unwrap();
+        assert_eq!(num_queued, 1);
 
-        let signatures: Vec<_> = transactions
-            .into_iter()
-            .map(|t| t.signature.parse::<Signature>().unwrap())
-            .collect();
+        let claimed =
+            claim_transactions(&pool, &PROGRAM_CONFIG.cluster, "test", CLAIM_BATCH_SIZE, 60)
+                .await
+                .unwrap();
+        assert_eq!(claimed.len(), 1);
+        assert_eq!(claimed[0].signature, own.signature);
+    }
 
-        let txn_futs: Vec<_> = signatures
-            .iter()
-            .map(|s| rpc_client.get_transaction_with_config(s, txn_config))
-            .collect();
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn invalid_signatures_are_dead_lettered() {
+        let pool = test_pool("invalid_signatures_are_dead_lettered").await;
+        let transaction = PgTransaction {
+            signature: "not-a-signature".to_string(),
+            program_pk: PROGRAM_CONFIG.program_ids[0].to_string(),
+            block_datetime: to_timestampz(1_700_000_000),
+            slot: 100,
+            err: false,
+            processed: false,
+            cluster: PROGRAM_CONFIG.cluster.clone(),
+        };
+        requeue_transactions_for_rescrape(&pool, &PROGRAM_CONFIG.cluster, vec![transaction], None)
+            .await
+            .unwrap();
+        let claimed =
+            claim_transactions(&pool, &PROGRAM_CONFIG.cluster, "test", CLAIM_BATCH_SIZE, 60)
+                .await
+                .unwrap();
+        let source = ReplayTransactionSource::from_transactions(vec![]).unwrap();
+        let markets = MarketRegistry::new(vec![market()], None);
 
-        let mut txns = join_all(txn_futs).await;
+        scrape_claimed_transactions(&source, &pool, &markets, false, claimed)
+            .await
+            .unwrap();
 
-        let (fills, new_markets, completed_sigs) =
-            parse_openbook_txns(&mut txns, sig_strings, target_markets);
-        for fill in fills.iter() {
-            let market_metadata = target_markets.get(&fill.market_pk).unwrap();
-            METRIC_FILLS_TOTAL
-                .with_label_values(&[&market_metadata.market_name])
-                .inc();
-        }
-        // Write to the database, and update properly fetched transactions as processed
-        insert_atomically(pool, worker_id, fills, new_markets, completed_sigs).await?;
+        let dead_letters = fetch_dead_letter_transactions(&pool).await.unwrap();
+        assert_eq!(dead_letters.len(), 1);
+        assert_eq!(dead_letters[0].signature, "not-a-signature");
+        assert_eq!(dead_letters[0].attempts, 1);
+        assert!(dead_letters[0]
+            .last_error
+            .as_deref()
+            .unwrap()
+            .starts_with("invalid signature"));
     }
 }
@@ -0,0 +1,469 @@
+use async_trait::async_trait;
+use log::info;
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind, Result as ClientResult},
+    rpc_client::GetConfirmedSignaturesForAddress2Config,
+    rpc_response::RpcConfirmedTransactionStatusWithSignature,
+};
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use solana_transaction_status::{
+    EncodedConfirmedTransactionWithStatusMeta, TransactionConfirmationStatus,
+};
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use super::{
+    parsing::{transaction_account_keys, transaction_signature},
+    rpc_pool::RpcPool,
+};
+
+const DEFAULT_SIGNATURE_LIMIT: usize = 1000;
+
+/// Where signatures and transactions are read from. Keeping this behind a trait lets the
+/// parsing and insert pipeline run against recorded transactions as well as a live RPC.
+#[async_trait]
+pub trait TransactionSource: Send + Sync {
+    /// Mirrors `getSignaturesForAddress`: newest first, bounded by `before` and `until`.
+    async fn get_signatures_for_address(
+        &self,
+        address: &Pubkey,
+        config: GetConfirmedSignaturesForAddress2Config,
+    ) -> ClientResult<Vec<RpcConfirmedTransactionStatusWithSignature>>;
+
+    /// Fetches the given transactions, returning one result per signature in the same order.
+    async fn get_transactions(
+        &self,
+        signatures: &[Signature],
+    ) -> Vec<ClientResult<EncodedConfirmedTransactionWithStatusMeta>>;
+
+    /// The latest slot at finalized commitment.
+    async fn get_finalized_slot(&self) -> ClientResult<u64>;
+
+    /// Mirrors `getBlocks` at finalized commitment: the slots between `start_slot` and
+    /// `end_slot`, inclusive, that have a block on the finalized chain.
+    async fn get_finalized_blocks(&self, start_slot: u64, end_slot: u64) -> ClientResult<Vec<u64>>;
+
+    /// Mirrors `getBlockTime`: the estimated production time of the block at `slot`.
+    async fn get_block_time(&self, slot: u64) -> ClientResult<i64>;
+
+    /// Mirrors `getBlock` at finalized commitment with full transaction details: every
+    /// transaction of the block at `slot` in block order, with the slot and time of the block.
+    async fn get_block_transactions(
+        &self,
+        slot: u64,
+    ) -> ClientResult<Vec<EncodedConfirmedTransactionWithStatusMeta>>;
+}
+
+/// RPC nodes sometimes return transactions without a block time. Fills them in with
+/// `getBlockTime` for their slot, leaving the ones it has no time for either.
+pub async fn fill_missing_block_times(
+    source: &dyn TransactionSource,
+    txns: &mut [ClientResult<EncodedConfirmedTransactionWithStatusMeta>],
+) {
+    let mut block_times: HashMap<u64, Option<i64>> = HashMap::new();
+    for txn in txns.iter_mut().filter_map(|t| t.as_mut().ok()) {
+        if txn.block_time.is_some() {
+            continue;
+        }
+        if let Entry::Vacant(entry) = block_times.entry(txn.slot) {
+            entry.insert(source.get_block_time(txn.slot).await.ok());
+        }
+        txn.block_time = block_times[&txn.slot];
+    }
+}
+
+/// Fills in the signatures returned without a block time with `getBlockTime` for their slot.
+/// Fails if a block time can't be fetched, so that the page is retried rather than stored
+/// without one.
+pub async fn fill_missing_signature_block_times(
+    source: &dyn TransactionSource,
+    sigs: &mut [RpcConfirmedTransactionStatusWithSignature],
+) -> ClientResult<()> {
+    let mut block_times: HashMap<u64, i64> = HashMap::new();
+    for sig in sigs.iter_mut().filter(|s| s.block_time.is_none()) {
+        let block_time = match block_times.get(&sig.slot) {
+            Some(block_time) => *block_time,
+            None => {
+                let block_time = source.get_block_time(sig.slot).await?;
+                block_times.insert(sig.slot, block_time);
+                block_time
+            }
+        };
+        sig.block_time = Some(block_time);
+    }
+    Ok(())
+}
+
+/// Uses the replay backend when `REPLAY_PATH` is set, and the RPC endpoints otherwise.
+pub fn transaction_source_from_env() -> anyhow::Result<Arc<dyn TransactionSource>> {
+    match dotenv::var("REPLAY_PATH") {
+        Ok(path) => Ok(Arc::new(ReplayTransactionSource::from_path(path)?)),
+        Err(_) => Ok(Arc::new(RpcPool::from_env()?)),
+    }
+}
+
+/// Serves transactions recorded as `EncodedConfirmedTransactionWithStatusMeta` JSON, so that
+/// ingestion can be re-run deterministically without an RPC. The path can be a single file or a
+/// directory; `.json` files hold one transaction or an array of them, `.jsonl` files hold one
+/// transaction per line.
+pub struct ReplayTransactionSource {
+    /// Newest first, like `getSignaturesForAddress`.
+    transactions: Vec<ReplayedTransaction>,
+    by_signature: HashMap<String, usize>,
+}
+
+struct ReplayedTransaction {
+    signature: String,
+    account_keys: Vec<String>,
+    transaction: EncodedConfirmedTransactionWithStatusMeta,
+}
+
+impl ReplayTransactionSource {
+    pub fn from_path(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let files: Vec<PathBuf> = if path.is_dir() {
+            let mut files: Vec<PathBuf> = fs::read_dir(path)?
+                .filter_map(|e| e.ok().map(|e| e.path()))
+                .filter(|p| {
+                    matches!(
+                        p.extension().and_then(|e| e.to_str()),
+                        Some("json" | "jsonl")
+                    )
+                })
+                .collect();
+            files.sort();
+            files
+        } else {
+            vec![path.to_path_buf()]
+        };
+
+        let mut recorded = vec![];
+        for file in files.iter() {
+            recorded.append(&mut read_recorded_transactions(file)?);
+        }
+        let source = Self::from_transactions(recorded)?;
+        info!(
+            "Replaying {} transactions from {}",
+            source.transactions.len(),
+            path.display()
+        );
+        Ok(source)
+    }
+
+    pub fn from_transactions(
+        recorded: Vec<EncodedConfirmedTransactionWithStatusMeta>,
+    ) -> anyhow::Result<Self> {
+        let mut transactions = vec![];
+        for transaction in recorded.into_iter() {
+            let signature = transaction_signature(&transaction.transaction)
+                .ok_or_else(|| anyhow::anyhow!("recorded transaction has no signature"))?;
+            let account_keys = transaction_account_keys(&transaction.transaction);
+            transactions.push(ReplayedTransaction {
+                signature,
+                account_keys,
+                transaction,
+            });
+        }
+        // stable, so transactions within a slot keep their recorded order
+        transactions.sort_by_key(|t| std::cmp::Reverse(t.transaction.slot));
+        let mut seen = HashSet::new();
+        transactions.retain(|t| seen.insert(t.signature.clone()));
+
+        let by_signature = transactions
+            .iter()
+            .enumerate()
+            .map(|(idx, t)| (t.signature.clone(), idx))
+            .collect();
+        Ok(ReplayTransactionSource {
+            transactions,
+            by_signature,
+        })
+    }
+}
+
+/// Reads a `.json` or `.jsonl` file of recorded transactions.
+pub fn read_recorded_transactions(
+    file: &Path,
+) -> anyhow::Result<Vec<EncodedConfirmedTransactionWithStatusMeta>> {
+    let contents = fs::read_to_string(file)?;
+    if file.extension().and_then(|e| e.to_str()) == Some("jsonl") {
+        let mut transactions = vec![];
+        for line in contents.lines().filter(|l| !l.trim().is_empty()) {
+            transactions.push(serde_json::from_str(line)?);
+        }
+        return Ok(transactions);
+    }
+    match serde_json::from_str::<Vec<EncodedConfirmedTransactionWithStatusMeta>>(&contents) {
+        Ok(transactions) => Ok(transactions),
+        Err(_) => Ok(vec![serde_json::from_str(&contents)?]),
+    }
+}
+
+#[async_trait]
+impl TransactionSource for ReplayTransactionSource {
+    async fn get_signatures_for_address(
+        &self,
+        address: &Pubkey,
+        config: GetConfirmedSignaturesForAddress2Config,
+    ) -> ClientResult<Vec<RpcConfirmedTransactionStatusWithSignature>> {
+        let address = address.to_string();
+        let start = match config.before {
+            Some(before) => match self.by_signature.get(&before.to_string()) {
+                Some(idx) => idx + 1,
+                None => return Ok(vec![]),
+            },
+            None => 0,
+        };
+        let until = config.until.map(|s| s.to_string());
+        let limit = config.limit.unwrap_or(DEFAULT_SIGNATURE_LIMIT);
+
+        Ok(self.transactions[start..]
+            .iter()
+            .take_while(|t| Some(&t.signature) != until.as_ref())
+            .filter(|t| t.account_keys.contains(&address))
+            .take(limit)
+            .map(|t| RpcConfirmedTransactionStatusWithSignature {
+                signature: t.signature.clone(),
+                slot: t.transaction.slot,
+                err: t
+                    .transaction
+                    .transaction
+                    .meta
+                    .as_ref()
+                    .and_then(|m| m.err.clone()),
+                memo: None,
+                block_time: t.transaction.block_time,
+                confirmation_status: Some(TransactionConfirmationStatus::Confirmed),
+            })
+            .collect())
+    }
+
+    async fn get_transactions(
+        &self,
+        signatures: &[Signature],
+    ) -> Vec<ClientResult<EncodedConfirmedTransactionWithStatusMeta>> {
+        signatures
+            .iter()
+            .map(|s| match self.by_signature.get(&s.to_string()) {
+                Some(idx) => {
+                    let recorded = &self.transactions[*idx].transaction;
+                    Ok(EncodedConfirmedTransactionWithStatusMeta {
+                        slot: recorded.slot,
+                        transaction: recorded.transaction.clone(),
+                        block_time: recorded.block_time,
+                    })
+                }
+                None => Err(ClientError::from(ClientErrorKind::Custom(format!(
+                    "transaction {} not found in replay",
+                    s
+                )))),
+            })
+            .collect()
+    }
+
+    /// Recorded transactions are treated as finalized, up to the newest of them.
+    async fn get_finalized_slot(&self) -> ClientResult<u64> {
+        Ok(self
+            .transactions
+            .first()
+            .map(|t| t.transaction.slot)
+            .unwrap_or(0))
+    }
+
+    /// A replay can't tell a skipped slot from a slot it has no recordings of, so every slot up
+    /// to the newest recorded one is reported as a block. Finalization against a replay then
+    /// never orphans a fill.
+    async fn get_finalized_blocks(&self, start_slot: u64, end_slot: u64) -> ClientResult<Vec<u64>> {
+        let end_slot = end_slot.min(self.get_finalized_slot().await?);
+        Ok((start_slot..=end_slot).collect())
+    }
+
+    async fn get_block_time(&self, slot: u64) -> ClientResult<i64> {
+        self.transactions
+            .iter()
+            .filter(|t| t.transaction.slot == slot)
+            .find_map(|t| t.transaction.block_time)
+            .ok_or_else(|| {
+                ClientError::from(ClientErrorKind::Custom(format!(
+                    "no block time for slot {} in replay",
+                    slot
+                )))
+            })
+    }
+
+    /// Only the recorded transactions of the slot, in the order they were recorded.
+    async fn get_block_transactions(
+        &self,
+        slot: u64,
+    ) -> ClientResult<Vec<EncodedConfirmedTransactionWithStatusMeta>> {
+        Ok(self
+            .transactions
+            .iter()
+            .filter(|t| t.transaction.slot == slot)
+            .map(|t| EncodedConfirmedTransactionWithStatusMeta {
+                slot: t.transaction.slot,
+                transaction: t.transaction.transaction.clone(),
+                block_time: t.transaction.block_time,
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::recorded_transaction;
+
+    fn transactions_of(
+        address: &Pubkey,
+        slots: &[u64],
+    ) -> Vec<EncodedConfirmedTransactionWithStatusMeta> {
+        slots
+            .iter()
+            .map(|slot| {
+                recorded_transaction(*slot, Some(*slot as i64), &[address.to_string()], vec![])
+            })
+            .collect()
+    }
+
+    fn signature_of(txn: &EncodedConfirmedTransactionWithStatusMeta) -> Signature {
+        transaction_signature(&txn.transaction)
+            .unwrap()
+            .parse()
+            .unwrap()
+    }
+
+    async fn listed_slots(
+        source: &ReplayTransactionSource,
+        address: &Pubkey,
+        config: GetConfirmedSignaturesForAddress2Config,
+    ) -> Vec<u64> {
+        source
+            .get_signatures_for_address(address, config)
+            .await
+            .unwrap()
+            .iter()
+            .map(|s| s.slot)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn signatures_are_listed_newest_first_once() {
+        let address = Pubkey::new_unique();
+        let mut recorded = transactions_of(&address, &[100, 102, 101]);
+        let duplicate = serde_json::to_value(&recorded[1]).unwrap();
+        recorded.push(serde_json::from_value(duplicate).unwrap());
+        recorded.extend(transactions_of(&Pubkey::new_unique(), &[103]));
+        let source = ReplayTransactionSource::from_transactions(recorded).unwrap();
+
+        let slots = listed_slots(&source, &address, Default::default()).await;
+        assert_eq!(slots, vec![102, 101, 100]);
+    }
+
+    #[tokio::test]
+    async fn signatures_are_paged_with_before_until_and_limit() {
+        let address = Pubkey::new_unique();
+        let recorded = transactions_of(&address, &[100, 101, 102, 103, 104]);
+        let sig_101 = signature_of(&recorded[1]);
+        let sig_103 = signature_of(&recorded[3]);
+        let source = ReplayTransactionSource::from_transactions(recorded).unwrap();
+
+        let config = GetConfirmedSignaturesForAddress2Config {
+            limit: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            listed_slots(&source, &address, config).await,
+            vec![104, 103]
+        );
+
+        let config = GetConfirmedSignaturesForAddress2Config {
+            before: Some(sig_103),
+            limit: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            listed_slots(&source, &address, config).await,
+            vec![102, 101]
+        );
+
+        let config = GetConfirmedSignaturesForAddress2Config {
+            until: Some(sig_101),
+            ..Default::default()
+        };
+        assert_eq!(
+            listed_slots(&source, &address, config).await,
+            vec![104, 103, 102]
+        );
+
+        // a `before` signature that was not recorded lists nothing
+        let config = GetConfirmedSignaturesForAddress2Config {
+            before: Some(Signature::new_unique()),
+            ..Default::default()
+        };
+        assert!(listed_slots(&source, &address, config).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn recordings_are_loaded_from_json_and_jsonl_files() {
+        let address = Pubkey::new_unique();
+        let recorded = transactions_of(&address, &[100, 101, 102, 103, 104]);
+        let dir = std::env::temp_dir().join(format!("replay-{}", Pubkey::new_unique()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("a.json"),
+            serde_json::to_string(&recorded[0..2]).unwrap(),
+        )
+        .unwrap();
+        fs::write(
+            dir.join("b.json"),
+            serde_json::to_string(&recorded[2]).unwrap(),
+        )
+        .unwrap();
+        let lines: Vec<String> = recorded[3..]
+            .iter()
+            .map(|t| serde_json::to_string(t).unwrap())
+            .collect();
+        fs::write(dir.join("c.jsonl"), lines.join("\n")).unwrap();
+        fs::write(dir.join("notes.txt"), "not a recording").unwrap();
+
+        let source = ReplayTransactionSource::from_path(&dir).unwrap();
+        let single_file = ReplayTransactionSource::from_path(dir.join("c.jsonl")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let slots = listed_slots(&source, &address, Default::default()).await;
+        assert_eq!(slots, vec![104, 103, 102, 101, 100]);
+        let slots = listed_slots(&single_file, &address, Default::default()).await;
+        assert_eq!(slots, vec![104, 103]);
+    }
+
+    #[tokio::test]
+    async fn transactions_and_blocks_are_served_from_recordings() {
+        let address = Pubkey::new_unique();
+        let mut recorded = transactions_of(&address, &[100, 102]);
+        recorded.push(recorded_transaction(102, None, &[], vec![]));
+        let sig_100 = signature_of(&recorded[0]);
+        let source = ReplayTransactionSource::from_transactions(recorded).unwrap();
+
+        let txns = source
+            .get_transactions(&[sig_100, Signature::new_unique()])
+            .await;
+        assert_eq!(txns[0].as_ref().unwrap().slot, 100);
+        assert!(txns[1].is_err());
+
+        assert_eq!(source.get_block_time(102).await.unwrap(), 102);
+        assert!(source.get_block_time(101).await.is_err());
+        assert_eq!(source.get_block_transactions(102).await.unwrap().len(), 2);
+        assert!(source.get_block_transactions(101).await.unwrap().is_empty());
+
+        assert_eq!(source.get_finalized_slot().await.unwrap(), 102);
+        assert_eq!(
+            source.get_finalized_blocks(100, 110).await.unwrap(),
+            vec![100, 101, 102]
+        );
+    }
+}
@@ -0,0 +1,432 @@
+use deadpool_postgres::Pool;
+use futures::{Future, StreamExt};
+use log::{debug, info, warn};
+use solana_client::{
+    nonblocking::pubsub_client::PubsubClient,
+    rpc_config::{RpcTransactionLogsConfig, RpcTransactionLogsFilter},
+};
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey};
+use std::{
+    collections::{HashSet, VecDeque},
+    time::Duration as WaitDuration,
+};
+
+use crate::{
+    database::insert::insert_streamed_fills,
+    structs::openbook_v2::OpenBookFill,
+    utils::{market_registry::MarketRegistry, to_timestampz},
+    worker::metrics::{METRIC_WS_FILLS_TOTAL, METRIC_WS_RECONNECTS_TOTAL},
+};
+
+use super::{parsing::try_parse_openbook_fills_from_logs, source::TransactionSource};
+
+const MIN_RECONNECT_DELAY: WaitDuration = WaitDuration::from_millis(500);
+const MAX_RECONNECT_DELAY: WaitDuration = WaitDuration::from_secs(30);
+/// Number of recent (market_pk, seq_num) pairs remembered to drop fills that are
+/// delivered again after a resubscription. Also bounds the fills waiting to be written.
+const RECENT_FILLS_CAPACITY: usize = 10_000;
+/// A block can be confirmed shortly before its time is served, so `getBlockTime` is retried
+/// a few times before the fills of the slot are left to the polled path.
+const BLOCK_TIME_ATTEMPTS: usize = 3;
+const BLOCK_TIME_RETRY_DELAY: WaitDuration = WaitDuration::from_millis(400);
+
+/// Bounded set of recently written fills.
+struct RecentFills {
+    seen: HashSet<(String, u64)>,
+    order: VecDeque<(String, u64)>,
+}
+
+impl RecentFills {
+    fn new() -> Self {
+        RecentFills {
+            seen: HashSet::with_capacity(RECENT_FILLS_CAPACITY),
+            order: VecDeque::with_capacity(RECENT_FILLS_CAPACITY),
+        }
+    }
+
+    fn contains(&self, fill: &OpenBookFill) -> bool {
+        self.seen.contains(&(fill.market_pk.clone(), fill.seq_num))
+    }
+
+    /// Only called once a fill has been written, so that a failed write is tried again.
+    fn record(&mut self, fill: &OpenBookFill) {
+        let key = (fill.market_pk.clone(), fill.seq_num);
+        if !self.seen.insert(key.clone()) {
+            return;
+        }
+        self.order.push_back(key);
+        if self.order.len() > RECENT_FILLS_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// Fills that have been streamed but not written yet. A failed write leaves them here to be
+/// written with the next batch, across resubscriptions.
+struct PendingFills {
+    fills: Vec<OpenBookFill>,
+}
+
+impl PendingFills {
+    fn new() -> Self {
+        PendingFills { fills: vec![] }
+    }
+
+    fn contains(&self, fill: &OpenBookFill) -> bool {
+        self.fills
+            .iter()
+            .any(|f| f.market_pk == fill.market_pk && f.seq_num == fill.seq_num)
+    }
+
+    /// Drops the oldest fills past the capacity, the polled path writes them instead.
+    fn push(&mut self, fills: Vec<OpenBookFill>) {
+        self.fills.extend(fills);
+        if self.fills.len() > RECENT_FILLS_CAPACITY {
+            let dropped = self.fills.len() - RECENT_FILLS_CAPACITY;
+            warn!(
+                "dropping {} streamed fills that could not be written",
+                dropped
+            );
+            self.fills.drain(..dropped);
+        }
+    }
+}
+
+/// Subscribes to the logs of an OpenBook program over the pubsub websocket and writes fills as
+/// soon as they are seen. A logs notification has no block time, so it is fetched with
+/// `getBlockTime` for the slot of the notification; fills of a slot without a block time are
+/// left to the polled path. Disconnects are retried with exponential backoff.
+pub async fn stream_fills(
+    ws_url: String,
+    program_id: Pubkey,
+    source: &dyn TransactionSource,
+    pool: &Pool,
+    markets: &MarketRegistry,
+) -> anyhow::Result<()> {
+    let mut recent_fills = RecentFills::new();
+    let mut pending_fills = PendingFills::new();
+    let mut write_fills = |fills| insert_streamed_fills(pool, fills);
+    let mut reconnect_delay = MIN_RECONNECT_DELAY;
+
+    loop {
+        match subscribe_and_insert(
+            &ws_url,
+            &program_id,
+            source,
+            markets,
+            &mut recent_fills,
+            &mut pending_fills,
+            &mut write_fills,
+        )
+        .await
+        {
+            Ok(received) => {
+                warn!("logs subscription closed, resubscribing");
+                if received {
+                    reconnect_delay = MIN_RECONNECT_DELAY;
+                }
+            }
+            Err(e) => warn!("logs subscription failed: {:?}", e),
+        }
+        METRIC_WS_RECONNECTS_TOTAL.inc();
+        tokio::time::sleep(reconnect_delay).await;
+        reconnect_delay = (reconnect_delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+/// Runs a single subscription until the stream ends. Returns whether any notification was
+/// received, so the caller can reset its backoff.
+async fn subscribe_and_insert<F, Fut>(
+    ws_url: &str,
+    program_id: &Pubkey,
+    source: &dyn TransactionSource,
+    markets: &MarketRegistry,
+    recent_fills: &mut RecentFills,
+    pending_fills: &mut PendingFills,
+    write_fills: &mut F,
+) -> anyhow::Result<bool>
+where
+    F: FnMut(Vec<OpenBookFill>) -> Fut,
+    Fut: Future<Output = anyhow::Result<u64>>,
+{
+    let pubsub_client = PubsubClient::new(ws_url).await?;
+    let (mut notifications, unsubscribe) = pubsub_client
+        .logs_subscribe(
+            RpcTransactionLogsFilter::Mentions(vec![program_id.to_string()]),
+            RpcTransactionLogsConfig {
+                commitment: Some(CommitmentConfig::confirmed()),
+            },
+        )
+        .await?;
+    info!("Subscribed to logs for {}", program_id);
+
+    let mut received = false;
+    let mut last_block_time: Option<(u64, i64)> = None;
+    while let Some(notification) = notifications.next().await {
+        received = true;
+        let logs_response = notification.value;
+        let slot = notification.context.slot;
+        if logs_response.err.is_some() {
+            continue;
+        }
+        let target_markets = markets.snapshot();
+        // the block time is only fetched for notifications with fills, and stamped below
+        let fills = match try_parse_openbook_fills_from_logs(
+            &logs_response.logs,
+            &target_markets,
+            &logs_response.signature,
+            0,
+            slot,
+        ) {
+            Some(fills) => fills,
+            None => continue,
+        };
+
+        let mut new_fills: Vec<OpenBookFill> = fills
+            .into_iter()
+            .filter(|f| !recent_fills.contains(f) && !pending_fills.contains(f))
+            .collect();
+        if new_fills.is_empty() {
+            continue;
+        }
+        let block_time = match last_block_time {
+            Some((block_slot, block_time)) if block_slot == slot => block_time,
+            _ => match fetch_block_time(source, slot).await {
+                Some(block_time) => {
+                    last_block_time = Some((slot, block_time));
+                    block_time
+                }
+                None => {
+                    debug!(
+                        "No block time for slot {}, leaving the fills of {} to the polled path",
+                        slot, logs_response.signature
+                    );
+                    continue;
+                }
+            },
+        };
+        for fill in new_fills.iter_mut() {
+            fill.block_datetime = to_timestampz(block_time as u64);
+        }
+        debug!(
+            "Streamed {} fills from {}",
+            new_fills.len(),
+            logs_response.signature
+        );
+        pending_fills.push(new_fills);
+
+        match write_fills(pending_fills.fills.clone()).await {
+            Ok(_) => {
+                for fill in pending_fills.fills.drain(..) {
+                    if let Some(market_metadata) = target_markets.get(&fill.market_pk) {
+                        METRIC_WS_FILLS_TOTAL
+                            .with_label_values(&[&market_metadata.market_name])
+                            .inc();
+                    }
+                    recent_fills.record(&fill);
+                }
+            }
+            Err(e) => warn!(
+                "failed to write {} streamed fills, retrying with the next batch: {:?}",
+                pending_fills.fills.len(),
+                e
+            ),
+        }
+    }
+
+    unsubscribe().await;
+    Ok(received)
+}
+
+async fn fetch_block_time(source: &dyn TransactionSource, slot: u64) -> Option<i64> {
+    for attempt in 1..=BLOCK_TIME_ATTEMPTS {
+        match source.get_block_time(slot).await {
+            Ok(block_time) => return Some(block_time),
+            Err(e) => debug!("getBlockTime for slot {} failed: {:?}", slot, e),
+        }
+        if attempt < BLOCK_TIME_ATTEMPTS {
+            tokio::time::sleep(BLOCK_TIME_RETRY_DELAY).await;
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::openbook_v2::OpenBookMarketMetadata;
+    use crate::test_utils::{block_times, fill_log, market, program_logs};
+    use crate::utils::PROGRAM_CONFIG;
+    use futures::SinkExt;
+    use serde_json::json;
+    use solana_sdk::signature::Signature;
+    use std::sync::{Arc, Mutex};
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::tungstenite::Message;
+
+    /// A logs notification of a transaction that emitted one fill.
+    fn fill_notification(market: &OpenBookMarketMetadata, seq_num: u64, slot: u64) -> String {
+        json!({
+            "jsonrpc": "2.0",
+            "method": "logsNotification",
+            "params": {
+                "result": {
+                    "context": { "slot": slot },
+                    "value": {
+                        "signature": Signature::new_unique().to_string(),
+                        "err": null,
+                        "logs": program_logs(&[fill_log(market, seq_num, 20_000, 10)]),
+                    },
+                },
+                "subscription": 1,
+            },
+        })
+        .to_string()
+    }
+
+    /// Accepts one websocket connection, confirms the logs subscription, sends the
+    /// notifications and closes the connection.
+    async fn serve_notifications(notifications: Vec<String>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let ws_url = format!("ws://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+            let request = loop {
+                match ws.next().await.unwrap().unwrap() {
+                    Message::Text(text) => break text,
+                    _ => continue,
+                }
+            };
+            let request: serde_json::Value = serde_json::from_str(&request).unwrap();
+            assert_eq!(request["method"], "logsSubscribe");
+            let response = json!({ "jsonrpc": "2.0", "result": 1, "id": request["id"] });
+            ws.send(Message::Text(response.to_string())).await.unwrap();
+            for notification in notifications.into_iter() {
+                ws.send(Message::Text(notification)).await.unwrap();
+            }
+            ws.close(None).await.unwrap();
+        });
+        ws_url
+    }
+
+    /// Runs one subscription against the notifications, failing the first `failures` writes.
+    /// Returns the batches that were written.
+    async fn stream(
+        notifications: Vec<String>,
+        slot_block_times: &[(u64, i64)],
+        market: &OpenBookMarketMetadata,
+        failures: usize,
+        recent_fills: &mut RecentFills,
+    ) -> Vec<Vec<OpenBookFill>> {
+        let ws_url = serve_notifications(notifications).await;
+        let markets = MarketRegistry::new(vec![market.clone()], None);
+        let written = Arc::new(Mutex::new(vec![]));
+        let mut attempts = 0;
+        let mut write_fills = |fills: Vec<OpenBookFill>| {
+            attempts += 1;
+            let fail = attempts <= failures;
+            let written = written.clone();
+            async move {
+                if fail {
+                    return Err(anyhow::anyhow!("insert failed"));
+                }
+                let num_fills = fills.len() as u64;
+                written.lock().unwrap().push(fills);
+                Ok(num_fills)
+            }
+        };
+        let received = subscribe_and_insert(
+            &ws_url,
+            &PROGRAM_CONFIG.program_ids[0],
+            &block_times(slot_block_times),
+            &markets,
+            recent_fills,
+            &mut PendingFills::new(),
+            &mut write_fills,
+        )
+        .await
+        .unwrap();
+        assert!(received);
+        let written = written.lock().unwrap().clone();
+        written
+    }
+
+    #[tokio::test]
+    async fn streamed_fills_get_the_block_time_of_their_slot() {
+        let market = market();
+        let notifications = vec![fill_notification(&market, 1, 100)];
+        let slot_times = [(100, 1_700_000_000)];
+        let written = stream(
+            notifications,
+            &slot_times,
+            &market,
+            0,
+            &mut RecentFills::new(),
+        )
+        .await;
+
+        assert_eq!(written.len(), 1);
+        assert_eq!(written[0][0].seq_num, 1);
+        assert_eq!(written[0][0].slot, 100);
+        assert_eq!(written[0][0].block_datetime, to_timestampz(1_700_000_000));
+    }
+
+    #[tokio::test]
+    async fn fills_without_a_block_time_are_not_written() {
+        let market = market();
+        let notifications = vec![fill_notification(&market, 1, 100)];
+        let mut recent_fills = RecentFills::new();
+        let written = stream(notifications, &[], &market, 0, &mut recent_fills).await;
+
+        assert!(written.is_empty());
+        assert!(recent_fills.seen.is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_writes_are_retried_with_the_next_batch() {
+        let market = market();
+        let notifications = vec![
+            fill_notification(&market, 1, 100),
+            fill_notification(&market, 2, 101),
+        ];
+        let slot_times = [(100, 1_700_000_000), (101, 1_700_000_001)];
+        let mut recent_fills = RecentFills::new();
+        let written = stream(notifications, &slot_times, &market, 1, &mut recent_fills).await;
+
+        assert_eq!(written.len(), 1);
+        let seq_nums: Vec<u64> = written[0].iter().map(|f| f.seq_num).collect();
+        assert_eq!(seq_nums, vec![1, 2]);
+        assert_eq!(recent_fills.seen.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn redelivered_fills_are_written_once() {
+        let market = market();
+        let notification = fill_notification(&market, 1, 100);
+        let slot_times = [(100, 1_700_000_000)];
+        let mut recent_fills = RecentFills::new();
+        let written = stream(
+            vec![notification.clone()],
+            &slot_times,
+            &market,
+            0,
+            &mut recent_fills,
+        )
+        .await;
+        assert_eq!(written.len(), 1);
+
+        // delivered again after a resubscription
+        let written = stream(
+            vec![notification],
+            &slot_times,
+            &market,
+            0,
+            &mut recent_fills,
+        )
+        .await;
+        assert!(written.is_empty());
+    }
+}
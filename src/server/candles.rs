@@ -3,10 +3,11 @@ use openbook_offchain_services::{
     structs::{
         openbook_v2::OpenBookMarketMetadata, resolution::Resolution, tradingview::TvResponse,
     },
-    utils::{to_timestampz, WebContext},
+    utils::{to_timestampz, MarketFilter, WebContext},
 };
 
 use crate::server_error::ServerError;
+use std::str::FromStr;
 
 use {
     actix_web::{get, web, HttpResponse},
@@ -24,12 +25,13 @@ pub struct CandleParams {
 #[get("/candles")]
 pub async fn get_candles(
     info: web::Query<CandleParams>,
+    filter: web::Query<MarketFilter>,
     context: web::Data<WebContext>,
 ) -> Result<HttpResponse, ServerError> {
     let resolution =
         Resolution::from_str(info.resolution.as_str()).map_err(|_| ServerError::WrongResolution)?;
 
-    if !valid_market(&info.market_name, &context.markets) {
+    if !valid_market(&info.market_name, &context.filtered_markets(&filter)) {
         return Err(ServerError::WrongParameters);
     }
 
@@ -45,6 +47,6 @@ pub async fn get_candles(
     Ok(HttpResponse::Ok().json(TvResponse::candles_to_tv(candles)))
 }
 
-fn valid_market(market_name: &str, markets: &Vec<OpenBookMarketMetadata>) -> bool {
+fn valid_market(market_name: &str, markets: &[OpenBookMarketMetadata]) -> bool {
     markets.iter().any(|x| x.market_name == market_name)
 }
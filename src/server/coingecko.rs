@@ -1,18 +1,10 @@
-use std::time::{SystemTime, UNIX_EPOCH};
-
 use crate::server_error::ServerError;
 use actix_web::{get, web, HttpResponse, Scope};
-use futures::join;
 use openbook_offchain_services::{
-    database::fetch::{fetch_coingecko_24h_high_low, fetch_coingecko_24h_volume},
-    structs::coingecko::{
-        CoinGecko24HourVolume, CoinGeckoOrderBook, CoinGeckoPair, CoinGeckoTicker,
-        PgCoinGecko24HighLow,
-    },
-    utils::WebContext,
+    structs::coingecko::CoinGeckoPair,
+    utils::{MarketFilter, WebContext},
 };
 use serde::Deserialize;
-use solana_client::nonblocking::rpc_client::RpcClient;
 
 pub fn service() -> Scope {
     web::scope("/coingecko").service(pairs)
@@ -20,6 +12,8 @@ pub fn service() -> Scope {
     // .service(orderbook)
 }
 
+// used by the orderbook endpoint, once implemented
+#[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 pub struct OrderBookParams {
     pub ticker_id: String, // market_name
@@ -27,8 +21,11 @@ pub struct OrderBookParams {
 }
 
 #[get("/pairs")]
-pub async fn pairs(context: web::Data<WebContext>) -> Result<HttpResponse, ServerError> {
-    let markets = context.markets.clone();
+pub async fn pairs(
+    filter: web::Query<MarketFilter>,
+    context: web::Data<WebContext>,
+) -> Result<HttpResponse, ServerError> {
+    let markets = context.filtered_markets(&filter);
 
     let pairs = markets
         .iter()
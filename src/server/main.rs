@@ -1,7 +1,7 @@
 use actix_web::{
     http::StatusCode,
     middleware::Logger,
-    rt::System,
+    rt::{self, System},
     web::{self, Data},
     App, HttpServer,
 };
@@ -11,8 +11,8 @@ use prometheus::Registry;
 
 use markets::get_markets;
 use openbook_offchain_services::{
-    database::{fetch::fetch_active_markets, initialize::connect_to_database},
-    utils::WebContext,
+    database::initialize::connect_to_database,
+    utils::{market_registry::MarketRegistry, WebContext},
 };
 use std::thread;
 use traders::{get_top_traders_by_base_volume, get_top_traders_by_quote_volume};
@@ -32,7 +32,9 @@ async fn main() -> std::io::Result<()> {
     let bind_addr: String = dotenv::var("SERVER_BIND_ADDR").expect("reading bind addr from env");
 
     let pool = connect_to_database().await.unwrap();
-    let markets = fetch_active_markets(&pool).await.unwrap();
+    let markets = MarketRegistry::load(&pool, None).await.unwrap();
+    let refresh_markets = markets.clone();
+    let refresh_pool = pool.clone();
 
     let registry = Registry::new();
     // For serving metrics on a private port
@@ -78,7 +80,17 @@ async fn main() -> std::io::Result<()> {
         .bind(&bind_addr)
         .unwrap()
         .run();
-        sys.block_on(srv).unwrap();
+        sys.block_on(async move {
+            // keeps the market list up to date while serving
+            rt::spawn(async move {
+                refresh_markets
+                    .refresh_periodically(&refresh_pool)
+                    .await
+                    .unwrap();
+            });
+            srv.await
+        })
+        .unwrap();
     });
 
     // Thread to serve metrics endpoint privately
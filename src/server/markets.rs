@@ -1,9 +1,12 @@
 use crate::server_error::ServerError;
 use actix_web::{get, web, HttpResponse};
-use openbook_offchain_services::utils::WebContext;
+use openbook_offchain_services::utils::{MarketFilter, WebContext};
 
 #[get("/markets")]
-pub async fn get_markets(context: web::Data<WebContext>) -> Result<HttpResponse, ServerError> {
-    let markets = &context.markets;
+pub async fn get_markets(
+    filter: web::Query<MarketFilter>,
+    context: web::Data<WebContext>,
+) -> Result<HttpResponse, ServerError> {
+    let markets = context.filtered_markets(&filter);
     Ok(HttpResponse::Ok().json(markets))
 }
@@ -45,6 +45,6 @@ impl error::ResponseError for ServerError {
 
 impl From<ServerError> for std::io::Error {
     fn from(e: ServerError) -> Self {
-        std::io::Error::new(std::io::ErrorKind::Other, e.to_string())
+        std::io::Error::other(e.to_string())
     }
 }
@@ -4,7 +4,7 @@ use openbook_offchain_services::{
         fetch_top_traders_by_base_volume_from, fetch_top_traders_by_quote_volume_from,
     },
     structs::trader::{TraderResponse, VolumeType},
-    utils::{to_timestampz, WebContext},
+    utils::{to_timestampz, MarketFilter, WebContext},
 };
 use {
     actix_web::{get, web, HttpResponse},
@@ -21,11 +21,12 @@ pub struct TraderParams {
 #[get("/traders/base-volume")]
 pub async fn get_top_traders_by_base_volume(
     info: web::Query<TraderParams>,
+    filter: web::Query<MarketFilter>,
     context: web::Data<WebContext>,
 ) -> Result<HttpResponse, ServerError> {
     let selected_market = context
-        .markets
-        .iter()
+        .filtered_markets(&filter)
+        .into_iter()
         .find(|x| x.market_name == info.market_name);
     if selected_market.is_none() {
         return Err(ServerError::MarketNotFound);
@@ -58,11 +59,12 @@ pub async fn get_top_traders_by_base_volume(
 #[get("/traders/quote-volume")]
 pub async fn get_top_traders_by_quote_volume(
     info: web::Query<TraderParams>,
+    filter: web::Query<MarketFilter>,
     context: web::Data<WebContext>,
 ) -> Result<HttpResponse, ServerError> {
     let selected_market = context
-        .markets
-        .iter()
+        .filtered_markets(&filter)
+        .into_iter()
         .find(|x| x.market_name == info.market_name);
     if selected_market.is_none() {
         return Err(ServerError::MarketNotFound);
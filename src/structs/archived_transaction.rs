@@ -0,0 +1,86 @@
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::Serialize;
+use solana_transaction_status::{
+    option_serializer::OptionSerializer, EncodedConfirmedTransactionWithStatusMeta,
+};
+use std::io::{Read, Write};
+use tokio_postgres::Row;
+
+use crate::utils::PROGRAM_CONFIG;
+
+/// A fetched transaction kept so it can be parsed again without an RPC. Only what the event
+/// decoders read is kept: the message, the log messages, the inner instructions and the loaded
+/// addresses. The payload is the gzipped JSON of the trimmed transaction.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArchivedTransaction {
+    pub signature: String,
+    pub slot: u64,
+    pub block_time: Option<i64>,
+    pub program_pk: String,
+    pub account_keys: Vec<String>,
+    pub cluster: String,
+    pub payload: Vec<u8>,
+}
+
+impl ArchivedTransaction {
+    pub fn from_transaction(
+        signature: &str,
+        program_pk: &str,
+        account_keys: Vec<String>,
+        txn: &EncodedConfirmedTransactionWithStatusMeta,
+    ) -> anyhow::Result<Self> {
+        let mut trimmed = EncodedConfirmedTransactionWithStatusMeta {
+            slot: txn.slot,
+            transaction: txn.transaction.clone(),
+            block_time: txn.block_time,
+        };
+        if let Some(meta) = trimmed.transaction.meta.as_mut() {
+            meta.pre_balances = vec![];
+            meta.post_balances = vec![];
+            meta.pre_token_balances = OptionSerializer::None;
+            meta.post_token_balances = OptionSerializer::None;
+            meta.rewards = OptionSerializer::None;
+            meta.return_data = OptionSerializer::Skip;
+        }
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&serde_json::to_vec(&trimmed)?)?;
+        Ok(ArchivedTransaction {
+            signature: signature.to_string(),
+            slot: txn.slot,
+            block_time: txn.block_time,
+            program_pk: program_pk.to_string(),
+            account_keys,
+            cluster: PROGRAM_CONFIG.cluster.clone(),
+            payload: encoder.finish()?,
+        })
+    }
+
+    /// Decompresses the archived transaction.
+    pub fn transaction(&self) -> anyhow::Result<EncodedConfirmedTransactionWithStatusMeta> {
+        let mut json = vec![];
+        GzDecoder::new(self.payload.as_slice()).read_to_end(&mut json)?;
+        Ok(serde_json::from_slice(&json)?)
+    }
+
+    pub fn from_row(row: Row) -> Self {
+        let slot_raw = row.get::<usize, i64>(1);
+        ArchivedTransaction {
+            signature: row.get(0),
+            slot: slot_raw as u64,
+            block_time: row.get(2),
+            program_pk: row.get(3),
+            account_keys: row.get(4),
+            cluster: row.get(5),
+            payload: row.get(6),
+        }
+    }
+}
+
+/// The outcome of reparsing archived transactions.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ReparseSummary {
+    pub transactions: u64,
+    pub fills: u64,
+    pub failed: Vec<(String, String)>,
+}
@@ -0,0 +1,30 @@
+use chrono::{DateTime, Utc};
+use tokio_postgres::Row;
+
+/// Progress of a historical signature backfill for a single address. The
+/// `before_signature` cursor is the oldest signature written so far, so a
+/// restarted backfill resumes paging from there.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BackfillState {
+    pub address: String,
+    pub before_signature: Option<String>,
+    pub oldest_slot: Option<u64>,
+    pub target_slot: Option<u64>,
+    pub target_datetime: Option<DateTime<Utc>>,
+    pub complete: bool,
+}
+
+impl BackfillState {
+    pub fn from_row(row: Row) -> Self {
+        let oldest_slot_raw = row.get::<usize, Option<i64>>(2);
+        let target_slot_raw = row.get::<usize, Option<i64>>(3);
+        BackfillState {
+            address: row.get(0),
+            before_signature: row.get(1),
+            oldest_slot: oldest_slot_raw.map(|s| s as u64),
+            target_slot: target_slot_raw.map(|s| s as u64),
+            target_datetime: row.get(4),
+            complete: row.get(5),
+        }
+    }
+}
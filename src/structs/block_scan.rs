@@ -0,0 +1,114 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio_postgres::Row;
+
+/// Consecutive slots whose blocks were all read by the block scanner, including the skipped
+/// slots in between. The datetimes are the times of the first and last blocks of the range.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockScanRange {
+    pub cluster: String,
+    pub start_slot: u64,
+    pub end_slot: u64,
+    pub start_datetime: Option<DateTime<Utc>>,
+    pub end_datetime: Option<DateTime<Utc>>,
+    pub num_blocks: i64,
+    pub num_transactions: i64,
+}
+
+impl BlockScanRange {
+    pub fn from_row(row: Row) -> Self {
+        let start_slot_raw = row.get::<usize, i64>(1);
+        let end_slot_raw = row.get::<usize, i64>(2);
+        BlockScanRange {
+            cluster: row.get(0),
+            start_slot: start_slot_raw as u64,
+            end_slot: end_slot_raw as u64,
+            start_datetime: row.get(3),
+            end_datetime: row.get(4),
+            num_blocks: row.get(5),
+            num_transactions: row.get(6),
+        }
+    }
+}
+
+/// Whether every block of a time window was scanned. The window is covered when the scanned
+/// ranges around it are consecutive and start before it and end after it.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct BlockScanCoverage {
+    pub from: String,
+    pub to: String,
+    pub covered: bool,
+    /// Scanned `(start_slot, end_slot)` ranges around the window.
+    pub ranges: Vec<(u64, u64)>,
+    /// Slots between the scanned ranges that were not scanned.
+    pub gaps: Vec<(u64, u64)>,
+}
+
+impl BlockScanCoverage {
+    pub fn from_ranges(from: DateTime<Utc>, to: DateTime<Utc>, ranges: &[BlockScanRange]) -> Self {
+        let mut coverage = BlockScanCoverage {
+            from: from.to_rfc3339(),
+            to: to.to_rfc3339(),
+            ..Default::default()
+        };
+        for r in ranges.iter() {
+            if let Some((_, end_slot)) = coverage.ranges.last_mut() {
+                if r.start_slot <= *end_slot + 1 {
+                    *end_slot = (*end_slot).max(r.end_slot);
+                    continue;
+                }
+                coverage.gaps.push((*end_slot + 1, r.start_slot - 1));
+            }
+            coverage.ranges.push((r.start_slot, r.end_slot));
+        }
+        let starts_before =
+            matches!(ranges.first().and_then(|r| r.start_datetime), Some(d) if d < from);
+        let ends_after = ranges.iter().filter_map(|r| r.end_datetime).any(|d| d > to);
+        coverage.covered = starts_before && ends_after && coverage.gaps.is_empty();
+        coverage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::to_timestampz;
+
+    fn range(start_slot: u64, end_slot: u64, times: Option<(u64, u64)>) -> BlockScanRange {
+        BlockScanRange {
+            cluster: "mainnet".to_string(),
+            start_slot,
+            end_slot,
+            start_datetime: times.map(|(start, _)| to_timestampz(start)),
+            end_datetime: times.map(|(_, end)| to_timestampz(end)),
+            num_blocks: 0,
+            num_transactions: 0,
+        }
+    }
+
+    #[test]
+    fn ranges_of_empty_blocks_close_gaps() {
+        let ranges = vec![
+            range(100, 119, Some((90, 98))),
+            range(120, 139, None),
+            range(140, 159, Some((106, 114))),
+        ];
+        let coverage =
+            BlockScanCoverage::from_ranges(to_timestampz(95), to_timestampz(110), &ranges);
+        assert!(coverage.covered);
+        assert_eq!(coverage.ranges, vec![(100, 159)]);
+        assert!(coverage.gaps.is_empty());
+    }
+
+    #[test]
+    fn missing_slots_are_gaps() {
+        let ranges = vec![
+            range(100, 119, Some((90, 98))),
+            range(140, 159, Some((106, 114))),
+        ];
+        let coverage =
+            BlockScanCoverage::from_ranges(to_timestampz(95), to_timestampz(110), &ranges);
+        assert!(!coverage.covered);
+        assert_eq!(coverage.gaps, vec![(120, 139)]);
+    }
+}
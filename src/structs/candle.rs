@@ -15,14 +15,21 @@ pub struct Candle {
     pub low: f64,
     pub volume: f64,
     pub complete: bool,
+    pub program_pk: String,
+    pub cluster: String,
 }
 
 impl Candle {
-    pub fn create_empty_candle(market_name: String, resolution: Resolution) -> Candle {
+    pub fn create_empty_candle(
+        market_name: String,
+        program_pk: String,
+        cluster: String,
+        resolution: Resolution,
+    ) -> Candle {
         Candle {
             market_name,
-            start_time: DateTime::from_utc(NaiveDateTime::MIN, Utc),
-            end_time: DateTime::from_utc(NaiveDateTime::MIN, Utc),
+            start_time: DateTime::from_naive_utc_and_offset(NaiveDateTime::MIN, Utc),
+            end_time: DateTime::from_naive_utc_and_offset(NaiveDateTime::MIN, Utc),
             resolution: resolution.to_string(),
             open: 0.0,
             close: 0.0,
@@ -30,6 +37,8 @@ impl Candle {
             low: 0.0,
             volume: 0.0,
             complete: false,
+            program_pk,
+            cluster,
         }
     }
 
@@ -45,6 +54,8 @@ impl Candle {
             low: row.get(7),
             volume: row.get(8),
             complete: row.get(9),
+            program_pk: row.get(10),
+            cluster: row.get(11),
         }
     }
 }
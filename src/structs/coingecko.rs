@@ -39,10 +39,7 @@ pub struct PgCoinGecko24HourVolume {
     pub quote_size: f64,
 }
 impl PgCoinGecko24HourVolume {
-    pub fn convert_to_readable(
-        &self,
-        markets: &Vec<OpenBookMarketMetadata>,
-    ) -> CoinGecko24HourVolume {
+    pub fn convert_to_readable(&self, markets: &[OpenBookMarketMetadata]) -> CoinGecko24HourVolume {
         let market = markets
             .iter()
             .find(|m| m.market_pk == self.address)
@@ -0,0 +1,108 @@
+use tokio_postgres::Row;
+
+/// Two fills of a market that are adjacent in the fills table but not in `seq_num`. The
+/// missing fills happened in a transaction between `from_slot` and `to_slot`, and between the
+/// transactions of the two fills when their signatures are known.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FillGap {
+    pub market_pk: String,
+    pub from_seq_num: u64,
+    pub to_seq_num: u64,
+    pub from_slot: u64,
+    pub to_slot: u64,
+    pub from_signature: Option<String>,
+    pub to_signature: Option<String>,
+}
+
+impl FillGap {
+    pub fn from_row(row: Row) -> Self {
+        let from_seq_num_raw = row.get::<usize, i64>(1);
+        let to_seq_num_raw = row.get::<usize, i64>(2);
+        let from_slot_raw = row.get::<usize, i64>(3);
+        let to_slot_raw = row.get::<usize, i64>(4);
+        FillGap {
+            market_pk: row.get(0),
+            from_seq_num: from_seq_num_raw as u64,
+            to_seq_num: to_seq_num_raw as u64,
+            from_slot: from_slot_raw as u64,
+            to_slot: to_slot_raw as u64,
+            from_signature: row.get(5),
+            to_signature: row.get(6),
+        }
+    }
+
+    /// Number of fills missing between the two fills.
+    pub fn missing_fills(&self) -> u64 {
+        self.to_seq_num - self.from_seq_num - 1
+    }
+}
+
+/// The range of `seq_num` covered by the fills of a market, and how many fills are stored in it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FillSequence {
+    pub market_pk: String,
+    pub fill_count: i64,
+    pub min_seq_num: i64,
+    pub max_seq_num: i64,
+}
+
+impl FillSequence {
+    pub fn from_row(row: Row) -> Self {
+        FillSequence {
+            market_pk: row.get(0),
+            fill_count: row.get(1),
+            min_seq_num: row.get(2),
+            max_seq_num: row.get(3),
+        }
+    }
+
+    /// Share of the fills in the range that are stored, 1 when none are missing.
+    pub fn completeness(&self) -> f64 {
+        let expected = self.max_seq_num - self.min_seq_num + 1;
+        if expected <= 0 {
+            return 1.0;
+        }
+        self.fill_count as f64 / expected as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sequence(fill_count: i64, min_seq_num: i64, max_seq_num: i64) -> FillSequence {
+        FillSequence {
+            market_pk: "market".to_string(),
+            fill_count,
+            min_seq_num,
+            max_seq_num,
+        }
+    }
+
+    #[test]
+    fn missing_fills_excludes_the_fills_around_the_gap() {
+        let gap = FillGap {
+            market_pk: "market".to_string(),
+            from_seq_num: 10,
+            to_seq_num: 14,
+            from_slot: 100,
+            to_slot: 101,
+            from_signature: None,
+            to_signature: None,
+        };
+        assert_eq!(gap.missing_fills(), 3);
+    }
+
+    #[test]
+    fn completeness_is_the_share_of_stored_fills() {
+        assert_eq!(sequence(10, 100, 109).completeness(), 1.0);
+        assert_eq!(sequence(1, 5, 5).completeness(), 1.0);
+        assert_eq!(sequence(3, 0, 3).completeness(), 0.75);
+        assert_eq!(sequence(2, 1, 100).completeness(), 0.02);
+    }
+
+    #[test]
+    fn completeness_of_an_empty_range_is_full() {
+        assert_eq!(sequence(0, 10, 9).completeness(), 1.0);
+    }
+}
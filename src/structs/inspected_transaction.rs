@@ -0,0 +1,28 @@
+use serde::Serialize;
+
+/// What the parser makes of a single transaction, for the admin `inspect` command.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct InspectedTransaction {
+    pub signature: String,
+    pub slot: u64,
+    pub block_datetime: Option<String>,
+    pub log_truncated: bool,
+    /// Why ingestion would fail on the transaction, if it would.
+    pub error: Option<String>,
+    pub events: Vec<InspectedEvent>,
+    /// Markets with fills in the transaction that are not in `market_metadata`.
+    pub unknown_markets: Vec<String>,
+    /// `Program data:` lines that are not a known event, with their index among the program
+    /// data lines when they are valid base64.
+    pub undecoded_program_data: Vec<(Option<u32>, String)>,
+}
+
+/// A decoded event as field and value pairs. Amounts are scaled to UI units where the market
+/// of the event is known, and left native otherwise.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct InspectedEvent {
+    pub log_index: Option<u32>,
+    pub kind: &'static str,
+    pub market_pk: Option<String>,
+    pub fields: Vec<(&'static str, String)>,
+}
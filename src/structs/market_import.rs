@@ -0,0 +1,27 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio_postgres::Row;
+
+/// A market queued for import because fills of it were found before its metadata. Imports that
+/// failed too many times are no longer claimed, they are retried or dropped with the admin tool.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct MarketImport {
+    pub market_pk: String,
+    pub queued_datetime: String,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub stuck: bool,
+}
+
+impl MarketImport {
+    pub fn from_row(row: Row) -> Self {
+        let queued_datetime = row.get::<usize, DateTime<Utc>>(1);
+        MarketImport {
+            market_pk: row.get(0),
+            queued_datetime: queued_datetime.to_rfc3339(),
+            attempts: row.get(2),
+            last_error: row.get(3),
+            stuck: row.get(4),
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio_postgres::Row;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct MarketSummary {
+    pub market_pk: String,
+    pub market_name: String,
+    pub scraper_active: bool,
+    pub fill_count: i64,
+    pub candle_count: i64,
+}
+impl MarketSummary {
+    pub fn from_row(row: Row) -> Self {
+        MarketSummary {
+            market_pk: row.get(0),
+            market_name: row.get(1),
+            scraper_active: row.get(2),
+            fill_count: row.get(3),
+            candle_count: row.get(4),
+        }
+    }
+}
+
+/// How far behind the chain the fills and the 1 minute candles of a market are.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct MarketLag {
+    pub market_pk: String,
+    pub market_name: String,
+    pub scraper_active: bool,
+    pub latest_fill: Option<String>,
+    pub fill_lag_seconds: Option<i64>,
+    pub latest_candle: Option<String>,
+    pub candle_lag_seconds: Option<i64>,
+}
+impl MarketLag {
+    pub fn from_row(row: Row) -> Self {
+        let latest_fill = row.get::<usize, Option<DateTime<Utc>>>(3);
+        let latest_candle = row.get::<usize, Option<DateTime<Utc>>>(4);
+        let now = Utc::now();
+        MarketLag {
+            market_pk: row.get(0),
+            market_name: row.get(1),
+            scraper_active: row.get(2),
+            latest_fill: latest_fill.map(|d| d.to_rfc3339()),
+            fill_lag_seconds: latest_fill.map(|d| (now - d).num_seconds()),
+            latest_candle: latest_candle.map(|d| d.to_rfc3339()),
+            candle_lag_seconds: latest_candle.map(|d| (now - d).num_seconds()),
+        }
+    }
+}
@@ -1,7 +1,16 @@
+pub mod archived_transaction;
+pub mod backfill;
+pub mod block_scan;
 pub mod candle;
 pub mod coingecko;
+pub mod fill_gap;
+pub mod inspected_transaction;
+pub mod market_import;
+pub mod market_summary;
 pub mod openbook_v2;
+pub mod parse_error;
 pub mod resolution;
+pub mod scraper_state;
 pub mod trader;
 pub mod tradingview;
 pub mod transaction;
@@ -1,4 +1,7 @@
-use crate::utils::{to_timestampz, OPENBOOK_KEY};
+use crate::{
+    structs::parse_error::ParseError,
+    utils::{to_timestampz, PROGRAM_CONFIG},
+};
 use anchor_lang::prelude::*;
 use chrono::{DateTime, Utc};
 use num_traits::Pow;
@@ -21,6 +24,7 @@ pub struct OpenBookMarketMetadata {
     pub base_lot_size: i64,
     pub quote_lot_size: i64,
     pub scraper_active: bool,
+    pub cluster: String,
 }
 
 impl serde::Serialize for OpenBookMarketMetadata {
@@ -40,16 +44,32 @@ impl serde::Serialize for OpenBookMarketMetadata {
         state.serialize_field("base_lot_size", &self.base_lot_size)?;
         state.serialize_field("quote_lot_size", &self.quote_lot_size)?;
         state.serialize_field("scraper_active", &self.scraper_active)?;
+        state.serialize_field("cluster", &self.cluster)?;
         state.end()
     }
 }
 impl OpenBookMarketMetadata {
-    pub fn from_event(log: MarketMetaDataLog, block_datetime: DateTime<Utc>) -> Self {
+    /// Names the market after the on-chain name, or after its pubkey when the name is empty.
+    /// Names that are already taken get a suffix when the market is inserted.
+    pub fn from_event(
+        log: MarketMetaDataLog,
+        program_pk: &str,
+        block_datetime: DateTime<Utc>,
+    ) -> Self {
+        let market_pk = log.market.to_string();
+        let name = log
+            .name
+            .trim_matches(|c: char| c == '\0' || c.is_whitespace());
+        let market_name = if name.is_empty() {
+            market_pk.clone()
+        } else {
+            name.to_string()
+        };
         OpenBookMarketMetadata {
             creation_datetime: block_datetime,
-            program_pk: OPENBOOK_KEY.to_string(),
-            market_pk: log.market.to_string(),
-            market_name: log.market.to_string(),
+            program_pk: program_pk.to_string(),
+            market_pk,
+            market_name,
             base_mint: log.base_mint.to_string(),
             quote_mint: log.quote_mint.to_string(),
             base_decimals: log.base_decimals,
@@ -57,6 +77,7 @@ impl OpenBookMarketMetadata {
             base_lot_size: log.base_lot_size,
             quote_lot_size: log.quote_lot_size,
             scraper_active: false,
+            cluster: PROGRAM_CONFIG.cluster.clone(),
         }
     }
 
@@ -75,11 +96,13 @@ impl OpenBookMarketMetadata {
             base_lot_size: row.get(8),
             quote_lot_size: row.get(9),
             scraper_active: row.get(10),
+            cluster: row.get(11),
         }
     }
 }
 
 #[event]
+#[derive(Debug)]
 pub struct MarketMetaDataLog {
     pub market: Pubkey,
     pub name: String,
@@ -115,22 +138,43 @@ pub struct OpenBookFill {
 
     pub price: f64,
     pub quantity: f64, // number of base lots
+
+    pub program_pk: String,
+    pub cluster: String,
+
+    // Transaction the fill was emitted in, and the index of the event within it
+    pub signature: String,
+    pub log_index: u32,
 }
 
 impl OpenBookFill {
+    /// Fails on a fill whose price is 0 or whose fees overflow, which a valid fill can't have.
     pub fn from_log(
         log: FillLog,
         market: &OpenBookMarketMetadata,
-        slot: u64,
-        block_datetime: DateTime<Utc>,
-    ) -> Self {
-        let match_quote = log.quantity / log.price * market.quote_lot_size;
-        let maker_fees_quote_lots = match_quote * (log.maker_fee) / (FEES_SCALE_FACTOR as i64);
-        let taker_fees_quote_lots = match_quote * (log.taker_fee) / (FEES_SCALE_FACTOR as i64);
+        ctx: &EventContext,
+    ) -> std::result::Result<Self, ParseError> {
+        let malformed = || ParseError::MalformedEvent {
+            event: "FillLog",
+            log_index: ctx.log_index,
+        };
+        let match_quote = log
+            .quantity
+            .checked_div(log.price)
+            .and_then(|q| q.checked_mul(market.quote_lot_size))
+            .ok_or_else(malformed)?;
+        let fees_quote_lots = |fee: i64| {
+            match_quote
+                .checked_mul(fee)
+                .map(|f| f / (FEES_SCALE_FACTOR as i64))
+                .ok_or_else(malformed)
+        };
+        let maker_fees_quote_lots = fees_quote_lots(log.maker_fee)?;
+        let taker_fees_quote_lots = fees_quote_lots(log.taker_fee)?;
 
-        OpenBookFill {
-            block_datetime,
-            slot,
+        Ok(OpenBookFill {
+            block_datetime: ctx.block_datetime,
+            slot: ctx.slot,
             market_pk: log.market.to_string(),
             seq_num: log.seq_num,
             maker: log.maker.to_string(),
@@ -145,16 +189,24 @@ impl OpenBookFill {
             maker_out: log.maker_out,
             price: ui_price(log.price, market),
             quantity: ui_base_quantity(log.quantity, market),
-        }
+            program_pk: market.program_pk.clone(),
+            cluster: market.cluster.clone(),
+            signature: ctx.signature.clone(),
+            log_index: ctx.log_index,
+        })
     }
 
+    /// Fills stored before signatures were recorded have an empty signature. Client order ids
+    /// that don't parse are read as 0 rather than failing the whole query.
     pub fn from_row(row: Row) -> Self {
-        let slot_raw = row.get::<usize, i32>(1);
-        let seq_num_raw: i32 = row.get(3);
+        let slot_raw = row.get::<usize, i64>(1);
+        let seq_num_raw: i64 = row.get(3);
         let maker_client_order_id_raw: String = row.get(5);
         let taker_client_order_id_raw: String = row.get(9);
         let taker_side_raw = row.get::<usize, i32>(11);
         let maker_slot_raw = row.get::<usize, i32>(12);
+        let signature_raw = row.get::<usize, Option<String>>(18);
+        let log_index_raw = row.get::<usize, Option<i32>>(19);
 
         OpenBookFill {
             block_datetime: row.get(0),
@@ -162,17 +214,21 @@ impl OpenBookFill {
             market_pk: row.get(2),
             seq_num: seq_num_raw as u64,
             maker: row.get(4),
-            maker_client_order_id: maker_client_order_id_raw.parse::<u64>().unwrap(),
+            maker_client_order_id: maker_client_order_id_raw.parse::<u64>().unwrap_or_default(),
             maker_fee: row.get(6),
             maker_datetime: row.get(7),
             taker: row.get(8),
-            taker_client_order_id: taker_client_order_id_raw.parse::<u64>().unwrap(),
+            taker_client_order_id: taker_client_order_id_raw.parse::<u64>().unwrap_or_default(),
             taker_fee: row.get(10),
             taker_side: taker_side_raw as u8,
             maker_slot: maker_slot_raw as u8,
             maker_out: row.get(13),
             price: row.get(14),
             quantity: row.get(15),
+            program_pk: row.get(16),
+            cluster: row.get(17),
+            signature: signature_raw.unwrap_or_default(),
+            log_index: log_index_raw.unwrap_or_default() as u32,
         }
     }
 }
@@ -202,6 +258,108 @@ pub struct FillLog {
     pub quantity: i64, // number of base lots
 }
 
+/// Where a decoded event came from, used as the key of the event tables.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EventContext {
+    pub signature: String,
+    pub log_index: u32,
+    pub slot: u64,
+    pub block_datetime: DateTime<Utc>,
+}
+
+/// Decoded OpenBook events other than fills and market creation, grouped by type.
+#[derive(Default, Debug)]
+pub struct OpenBookEvents {
+    pub deposits: Vec<(EventContext, DepositLog)>,
+    pub settle_funds: Vec<(EventContext, SettleFundsLog)>,
+    pub sweep_fees: Vec<(EventContext, SweepFeesLog)>,
+    pub open_orders_positions: Vec<(EventContext, OpenOrdersPositionLog)>,
+    pub set_delegates: Vec<(EventContext, SetDelegateLog)>,
+    pub total_order_fills: Vec<(EventContext, TotalOrderFillEvent)>,
+}
+
+impl OpenBookEvents {
+    pub fn is_empty(&self) -> bool {
+        self.deposits.is_empty()
+            && self.settle_funds.is_empty()
+            && self.sweep_fees.is_empty()
+            && self.open_orders_positions.is_empty()
+            && self.set_delegates.is_empty()
+            && self.total_order_fills.is_empty()
+    }
+
+    pub fn append(&mut self, other: &mut OpenBookEvents) {
+        self.deposits.append(&mut other.deposits);
+        self.settle_funds.append(&mut other.settle_funds);
+        self.sweep_fees.append(&mut other.sweep_fees);
+        self.open_orders_positions
+            .append(&mut other.open_orders_positions);
+        self.set_delegates.append(&mut other.set_delegates);
+        self.total_order_fills.append(&mut other.total_order_fills);
+    }
+}
+
+#[event]
+#[derive(Debug)]
+pub struct DepositLog {
+    pub open_orders_account: Pubkey,
+    pub signer: Pubkey,
+    pub base_amount: u64,
+    pub quote_amount: u64,
+}
+
+#[event]
+#[derive(Debug)]
+pub struct SettleFundsLog {
+    pub open_orders_account: Pubkey,
+    pub base_native: u64,
+    pub quote_native: u64,
+    pub referrer_rebate: u64,
+    pub referrer: Option<Pubkey>,
+}
+
+#[event]
+#[derive(Debug)]
+pub struct SweepFeesLog {
+    pub market: Pubkey,
+    pub amount: u64,
+    pub receiver: Pubkey,
+}
+
+#[event]
+#[derive(Debug)]
+pub struct OpenOrdersPositionLog {
+    pub owner: Pubkey,
+    pub open_orders_account_num: u32,
+    pub market: Pubkey,
+    pub bids_base_lots: i64,
+    pub bids_quote_lots: i64,
+    pub asks_base_lots: i64,
+    pub base_free_native: u64,
+    pub quote_free_native: u64,
+    pub locked_maker_fees: u64,
+    pub referrer_rebates_available: u64,
+    pub maker_volume: u128, // quote native
+    pub taker_volume: u128, // quote native
+}
+
+#[event]
+#[derive(Debug)]
+pub struct SetDelegateLog {
+    pub open_orders_account: Pubkey,
+    pub delegate: Option<Pubkey>,
+}
+
+#[event]
+#[derive(Debug)]
+pub struct TotalOrderFillEvent {
+    pub side: u8,
+    pub taker: Pubkey,
+    pub total_quantity_paid: u64,
+    pub total_quantity_received: u64,
+    pub fees: u64,
+}
+
 pub fn ui_price(price: i64, market: &OpenBookMarketMetadata) -> f64 {
     let price_lots = price as f64;
     let base_multiplier = token_factor(market.base_decimals);
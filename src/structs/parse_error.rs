@@ -0,0 +1,51 @@
+use std::fmt;
+
+/// Why a transaction could not be ingested. Stored as the `last_error` of the transaction, and
+/// used to tell failures that may succeed on a later attempt from ones that never will.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParseError {
+    /// The transaction could not be fetched.
+    Rpc(String),
+    /// Neither the transaction nor `getBlockTime` gave a time for its slot.
+    MissingBlockTime { slot: u64 },
+    /// The transaction was returned without its status meta, so it has no logs.
+    MissingMeta,
+    /// A payload with a known event discriminator could not be deserialized.
+    MalformedEvent { event: &'static str, log_index: u32 },
+    /// The transaction has fills of markets that are not imported yet.
+    UnknownMarkets(Vec<String>),
+    /// The stored signature is not a valid signature, so the transaction can't be fetched.
+    InvalidSignature(String),
+}
+
+impl ParseError {
+    /// Malformed events decode the same way every time, and an invalid signature never parses,
+    /// so retrying them is pointless.
+    pub fn is_retryable(&self) -> bool {
+        !matches!(
+            self,
+            ParseError::MalformedEvent { .. } | ParseError::InvalidSignature(_)
+        )
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Rpc(e) => write!(f, "rpc error: {}", e),
+            ParseError::MissingBlockTime { slot } => {
+                write!(f, "no block time for slot {}", slot)
+            }
+            ParseError::MissingMeta => write!(f, "transaction has no status meta"),
+            ParseError::MalformedEvent { event, log_index } => {
+                write!(f, "malformed {} at event index {}", event, log_index)
+            }
+            ParseError::UnknownMarkets(markets) => {
+                write!(f, "fills of unknown markets {}", markets.join(", "))
+            }
+            ParseError::InvalidSignature(e) => write!(f, "invalid signature: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
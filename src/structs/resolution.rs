@@ -1,5 +1,5 @@
 use chrono::Duration;
-use std::fmt;
+use std::{fmt, str::FromStr};
 use strum::EnumIter;
 
 #[derive(EnumIter, Copy, Clone, Eq, PartialEq)]
@@ -63,8 +63,12 @@ impl Resolution {
             Resolution::R1d => day(),
         }
     }
+}
+
+impl FromStr for Resolution {
+    type Err = ();
 
-    pub fn from_str(v: &str) -> Result<Self, ()> {
+    fn from_str(v: &str) -> Result<Self, ()> {
         match v {
             "1M" => Ok(Resolution::R1m),
             "3M" => Ok(Resolution::R3m),
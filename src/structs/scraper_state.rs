@@ -0,0 +1,21 @@
+use tokio_postgres::Row;
+
+/// The newest signature that has been written to the transactions table for an address.
+/// Live signature polling pages back to this checkpoint with `until`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScraperState {
+    pub address: String,
+    pub last_signature: String,
+    pub last_slot: u64,
+}
+
+impl ScraperState {
+    pub fn from_row(row: Row) -> Self {
+        let last_slot_raw = row.get::<usize, i64>(2);
+        ScraperState {
+            address: row.get(0),
+            last_signature: row.get(1),
+            last_slot: last_slot_raw as u64,
+        }
+    }
+}
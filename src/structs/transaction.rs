@@ -1,8 +1,13 @@
 use chrono::{DateTime, Utc};
+use serde::Serialize;
 use solana_client::rpc_response::RpcConfirmedTransactionStatusWithSignature;
+use solana_sdk::pubkey::Pubkey;
+use solana_transaction_status::EncodedConfirmedTransactionWithStatusMeta;
 use tokio_postgres::Row;
 
-use crate::utils::{to_timestampz, OPENBOOK_KEY};
+use crate::utils::{to_timestampz, PROGRAM_CONFIG};
+
+use super::parse_error::ParseError;
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct PgTransaction {
@@ -12,24 +17,59 @@ pub struct PgTransaction {
     pub slot: u64,
     pub err: bool,
     pub processed: bool,
-    pub worker_partition: i32,
+    pub cluster: String,
 }
 
-pub const NUM_TRANSACTION_PARTITIONS: u64 = 10;
+/// Failed fetches of a transaction before it is dead lettered, unless `MAX_TRANSACTION_ATTEMPTS`
+/// is set.
+pub const DEFAULT_MAX_TRANSACTION_ATTEMPTS: i32 = 10;
 
 impl PgTransaction {
+    /// Fails if the signature has no block time, see `fill_missing_signature_block_times`.
     pub fn from_rpc_confirmed_transaction(
         rpc_confirmed_transaction: RpcConfirmedTransactionStatusWithSignature,
-    ) -> Self {
-        PgTransaction {
+        program_pk: &Pubkey,
+    ) -> Result<Self, ParseError> {
+        let block_time =
+            rpc_confirmed_transaction
+                .block_time
+                .ok_or(ParseError::MissingBlockTime {
+                    slot: rpc_confirmed_transaction.slot,
+                })?;
+        Ok(PgTransaction {
             signature: rpc_confirmed_transaction.signature,
-            program_pk: OPENBOOK_KEY.to_string(),
-            block_datetime: to_timestampz(rpc_confirmed_transaction.block_time.unwrap() as u64),
+            program_pk: program_pk.to_string(),
+            block_datetime: to_timestampz(block_time as u64),
             slot: rpc_confirmed_transaction.slot,
             err: rpc_confirmed_transaction.err.is_some(),
             processed: false,
-            worker_partition: (rpc_confirmed_transaction.slot % NUM_TRANSACTION_PARTITIONS) as i32,
-        }
+            cluster: PROGRAM_CONFIG.cluster.clone(),
+        })
+    }
+
+    /// A transaction read from its block rather than listed by signature. Fails if it has no
+    /// block time, see `fill_missing_block_times`.
+    pub fn from_block_transaction(
+        signature: &str,
+        program_pk: &str,
+        txn: &EncodedConfirmedTransactionWithStatusMeta,
+    ) -> Result<Self, ParseError> {
+        let block_time = txn
+            .block_time
+            .ok_or(ParseError::MissingBlockTime { slot: txn.slot })?;
+        Ok(PgTransaction {
+            signature: signature.to_string(),
+            program_pk: program_pk.to_string(),
+            block_datetime: to_timestampz(block_time as u64),
+            slot: txn.slot,
+            err: txn
+                .transaction
+                .meta
+                .as_ref()
+                .is_some_and(|m| m.err.is_some()),
+            processed: false,
+            cluster: PROGRAM_CONFIG.cluster.clone(),
+        })
     }
 
     pub fn from_row(row: Row) -> Self {
@@ -41,7 +81,33 @@ impl PgTransaction {
             slot: slot_raw as u64,
             err: row.get(4),
             processed: row.get(5),
-            worker_partition: row.get(6),
+            cluster: row.get(6),
+        }
+    }
+}
+
+/// A transaction that could not be fetched or parsed after too many attempts.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DeadLetterTransaction {
+    pub signature: String,
+    pub program_pk: String,
+    pub block_datetime: String,
+    pub slot: u64,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+}
+
+impl DeadLetterTransaction {
+    pub fn from_row(row: Row) -> Self {
+        let block_datetime = row.get::<usize, DateTime<Utc>>(2);
+        let slot_raw = row.get::<usize, i64>(3);
+        DeadLetterTransaction {
+            signature: row.get(0),
+            program_pk: row.get(1),
+            block_datetime: block_datetime.to_rfc3339(),
+            slot: slot_raw as u64,
+            attempts: row.get(4),
+            last_error: row.get(5),
         }
     }
 }
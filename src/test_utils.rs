@@ -0,0 +1,213 @@
+//! Fixtures shared by the unit tests: markets, fill events, and recorded transactions that can be
+//! served by a `ReplayTransactionSource` in place of an RPC.
+//!
+//! Tests that need a database are ignored by default. They run with `cargo test --
+//! --include-ignored` when `TEST_DATABASE_URL` points at a server the tests can create
+//! databases on.
+
+use anchor_lang::{__private::base64, event::EVENT_IX_TAG_LE, Event};
+use deadpool_postgres::{Manager, Pool};
+use serde_json::json;
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use solana_transaction_status::{
+    option_serializer::OptionSerializer, EncodedConfirmedTransactionWithStatusMeta,
+};
+use tokio_postgres::NoTls;
+
+use crate::{
+    database::initialize::setup_database,
+    scraper::source::ReplayTransactionSource,
+    structs::openbook_v2::{EventContext, FillLog, OpenBookFill, OpenBookMarketMetadata},
+    utils::{to_timestampz, PROGRAM_CONFIG},
+};
+
+/// An active market of the first configured program, with a fresh market key.
+pub fn market() -> OpenBookMarketMetadata {
+    OpenBookMarketMetadata {
+        creation_datetime: to_timestampz(0),
+        program_pk: PROGRAM_CONFIG.program_ids[0].to_string(),
+        market_pk: Pubkey::new_unique().to_string(),
+        market_name: "SOL-USDC".to_string(),
+        base_mint: Pubkey::new_unique().to_string(),
+        quote_mint: Pubkey::new_unique().to_string(),
+        base_decimals: 9,
+        quote_decimals: 6,
+        base_lot_size: 1_000_000,
+        quote_lot_size: 1,
+        scraper_active: true,
+        cluster: PROGRAM_CONFIG.cluster.clone(),
+    }
+}
+
+pub fn fill_log(
+    market: &OpenBookMarketMetadata,
+    seq_num: u64,
+    price: i64,
+    quantity: i64,
+) -> FillLog {
+    FillLog {
+        market: market.market_pk.parse().unwrap(),
+        taker_side: 0,
+        maker_slot: 0,
+        maker_out: false,
+        timestamp: 0,
+        seq_num,
+        maker: Pubkey::new_unique(),
+        maker_client_order_id: 0,
+        maker_fee: -100,
+        maker_timestamp: 0,
+        taker: Pubkey::new_unique(),
+        taker_client_order_id: 0,
+        taker_fee: 400,
+        price,
+        quantity,
+    }
+}
+
+/// A fill of the market as stored from a fresh transaction in the given slot.
+pub fn openbook_fill(
+    market: &OpenBookMarketMetadata,
+    seq_num: u64,
+    slot: u64,
+    block_time: u64,
+) -> OpenBookFill {
+    let ctx = EventContext {
+        signature: Signature::new_unique().to_string(),
+        log_index: 0,
+        slot,
+        block_datetime: to_timestampz(block_time),
+    };
+    OpenBookFill::from_log(fill_log(market, seq_num, 20_000, 10), market, &ctx).unwrap()
+}
+
+/// The logs of one call of the first configured program emitting the events.
+pub fn program_logs<E: Event>(events: &[E]) -> Vec<String> {
+    let program_id = PROGRAM_CONFIG.program_ids[0];
+    let mut logs = vec![format!("Program {} invoke [1]", program_id)];
+    for event in events.iter() {
+        logs.push(format!("Program data: {}", base64::encode(event.data())));
+    }
+    logs.push(format!("Program {} success", program_id));
+    logs
+}
+
+/// A transaction as returned by `getTransaction`, calling the first configured program with the
+/// given accounts and logs. Its signature is fresh.
+pub fn recorded_transaction(
+    slot: u64,
+    block_time: Option<i64>,
+    account_keys: &[String],
+    logs: Vec<String>,
+) -> EncodedConfirmedTransactionWithStatusMeta {
+    let mut keys = vec![
+        Pubkey::new_unique().to_string(),
+        PROGRAM_CONFIG.program_ids[0].to_string(),
+    ];
+    keys.extend(account_keys.iter().cloned());
+    serde_json::from_value(json!({
+        "slot": slot,
+        "blockTime": block_time,
+        "transaction": {
+            "signatures": [Signature::new_unique().to_string()],
+            "message": {
+                "header": {
+                    "numRequiredSignatures": 1,
+                    "numReadonlySignedAccounts": 0,
+                    "numReadonlyUnsignedAccounts": 1
+                },
+                "accountKeys": keys,
+                "recentBlockhash": "11111111111111111111111111111111",
+                "instructions": []
+            }
+        },
+        "meta": {
+            "err": null,
+            "status": { "Ok": null },
+            "fee": 5000,
+            "preBalances": [],
+            "postBalances": [],
+            "logMessages": logs
+        }
+    }))
+    .unwrap()
+}
+
+/// A transaction of a market that emitted fills.
+pub fn fill_transaction(
+    market: &OpenBookMarketMetadata,
+    slot: u64,
+    block_time: Option<i64>,
+    fills: &[FillLog],
+) -> EncodedConfirmedTransactionWithStatusMeta {
+    recorded_transaction(
+        slot,
+        block_time,
+        std::slice::from_ref(&market.market_pk),
+        program_logs(fills),
+    )
+}
+
+/// Adds the events to a transaction as `emit_cpi!` self-invocations of the first configured
+/// program, which is the second account of `recorded_transaction`.
+pub fn with_cpi_events<E: Event>(
+    mut txn: EncodedConfirmedTransactionWithStatusMeta,
+    events: &[E],
+) -> EncodedConfirmedTransactionWithStatusMeta {
+    let instructions: Vec<_> = events
+        .iter()
+        .map(|e| {
+            let data = [EVENT_IX_TAG_LE.as_slice(), e.data().as_slice()].concat();
+            json!({
+                "programIdIndex": 1,
+                "accounts": [],
+                "data": bs58::encode(data).into_string()
+            })
+        })
+        .collect();
+    let inner_instructions = serde_json::from_value(json!([{
+        "index": 0,
+        "instructions": instructions
+    }]))
+    .unwrap();
+    txn.transaction.meta.as_mut().unwrap().inner_instructions =
+        OptionSerializer::Some(inner_instructions);
+    txn
+}
+
+/// A source that only knows the block times of the given slots, each from an empty transaction.
+pub fn block_times(slots: &[(u64, i64)]) -> ReplayTransactionSource {
+    let recorded = slots
+        .iter()
+        .map(|(slot, block_time)| recorded_transaction(*slot, Some(*block_time), &[], vec![]))
+        .collect();
+    ReplayTransactionSource::from_transactions(recorded).unwrap()
+}
+
+/// Recreates a database named after the test and sets it up like the services do.
+pub async fn test_pool(name: &str) -> Pool {
+    let pool = empty_test_pool(name).await;
+    setup_database(&pool).await.unwrap();
+    pool
+}
+
+/// Recreates an empty database named after the test.
+pub async fn empty_test_pool(name: &str) -> Pool {
+    let url = dotenv::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL is not set");
+    let (client, connection) = tokio_postgres::connect(&url, NoTls).await.unwrap();
+    tokio::spawn(connection);
+    client
+        .batch_execute(&format!("DROP DATABASE IF EXISTS {} WITH (FORCE)", name))
+        .await
+        .unwrap();
+    client
+        .batch_execute(&format!("CREATE DATABASE {}", name))
+        .await
+        .unwrap();
+
+    let mut config: tokio_postgres::Config = url.parse().unwrap();
+    config.dbname(name);
+    Pool::builder(Manager::new(config, NoTls))
+        .max_size(4)
+        .build()
+        .unwrap()
+}
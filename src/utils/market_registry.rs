@@ -0,0 +1,143 @@
+use deadpool_postgres::Pool;
+use log::{info, warn};
+use std::{collections::HashMap, sync::Arc, time::Duration as WaitDuration};
+use tokio::sync::watch;
+
+use crate::{
+    database::fetch::{fetch_active_markets, fetch_all_markets},
+    structs::openbook_v2::OpenBookMarketMetadata,
+};
+
+const DEFAULT_MARKET_REFRESH_INTERVAL: WaitDuration = WaitDuration::from_secs(60);
+
+/// Markets keyed by market pubkey.
+pub type ActiveMarkets = Arc<HashMap<String, OpenBookMarketMetadata>>;
+
+/// The active markets, shared by every task of a binary and refreshed from the
+/// `market_metadata` table while they run. Readers take a snapshot per unit of work, and
+/// long running tasks can subscribe to be woken up when the set of markets changes.
+/// A registry can be limited to the markets of one cluster, and can hold every known market
+/// instead of the active ones.
+#[derive(Clone)]
+pub struct MarketRegistry {
+    sender: Arc<watch::Sender<ActiveMarkets>>,
+    cluster: Option<String>,
+    all_markets: bool,
+}
+
+impl MarketRegistry {
+    pub fn new(markets: Vec<OpenBookMarketMetadata>, cluster: Option<String>) -> Self {
+        let (sender, _) = watch::channel(Arc::new(by_market_pk(markets, &cluster)));
+        MarketRegistry {
+            sender: Arc::new(sender),
+            cluster,
+            all_markets: false,
+        }
+    }
+
+    pub async fn load(pool: &Pool, cluster: Option<String>) -> anyhow::Result<Self> {
+        let markets = fetch_active_markets(pool).await?;
+        Ok(Self::new(markets, cluster))
+    }
+
+    /// Loads every market in `market_metadata`, active or not.
+    pub async fn load_all(pool: &Pool, cluster: Option<String>) -> anyhow::Result<Self> {
+        let markets = fetch_all_markets(pool).await?;
+        Ok(MarketRegistry {
+            all_markets: true,
+            ..Self::new(markets, cluster)
+        })
+    }
+
+    /// The markets whose fills are ingested: every market when `SCRAPE_ALL_MARKETS` is true,
+    /// the active markets otherwise. `scraper_active` then only controls candle batching and
+    /// the API.
+    pub async fn load_for_ingestion(pool: &Pool, cluster: Option<String>) -> anyhow::Result<Self> {
+        if scrape_all_markets()? {
+            Self::load_all(pool, cluster).await
+        } else {
+            Self::load(pool, cluster).await
+        }
+    }
+
+    /// Whether the registry holds every known market rather than the active ones.
+    pub fn all_markets(&self) -> bool {
+        self.all_markets
+    }
+
+    pub fn snapshot(&self) -> ActiveMarkets {
+        self.sender.borrow().clone()
+    }
+
+    /// The markets ordered by name.
+    pub fn list(&self) -> Vec<OpenBookMarketMetadata> {
+        let mut markets: Vec<OpenBookMarketMetadata> = self.snapshot().values().cloned().collect();
+        markets.sort_by(|a, b| a.market_name.cmp(&b.market_name));
+        markets
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<ActiveMarkets> {
+        self.sender.subscribe()
+    }
+
+    /// Reloads the markets, notifying subscribers only if they changed.
+    pub async fn refresh(&self, pool: &Pool) -> anyhow::Result<()> {
+        let markets = if self.all_markets {
+            fetch_all_markets(pool).await?
+        } else {
+            fetch_active_markets(pool).await?
+        };
+        let markets = by_market_pk(markets, &self.cluster);
+        self.sender.send_if_modified(|current| {
+            if **current == markets {
+                return false;
+            }
+            for (market_pk, market) in markets.iter() {
+                if current.get(market_pk) != Some(market) {
+                    info!("Market {} is tracked", market.market_name);
+                }
+            }
+            for (market_pk, market) in current.iter() {
+                if !markets.contains_key(market_pk) {
+                    info!("Market {} is no longer tracked", market.market_name);
+                }
+            }
+            *current = Arc::new(markets);
+            true
+        });
+        Ok(())
+    }
+
+    /// Refreshes the markets every `MARKET_REFRESH_INTERVAL_SECS` seconds, 60 by default.
+    pub async fn refresh_periodically(&self, pool: &Pool) -> anyhow::Result<()> {
+        let interval = match dotenv::var("MARKET_REFRESH_INTERVAL_SECS") {
+            Ok(s) => WaitDuration::from_secs(s.parse::<u64>()?),
+            Err(_) => DEFAULT_MARKET_REFRESH_INTERVAL,
+        };
+        loop {
+            tokio::time::sleep(interval).await;
+            if let Err(e) = self.refresh(pool).await {
+                warn!("failed to refresh markets: {:?}", e);
+            }
+        }
+    }
+}
+
+/// Reads `SCRAPE_ALL_MARKETS`, false by default.
+pub fn scrape_all_markets() -> anyhow::Result<bool> {
+    match dotenv::var("SCRAPE_ALL_MARKETS") {
+        Ok(s) => Ok(s.parse::<bool>()?),
+        Err(_) => Ok(false),
+    }
+}
+
+fn by_market_pk(
+    markets: Vec<OpenBookMarketMetadata>,
+    cluster: &Option<String>,
+) -> HashMap<String, OpenBookMarketMetadata> {
+    markets
+        .into_iter()
+        .filter(|m| cluster.as_ref().is_none_or(|c| *c == m.cluster))
+        .map(|m| (m.market_pk.clone(), m))
+        .collect()
+}
@@ -1,12 +1,59 @@
+pub mod market_registry;
+
 use anchor_lang::prelude::Pubkey;
 use chrono::{NaiveDateTime, Utc};
 use deadpool_postgres::Pool;
+use lazy_static::lazy_static;
 use serde_derive::Deserialize;
 use solana_sdk::pubkey;
 
+use self::market_registry::MarketRegistry;
 use crate::structs::openbook_v2::OpenBookMarketMetadata;
 
 pub const OPENBOOK_KEY: Pubkey = pubkey!("opnbkNkqux64GppQhwbyEVc3axhssFhVYuwar8rDHCu");
+pub const DEFAULT_CLUSTER: &str = "mainnet";
+
+/// The OpenBook programs indexed by this deployment and the cluster they run on. Every
+/// transaction, fill, market and candle is stamped with the cluster and its program, so one
+/// database can hold several deployments side by side.
+#[derive(Clone, Debug)]
+pub struct ProgramConfig {
+    pub cluster: String,
+    pub program_ids: Vec<Pubkey>,
+}
+
+impl ProgramConfig {
+    /// Reads `CLUSTER` and the comma separated `PROGRAM_IDS`, defaulting to the mainnet
+    /// OpenBook program.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let cluster = dotenv::var("CLUSTER").unwrap_or_else(|_| DEFAULT_CLUSTER.to_string());
+        let program_ids = match dotenv::var("PROGRAM_IDS") {
+            Ok(s) => s
+                .split(',')
+                .map(|p| p.trim())
+                .filter(|p| !p.is_empty())
+                .map(|p| p.parse::<Pubkey>())
+                .collect::<Result<Vec<_>, _>>()?,
+            Err(_) => vec![OPENBOOK_KEY],
+        };
+        if program_ids.is_empty() {
+            return Err(anyhow::anyhow!("PROGRAM_IDS is empty"));
+        }
+        Ok(ProgramConfig {
+            cluster,
+            program_ids,
+        })
+    }
+
+    pub fn is_program(&self, program_id: &str) -> bool {
+        self.program_ids.iter().any(|p| p.to_string() == program_id)
+    }
+}
+
+lazy_static! {
+    pub static ref PROGRAM_CONFIG: ProgramConfig =
+        ProgramConfig::from_env().expect("reading PROGRAM_IDS and CLUSTER from env");
+}
 
 pub trait AnyhowWrap {
     type Value;
@@ -46,10 +93,38 @@ pub struct Config {
 
 pub struct WebContext {
     pub rpc_url: String,
-    pub markets: Vec<OpenBookMarketMetadata>,
+    pub markets: MarketRegistry,
     pub pool: Pool,
 }
 
+impl WebContext {
+    /// The active markets matching the filter, ordered by name.
+    pub fn filtered_markets(&self, filter: &MarketFilter) -> Vec<OpenBookMarketMetadata> {
+        self.markets
+            .list()
+            .into_iter()
+            .filter(|m| filter.matches(m))
+            .collect()
+    }
+}
+
+/// The optional `cluster` and `program_pk` query parameters accepted by every endpoint.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct MarketFilter {
+    pub cluster: Option<String>,
+    pub program_pk: Option<String>,
+}
+
+impl MarketFilter {
+    pub fn matches(&self, market: &OpenBookMarketMetadata) -> bool {
+        self.cluster.as_ref().is_none_or(|c| *c == market.cluster)
+            && self
+                .program_pk
+                .as_ref()
+                .is_none_or(|p| *p == market.program_pk)
+    }
+}
+
 #[allow(deprecated)]
 pub fn to_timestampz(seconds: u64) -> chrono::DateTime<Utc> {
     chrono::DateTime::<Utc>::from_utc(NaiveDateTime::from_timestamp(seconds as i64, 0), Utc)
@@ -7,13 +7,13 @@ use strum::IntoEnumIterator;
 use crate::{
     database::{
         fetch::{fetch_candles_from, fetch_earliest_candles, fetch_latest_finished_candle},
-        insert::build_candles_upsert_statement,
+        insert::upsert_candles,
     },
     structs::{
         candle::Candle,
         resolution::{day, Resolution},
     },
-    utils::{f64_max, f64_min, AnyhowWrap},
+    utils::{f64_max, f64_min},
 };
 
 pub async fn batch_higher_order_candles(
@@ -27,7 +27,7 @@ pub async fn batch_higher_order_candles(
         Some(candle) => {
             let start_time = candle.end_time;
             let end_time = start_time + day();
-            let mut constituent_candles = fetch_candles_from(
+            let constituent_candles = fetch_candles_from(
                 pool,
                 market_name,
                 resolution.get_constituent_resolution(),
@@ -39,11 +39,11 @@ pub async fn batch_higher_order_candles(
                 return Ok(Vec::new());
             }
             let combined_candles =
-                combine_into_higher_order_candles(&mut constituent_candles, resolution, start_time);
+                combine_into_higher_order_candles(&constituent_candles, resolution, start_time);
             Ok(combined_candles)
         }
         None => {
-            let mut constituent_candles =
+            let constituent_candles =
                 fetch_earliest_candles(pool, market_name, resolution.get_constituent_resolution())
                     .await?;
             if constituent_candles.is_empty() {
@@ -62,7 +62,7 @@ pub async fn batch_higher_order_candles(
             }
 
             let combined_candles =
-                combine_into_higher_order_candles(&mut constituent_candles, resolution, start_time);
+                combine_into_higher_order_candles(&constituent_candles, resolution, start_time);
 
             Ok(trim_candles(
                 combined_candles,
@@ -73,7 +73,7 @@ pub async fn batch_higher_order_candles(
 }
 
 fn combine_into_higher_order_candles(
-    constituent_candles: &Vec<Candle>,
+    constituent_candles: &[Candle],
     target_resolution: Resolution,
     st: DateTime<Utc>,
 ) -> Vec<Candle> {
@@ -83,6 +83,8 @@ fn combine_into_higher_order_candles(
 
     let empty_candle = Candle::create_empty_candle(
         constituent_candles[0].market_name.clone(),
+        constituent_candles[0].program_pk.clone(),
+        constituent_candles[0].cluster.clone(),
         target_resolution,
     );
     let now = Utc::now().duration_trunc(Duration::minutes(1)).unwrap();
@@ -99,29 +101,29 @@ fn combine_into_higher_order_candles(
     let mut start_time = st;
     let mut end_time = start_time + duration;
 
-    for i in 0..combined_candles.len() {
-        combined_candles[i].open = last_close;
-        combined_candles[i].low = last_close;
-        combined_candles[i].close = last_close;
-        combined_candles[i].high = last_close;
+    for combined_candle in combined_candles.iter_mut() {
+        combined_candle.open = last_close;
+        combined_candle.low = last_close;
+        combined_candle.close = last_close;
+        combined_candle.high = last_close;
 
         while matches!(con_iter.peek(), Some(c) if c.end_time <= end_time) {
             let unit_candle = con_iter.next().unwrap();
-            combined_candles[i].high = f64_max(combined_candles[i].high, unit_candle.high);
-            combined_candles[i].low = f64_min(combined_candles[i].low, unit_candle.low);
-            combined_candles[i].close = unit_candle.close;
-            combined_candles[i].volume += unit_candle.volume;
-            combined_candles[i].complete = unit_candle.complete;
-            combined_candles[i].end_time = unit_candle.end_time;
+            combined_candle.high = f64_max(combined_candle.high, unit_candle.high);
+            combined_candle.low = f64_min(combined_candle.low, unit_candle.low);
+            combined_candle.close = unit_candle.close;
+            combined_candle.volume += unit_candle.volume;
+            combined_candle.complete = unit_candle.complete;
+            combined_candle.end_time = unit_candle.end_time;
         }
 
-        combined_candles[i].start_time = start_time;
-        combined_candles[i].end_time = end_time;
+        combined_candle.start_time = start_time;
+        combined_candle.end_time = end_time;
 
         start_time = end_time;
         end_time += duration;
 
-        last_close = combined_candles[i].close;
+        last_close = combined_candle.close;
     }
 
     combined_candles
@@ -147,7 +149,7 @@ pub async fn backfill_batch_higher_order_candles(
     let mut start_time = earliest_candles[0].start_time.duration_trunc(day())?;
     while start_time < Utc::now() {
         let mut candles = vec![];
-        let mut constituent_candles = fetch_candles_from(
+        let constituent_candles = fetch_candles_from(
             pool,
             market_name,
             Resolution::R1m,
@@ -161,16 +163,11 @@ pub async fn backfill_batch_higher_order_candles(
                 continue;
             }
             let mut combined_candles =
-                combine_into_higher_order_candles(&mut constituent_candles, resolution, start_time);
+                combine_into_higher_order_candles(&constituent_candles, resolution, start_time);
             candles.append(&mut combined_candles);
         }
 
-        let upsert_statement = build_candles_upsert_statement(&candles);
-        let client = pool.get().await.unwrap();
-        client
-            .execute(&upsert_statement, &[])
-            .await
-            .map_err_anyhow()?;
+        upsert_candles(pool, &candles).await?;
         // println!("{:?} {:?} done", market_name, start_time);
         start_time += day();
     }
@@ -1,4 +1,4 @@
-use std::{cmp::min, collections::HashMap};
+use std::cmp::min;
 
 use chrono::{DateTime, Duration, DurationRound, Utc};
 use deadpool_postgres::Pool;
@@ -6,15 +6,12 @@ use log::debug;
 
 use crate::structs::openbook_v2::{OpenBookFill, OpenBookMarketMetadata};
 use crate::{
-    database::{
-        fetch::{fetch_earliest_fill, fetch_fills_from, fetch_latest_finished_candle},
-        insert::build_candles_upsert_statement,
-    },
+    database::fetch::{fetch_earliest_fill, fetch_fills_from, fetch_latest_finished_candle},
     structs::{
         candle::Candle,
         resolution::{day, Resolution},
     },
-    utils::{f64_max, f64_min, AnyhowWrap},
+    utils::{f64_max, f64_min},
 };
 
 pub async fn batch_1m_candles(
@@ -32,10 +29,10 @@ pub async fn batch_1m_candles(
                 start_time + day(),
                 (Utc::now() + Duration::minutes(1)).duration_trunc(Duration::minutes(1))?,
             );
-            let mut fills = fetch_fills_from(pool, market_pk, start_time, end_time).await?;
+            let fills = fetch_fills_from(pool, market_pk, start_time, end_time).await?;
 
             let candles = combine_fills_into_1m_candles(
-                &mut fills,
+                &fills,
                 market,
                 start_time,
                 end_time,
@@ -59,10 +56,10 @@ pub async fn batch_1m_candles(
                 start_time + day(),
                 Utc::now().duration_trunc(Duration::minutes(1))?,
             );
-            let mut fills = fetch_fills_from(pool, market_pk, start_time, end_time).await?;
+            let fills = fetch_fills_from(pool, market_pk, start_time, end_time).await?;
             if !fills.is_empty() {
                 let candles =
-                    combine_fills_into_1m_candles(&mut fills, market, start_time, end_time, None);
+                    combine_fills_into_1m_candles(&fills, market, start_time, end_time, None);
                 Ok(candles)
             } else {
                 Ok(Vec::new())
@@ -72,18 +69,23 @@ pub async fn batch_1m_candles(
 }
 
 fn combine_fills_into_1m_candles(
-    fills: &mut Vec<OpenBookFill>,
+    fills: &[OpenBookFill],
     market: &OpenBookMarketMetadata,
     st: DateTime<Utc>,
     et: DateTime<Utc>,
     maybe_last_price: Option<f64>,
 ) -> Vec<Candle> {
-    let empty_candle = Candle::create_empty_candle(market.market_name.clone(), Resolution::R1m);
+    let empty_candle = Candle::create_empty_candle(
+        market.market_name.clone(),
+        market.program_pk.clone(),
+        market.cluster.clone(),
+        Resolution::R1m,
+    );
 
     let minutes = (et - st).num_minutes();
     let mut candles = vec![empty_candle; minutes as usize];
 
-    let mut fills_iter = fills.iter_mut().peekable();
+    let mut fills_iter = fills.iter().peekable();
     let mut start_time = st;
     let mut end_time = start_time + Duration::minutes(1);
 
@@ -95,26 +97,26 @@ fn combine_fills_into_1m_candles(
         }
     };
 
-    for i in 0..candles.len() {
-        candles[i].open = last_price;
-        candles[i].close = last_price;
-        candles[i].low = last_price;
-        candles[i].high = last_price;
+    for candle in candles.iter_mut() {
+        candle.open = last_price;
+        candle.close = last_price;
+        candle.low = last_price;
+        candle.high = last_price;
 
         while matches!(fills_iter.peek(), Some(f) if f.block_datetime < end_time) {
             let fill = fills_iter.next().unwrap();
 
-            candles[i].close = fill.price;
-            candles[i].low = f64_min(fill.price, candles[i].low);
-            candles[i].high = f64_max(fill.price, candles[i].high);
-            candles[i].volume += fill.quantity;
+            candle.close = fill.price;
+            candle.low = f64_min(fill.price, candle.low);
+            candle.high = f64_max(fill.price, candle.high);
+            candle.volume += fill.quantity;
 
             last_price = fill.price;
         }
 
-        candles[i].start_time = start_time;
-        candles[i].end_time = end_time;
-        candles[i].complete = matches!(fills_iter.peek(), Some(f) if f.block_datetime > end_time)
+        candle.start_time = start_time;
+        candle.end_time = end_time;
+        candle.complete = matches!(fills_iter.peek(), Some(f) if f.block_datetime > end_time)
             || end_time < Utc::now() - Duration::minutes(10);
         start_time = end_time;
         end_time += Duration::minutes(1);
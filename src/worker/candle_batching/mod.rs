@@ -3,14 +3,15 @@ pub mod minute_candles;
 
 use chrono::Duration;
 use deadpool_postgres::Pool;
-use log::{error, warn};
+use log::{error, info, warn};
+use std::collections::HashMap;
 use strum::IntoEnumIterator;
-use tokio::time::sleep;
+use tokio::{task::JoinHandle, time::sleep};
 
 use crate::{
-    database::insert::build_candles_upsert_statement,
+    database::insert::upsert_candles,
     structs::{candle::Candle, openbook_v2::OpenBookMarketMetadata, resolution::Resolution},
-    utils::AnyhowWrap,
+    utils::market_registry::MarketRegistry,
     worker::candle_batching::minute_candles::batch_1m_candles,
 };
 
@@ -18,6 +19,40 @@ use self::higher_order_candles::batch_higher_order_candles;
 
 use super::metrics::METRIC_CANDLES_TOTAL;
 
+/// Runs a batching task per active market. Tasks are started and stopped as markets are
+/// activated, deactivated or renamed, without restarting the worker.
+pub async fn batch_active_markets(pool: &Pool, markets: &MarketRegistry) -> anyhow::Result<()> {
+    let mut receiver = markets.subscribe();
+    let mut tasks: HashMap<String, (OpenBookMarketMetadata, JoinHandle<()>)> = HashMap::new();
+    loop {
+        let active_markets = receiver.borrow_and_update().clone();
+
+        tasks.retain(|market_pk, (market, handle)| {
+            if active_markets.get(market_pk) == Some(market) {
+                return true;
+            }
+            info!("Stopping batching for {}", market.market_name);
+            handle.abort();
+            false
+        });
+        for (market_pk, market) in active_markets.iter() {
+            if tasks.contains_key(market_pk) {
+                continue;
+            }
+            info!("Starting batching for {}", market.market_name);
+            let batch_pool = pool.clone();
+            let batch_market = market.clone();
+            let handle = tokio::spawn(async move {
+                batch_for_market(&batch_pool, &batch_market).await.unwrap();
+                error!("batching halted for market {}", &batch_market.market_name);
+            });
+            tasks.insert(market_pk.clone(), (market.clone(), handle));
+        }
+
+        receiver.changed().await?;
+    }
+}
+
 pub async fn batch_for_market(pool: &Pool, market: &OpenBookMarketMetadata) -> anyhow::Result<()> {
     loop {
         let market_clone = market.clone();
@@ -60,14 +95,6 @@ async fn batch_inner(pool: &Pool, market: &OpenBookMarketMetadata) -> anyhow::Re
 }
 
 async fn save_candles(pool: &Pool, candles: Vec<Candle>) -> anyhow::Result<()> {
-    if candles.is_empty() {
-        return Ok(());
-    }
-    let upsert_statement = build_candles_upsert_statement(&candles);
-    let client = pool.get().await.unwrap();
-    client
-        .execute(&upsert_statement, &[])
-        .await
-        .map_err_anyhow()?;
+    upsert_candles(pool, &candles).await?;
     Ok(())
 }
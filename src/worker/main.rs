@@ -1,63 +1,97 @@
-use log::{error, info};
-use openbook_offchain_services::database::fetch::fetch_active_markets;
-use openbook_offchain_services::scraper::scrape::{scrape_signatures, scrape_transactions};
-use openbook_offchain_services::structs::openbook_v2::OpenBookMarketMetadata;
-use openbook_offchain_services::structs::transaction::NUM_TRANSACTION_PARTITIONS;
+use log::info;
+use openbook_offchain_services::scraper::import::import_unknown_markets;
+use openbook_offchain_services::scraper::scrape::{
+    scrape_signatures, scrape_transactions, signature_addresses, ClaimConfig,
+};
+use openbook_offchain_services::scraper::source::transaction_source_from_env;
+use openbook_offchain_services::utils::{market_registry::MarketRegistry, PROGRAM_CONFIG};
 use openbook_offchain_services::worker::metrics::{
     serve_metrics, METRIC_DB_POOL_AVAILABLE, METRIC_DB_POOL_SIZE,
 };
 use openbook_offchain_services::{
     database::initialize::{connect_to_database, setup_database},
-    worker::candle_batching::batch_for_market,
+    worker::candle_batching::batch_active_markets,
 };
-use std::{collections::HashMap, time::Duration as WaitDuration};
+use std::time::Duration as WaitDuration;
 
 #[tokio::main(flavor = "multi_thread", worker_threads = 10)]
 async fn main() -> anyhow::Result<()> {
     env_logger::init();
     dotenv::dotenv().ok();
 
-    let rpc_url: String = dotenv::var("RPC_URL").unwrap();
     let pool = connect_to_database().await?;
     setup_database(&pool).await?;
 
-    let markets = fetch_active_markets(&pool).await?;
-    let target_markets: HashMap<String, OpenBookMarketMetadata> = markets
-        .into_iter()
-        .map(|m| (m.market_pk.clone(), m))
-        .collect();
-    info!("{:?}", target_markets);
+    let markets = MarketRegistry::load(&pool, Some(PROGRAM_CONFIG.cluster.clone())).await?;
+    info!("{:?}", markets.list());
+    // fills are ingested for every market when SCRAPE_ALL_MARKETS is set, candles are only
+    // batched for the active ones
+    let ingestion_markets =
+        MarketRegistry::load_for_ingestion(&pool, Some(PROGRAM_CONFIG.cluster.clone())).await?;
     let mut handles = vec![];
 
-    // signature scraping
-    let rpc_clone = rpc_url.clone();
-    let pool_clone = pool.clone();
-    handles.push(tokio::spawn(async move {
-        scrape_signatures(rpc_clone, &pool_clone).await.unwrap();
-    }));
+    // market refresh
+    for registry in [markets.clone(), ingestion_markets.clone()] {
+        let pool_clone = pool.clone();
+        handles.push(tokio::spawn(async move {
+            registry.refresh_periodically(&pool_clone).await.unwrap();
+        }));
+    }
+
+    let source = transaction_source_from_env()?;
 
-    // transaction/fill scraping
-    for id in 0..NUM_TRANSACTION_PARTITIONS {
-        let rpc_clone = rpc_url.clone();
+    // signature scraping, one task per program or per scraped market address
+    for (address, program_id) in signature_addresses()? {
+        let source_clone = source.clone();
         let pool_clone = pool.clone();
-        let markets_clone = target_markets.clone();
         handles.push(tokio::spawn(async move {
-            scrape_transactions(id as i32, rpc_clone, &pool_clone, &markets_clone)
+            scrape_signatures(source_clone.as_ref(), &pool_clone, address, program_id)
                 .await
                 .unwrap();
         }));
     }
 
-    // candle batching
-    let cloned_markets = target_markets.clone();
-    for (_, market) in cloned_markets.into_iter() {
-        let batch_pool = pool.clone();
+    // transaction/fill scraping, sharing the backlog with any other scraper processes
+    let claim_config = ClaimConfig::from_env()?;
+    for task in 0..claim_config.tasks {
+        let claim_config = claim_config.clone();
+        let source_clone = source.clone();
+        let pool_clone = pool.clone();
+        let markets_clone = ingestion_markets.clone();
         handles.push(tokio::spawn(async move {
-            batch_for_market(&batch_pool, &market).await.unwrap();
-            error!("batching halted for market {}", &market.market_name);
+            scrape_transactions(
+                task,
+                &claim_config,
+                source_clone.as_ref(),
+                &pool_clone,
+                &markets_clone,
+            )
+            .await
+            .unwrap();
         }));
     }
 
+    // import of markets first seen in fills, when every market is scraped
+    if ingestion_markets.all_markets() {
+        let source_clone = source.clone();
+        let pool_clone = pool.clone();
+        let markets_clone = ingestion_markets.clone();
+        handles.push(tokio::spawn(async move {
+            import_unknown_markets(source_clone.as_ref(), &pool_clone, &markets_clone)
+                .await
+                .unwrap();
+        }));
+    }
+
+    // candle batching
+    let batch_markets = markets.clone();
+    let batch_pool = pool.clone();
+    handles.push(tokio::spawn(async move {
+        batch_active_markets(&batch_pool, &batch_markets)
+            .await
+            .unwrap();
+    }));
+
     let monitor_pool = pool.clone();
     handles.push(tokio::spawn(async move {
         loop {
@@ -2,8 +2,10 @@ use actix_web::{dev::Server, http::StatusCode, App, HttpServer};
 use actix_web_prom::PrometheusMetricsBuilder;
 use lazy_static::lazy_static;
 use prometheus::{
-    register_int_counter_vec_with_registry, register_int_counter_with_registry,
-    register_int_gauge_with_registry, IntCounter, IntCounterVec, IntGauge, Registry,
+    register_gauge_vec_with_registry, register_int_counter_vec_with_registry,
+    register_int_counter_with_registry, register_int_gauge_vec_with_registry,
+    register_int_gauge_with_registry, GaugeVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec,
+    Registry,
 };
 
 lazy_static! {
@@ -36,6 +38,14 @@ lazy_static! {
         METRIC_REGISTRY
     )
     .unwrap();
+    pub static ref METRIC_UNSTORABLE_SIGNATURES_TOTAL: IntCounterVec =
+        register_int_counter_vec_with_registry!(
+            "unstorable_signatures_total",
+            "Listed signatures that could not be stored, failing their page",
+            &["address"],
+            METRIC_REGISTRY
+        )
+        .unwrap();
     pub static ref METRIC_RPC_ERRORS_TOTAL: IntCounterVec =
         register_int_counter_vec_with_registry!(
             "rpc_errors_total",
@@ -44,6 +54,104 @@ lazy_static! {
             METRIC_REGISTRY
         )
         .unwrap();
+    pub static ref METRIC_UNKNOWN_EVENTS_TOTAL: IntCounter = register_int_counter_with_registry!(
+        "unknown_events_total",
+        "Program data payloads with an unknown event discriminator",
+        METRIC_REGISTRY
+    )
+    .unwrap();
+    pub static ref METRIC_EVENT_DECODE_ERRORS_TOTAL: IntCounterVec =
+        register_int_counter_vec_with_registry!(
+            "event_decode_errors_total",
+            "Program data payloads that failed to decode as their event type",
+            &["event"],
+            METRIC_REGISTRY
+        )
+        .unwrap();
+    pub static ref METRIC_WS_FILLS_TOTAL: IntCounterVec = register_int_counter_vec_with_registry!(
+        "ws_fills_total",
+        "Total number of fills streamed from the logs subscription",
+        &["market"],
+        METRIC_REGISTRY
+    )
+    .unwrap();
+    pub static ref METRIC_TRUNCATED_LOGS_TOTAL: IntCounter = register_int_counter_with_registry!(
+        "truncated_logs_total",
+        "Total number of transactions with truncated program logs",
+        METRIC_REGISTRY
+    )
+    .unwrap();
+    pub static ref METRIC_FILL_COMMITMENT_TOTAL: IntCounterVec =
+        register_int_counter_vec_with_registry!(
+            "fill_commitment_total",
+            "Total number of confirmed fills reconciled against the finalized chain",
+            &["commitment"],
+            METRIC_REGISTRY
+        )
+        .unwrap();
+    pub static ref METRIC_RPC_ENDPOINT_ERRORS_TOTAL: IntCounterVec =
+        register_int_counter_vec_with_registry!(
+            "rpc_endpoint_errors_total",
+            "RPC errors that caused a request to fail over to another endpoint",
+            &["endpoint", "method"],
+            METRIC_REGISTRY
+        )
+        .unwrap();
+    pub static ref METRIC_RPC_ENDPOINT_HEALTH: GaugeVec = register_gauge_vec_with_registry!(
+        "rpc_endpoint_health",
+        "Health score of each RPC endpoint, from 0 to 1",
+        &["endpoint"],
+        METRIC_REGISTRY
+    )
+    .unwrap();
+    pub static ref METRIC_DEAD_LETTER_TRANSACTIONS_TOTAL: IntCounter =
+        register_int_counter_with_registry!(
+            "dead_letter_transactions_total",
+            "Total number of transactions dead lettered after too many failed attempts",
+            METRIC_REGISTRY
+        )
+        .unwrap();
+    pub static ref METRIC_FILL_COMPLETENESS: GaugeVec = register_gauge_vec_with_registry!(
+        "fill_completeness",
+        "Share of the seq_num range of a market's recent fills that is stored",
+        &["market"],
+        METRIC_REGISTRY
+    )
+    .unwrap();
+    pub static ref METRIC_MISSING_FILLS: IntGaugeVec = register_int_gauge_vec_with_registry!(
+        "missing_fills",
+        "Number of seq_nums missing from a market's recent fills",
+        &["market"],
+        METRIC_REGISTRY
+    )
+    .unwrap();
+    pub static ref METRIC_MARKET_IMPORTS_TOTAL: IntCounterVec =
+        register_int_counter_vec_with_registry!(
+            "market_imports_total",
+            "Total number of imports of markets first seen in fills, by result",
+            &["result"],
+            METRIC_REGISTRY
+        )
+        .unwrap();
+    pub static ref METRIC_WS_RECONNECTS_TOTAL: IntCounter = register_int_counter_with_registry!(
+        "ws_reconnects_total",
+        "Total number of logs subscription reconnects",
+        METRIC_REGISTRY
+    )
+    .unwrap();
+    pub static ref METRIC_BACKFILL_SLOT: IntGaugeVec = register_int_gauge_vec_with_registry!(
+        "backfill_slot",
+        "Oldest slot reached by the signature backfill",
+        &["address"],
+        METRIC_REGISTRY
+    )
+    .unwrap();
+    pub static ref METRIC_BLOCK_SCAN_SLOT: IntGauge = register_int_gauge_with_registry!(
+        "block_scan_slot",
+        "Last slot read by the block scanner",
+        METRIC_REGISTRY
+    )
+    .unwrap();
     pub static ref METRIC_DB_POOL_SIZE: IntGauge = register_int_gauge_with_registry!(
         "db_pool_size",
         "Current size of the DB connection pool",